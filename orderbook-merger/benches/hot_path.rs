@@ -0,0 +1,270 @@
+use std::{collections::BTreeMap, path::Path};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use prost::Message as _;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer};
+use tokio_tungstenite::tungstenite::Message;
+use orderbook_merger::{
+    display_to_storage,
+    exchanges::{binance, bitstamp, exchange::parse_message},
+    make_summary,
+    orderbook::orderbook::{OrderBook, OrderBookOnlyLevels, PublishedBook, StorageLevel, Update},
+    recorder::{RecordKind, RecordReader, Scales},
+    ExchangeName, Symbol,
+};
+
+// The fixture is a recording in the format written by the `[recorder]` table: the scales, a REST
+// snapshot of 1000 levels per side and 1000 websocket diffs of each exchange. Any other recording
+// with a snapshot can be benchmarked by pointing `BENCH_RECORDING` at it.
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/fixtures/ethusdt.rec.gz");
+
+const DEPTHS: [usize; 3] = [10, 100, 500];
+const VENUES: [usize; 3] = [2, 5, 10];
+
+// The `Recorded` struct holds what a connector of one exchange received.
+#[derive(Default)]
+struct Recorded {
+    scales: Option<Scales>,
+    snapshot: Vec<u8>,
+    updates: Vec<Message>,
+}
+
+impl Recorded {
+    fn scales(&self) -> Scales {
+        self.scales.expect("recording has no scales")
+    }
+
+    fn bytes(&self) -> u64 {
+        self.updates.iter().map(|message| message.len() as u64).sum()
+    }
+}
+
+struct Fixture {
+    binance: Recorded,
+    bitstamp: Recorded,
+}
+
+impl Fixture {
+    fn load() -> Self {
+        let path = std::env::var("BENCH_RECORDING").unwrap_or_else(|_| FIXTURE.to_string());
+        let mut fixture = Fixture {
+            binance: Recorded::default(),
+            bitstamp: Recorded::default(),
+        };
+        for record in RecordReader::open(Path::new(&path)).unwrap() {
+            let record = record.unwrap();
+            let recorded = match record.exchange {
+                ExchangeName::BINANCE => &mut fixture.binance,
+                ExchangeName::BITSTAMP => &mut fixture.bitstamp,
+            };
+            match record.kind {
+                RecordKind::Scales => recorded.scales = Some(serde_json::from_slice(&record.payload).unwrap()),
+                // Only the diffs after the first snapshot apply to it.
+                RecordKind::Snapshot if recorded.snapshot.is_empty() => recorded.snapshot = record.payload,
+                RecordKind::Text | RecordKind::Binary if !recorded.snapshot.is_empty() => {
+                    recorded.updates.extend(record.to_message())
+                }
+                _ => {}
+            }
+        }
+        fixture
+    }
+}
+
+// The `synced_book` function builds the book of `exchange` from the recorded snapshot, and returns
+// it with the recorded diffs that parse as updates.
+fn synced_book<S, U>(exchange: ExchangeName, recorded: &Recorded, depth: usize) -> (OrderBook, Vec<U>)
+where
+    S: Update,
+    U: Update + From<S>,
+{
+    let scales = recorded.scales();
+    let mut book = OrderBook::new_orderbook(exchange, Symbol::ETHUSDT, scales.price_scale, scales.quantity_scale, depth);
+    let snapshot = S::parse(&recorded.snapshot, scales).unwrap();
+    book.update(&U::from(snapshot)).unwrap();
+    let updates = recorded
+        .updates
+        .iter()
+        .filter_map(|message| parse_message(message, scales).ok())
+        .collect();
+    (book, updates)
+}
+
+fn binance_book(fixture: &Fixture, depth: usize) -> (OrderBook, Vec<binance::BookUpdate>) {
+    synced_book::<binance::Snapshot, binance::BookUpdate>(ExchangeName::BINANCE, &fixture.binance, depth)
+}
+
+fn bitstamp_book(fixture: &Fixture, depth: usize) -> (OrderBook, Vec<bitstamp::BookUpdate>) {
+    synced_book::<bitstamp::Snapshot, bitstamp::BookUpdate>(ExchangeName::BITSTAMP, &fixture.bitstamp, depth)
+}
+
+// The `venue_levels` function returns the book levels of `venues` exchanges, alternating between
+// the recorded Binance and Bitstamp books, as the aggregator would merge them.
+fn venue_levels(fixture: &Fixture, venues: usize, depth: usize) -> Vec<OrderBookOnlyLevels> {
+    let books = [binance_book(fixture, depth).0, bitstamp_book(fixture, depth).0];
+    let levels = books.map(|book| book.get_book_levels().unwrap());
+    levels.iter().cycle().take(venues).cloned().collect()
+}
+
+// The `DecimalLevels` struct parses levels the way updates were parsed before they went straight
+// to storage amounts: each frame was copied, its levels collected into maps of `Decimal`s and
+// converted to storage amounts one by one. It is kept as the reference the gains of parsing are
+// reported against, as `parse_book_update/*_decimal_maps`.
+#[derive(Deserialize)]
+struct DecimalLevels {
+    #[serde(alias = "b", deserialize_with = "decimal_map")]
+    bids: BTreeMap<Decimal, Decimal>,
+    #[serde(alias = "a", deserialize_with = "decimal_map")]
+    asks: BTreeMap<Decimal, Decimal>,
+}
+
+#[derive(Deserialize)]
+struct DecimalData {
+    data: DecimalLevels,
+}
+
+fn decimal_map<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<Decimal, Decimal>, D::Error> {
+    let levels: Vec<[&str; 2]> = Deserialize::deserialize(deserializer)?;
+    let decimal = |amount: &str| amount.parse::<Decimal>().map_err(serde::de::Error::custom);
+    levels
+        .into_iter()
+        .map(|[price, quantity]| Ok((decimal(price)?, decimal(quantity)?)))
+        .collect()
+}
+
+fn decimal_storage_levels(levels: &DecimalLevels, scales: Scales) -> [Vec<StorageLevel>; 2] {
+    [&levels.bids, &levels.asks].map(|side| {
+        side.iter()
+            .filter_map(|(price, quantity)| {
+                let price = display_to_storage(*price, scales.price_scale).ok()?;
+                Some((price, display_to_storage(*quantity, scales.quantity_scale).ok()?))
+            })
+            .collect()
+    })
+}
+
+fn parse(c: &mut Criterion, fixture: &Fixture) {
+    let mut group = c.benchmark_group("parse_book_update");
+    let (binance_scales, bitstamp_scales) = (fixture.binance.scales(), fixture.bitstamp.scales());
+    group.throughput(Throughput::Bytes(fixture.binance.bytes()));
+    group.bench_function("binance", |b| {
+        b.iter(|| {
+            for message in &fixture.binance.updates {
+                let _ = std::hint::black_box(parse_message::<binance::BookUpdate>(message, binance_scales));
+            }
+        })
+    });
+    group.bench_function("binance_decimal_maps", |b| {
+        b.iter(|| {
+            for message in &fixture.binance.updates {
+                if let Ok(levels) = serde_json::from_slice::<DecimalLevels>(&message.clone().into_data()) {
+                    std::hint::black_box(decimal_storage_levels(&levels, binance_scales));
+                }
+            }
+        })
+    });
+    group.throughput(Throughput::Bytes(fixture.bitstamp.bytes()));
+    group.bench_function("bitstamp", |b| {
+        b.iter(|| {
+            for message in &fixture.bitstamp.updates {
+                let _ = std::hint::black_box(parse_message::<bitstamp::BookUpdate>(message, bitstamp_scales));
+            }
+        })
+    });
+    group.bench_function("bitstamp_decimal_maps", |b| {
+        b.iter(|| {
+            for message in &fixture.bitstamp.updates {
+                if let Ok(levels) = serde_json::from_slice::<DecimalData>(&message.clone().into_data()) {
+                    std::hint::black_box(decimal_storage_levels(&levels.data, bitstamp_scales));
+                }
+            }
+        })
+    });
+    group.finish();
+}
+
+fn apply_updates<U: Update>((mut book, updates): (OrderBook, Vec<U>)) -> OrderBook {
+    for update in &updates {
+        book.update(update).unwrap();
+    }
+    book
+}
+
+// Each iteration applies every recorded diff, as received, to a book synced from the snapshot.
+fn update(c: &mut Criterion, fixture: &Fixture) {
+    let mut group = c.benchmark_group("orderbook_update");
+    group.throughput(Throughput::Elements(fixture.binance.updates.len() as u64));
+    group.bench_function("binance", |b| {
+        b.iter_batched(|| binance_book(fixture, 10), apply_updates, BatchSize::LargeInput)
+    });
+    group.throughput(Throughput::Elements(fixture.bitstamp.updates.len() as u64));
+    group.bench_function("bitstamp", |b| {
+        b.iter_batched(|| bitstamp_book(fixture, 10), apply_updates, BatchSize::LargeInput)
+    });
+    group.finish();
+}
+
+// Connectors publish a copy of their full book after every update.
+fn publish(c: &mut Criterion, fixture: &Fixture) {
+    let mut group = c.benchmark_group("publish_book");
+    let published = PublishedBook::default();
+    for (exchange, book) in [
+        ("binance", apply_updates(binance_book(fixture, 10))),
+        ("bitstamp", apply_updates(bitstamp_book(fixture, 10))),
+    ] {
+        group.throughput(Throughput::Elements((book.bids.len() + book.asks.len()) as u64));
+        group.bench_with_input(exchange, &book, |b, book| b.iter(|| published.publish(book)));
+    }
+    group.finish();
+}
+
+fn book_levels(c: &mut Criterion, fixture: &Fixture) {
+    let mut group = c.benchmark_group("get_book_levels");
+    for depth in DEPTHS {
+        let book = apply_updates(binance_book(fixture, depth));
+        group.throughput(Throughput::Elements(depth as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(depth), &book, |b, book| {
+            b.iter(|| book.get_book_levels())
+        });
+    }
+    group.finish();
+}
+
+fn summary(c: &mut Criterion, fixture: &Fixture) {
+    let mut group = c.benchmark_group("make_summary");
+    for venues in VENUES {
+        for depth in DEPTHS {
+            let levels = venue_levels(fixture, venues, depth);
+            group.throughput(Throughput::Elements((venues * depth) as u64));
+            group.bench_with_input(BenchmarkId::new(format!("{venues}_venues"), depth), &levels, |b, levels| {
+                b.iter_batched(|| levels.clone(), make_summary, BatchSize::SmallInput)
+            });
+        }
+    }
+    group.finish();
+}
+
+fn encode(c: &mut Criterion, fixture: &Fixture) {
+    let mut group = c.benchmark_group("encode_summary");
+    for depth in DEPTHS {
+        let summary = make_summary(venue_levels(fixture, 2, depth));
+        group.throughput(Throughput::Bytes(summary.encoded_len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(depth), &summary, |b, summary| {
+            b.iter(|| summary.encode_to_vec())
+        });
+    }
+    group.finish();
+}
+
+fn benches(c: &mut Criterion) {
+    let fixture = Fixture::load();
+    parse(c, &fixture);
+    update(c, &fixture);
+    publish(c, &fixture);
+    book_levels(c, &fixture);
+    summary(c, &fixture);
+    encode(c, &fixture);
+}
+
+criterion_group!(hot_path, benches);
+criterion_main!(hot_path);
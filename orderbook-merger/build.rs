@@ -1,7 +1,13 @@
+use std::{env, path::PathBuf};
+
 fn main(){
-    tonic_build::compile_protos("proto/orderbook_summary.proto")
+    // The file descriptor set is embedded in the server for gRPC reflection.
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("orderbook_summary_descriptor.bin"))
+        // Summaries are also served as JSON with the field names of the proto file.
+        .type_attribute("orderbook_summary.Summary", "#[derive(serde::Serialize)]")
+        .type_attribute("orderbook_summary.Level", "#[derive(serde::Serialize)]")
+        .compile(&["proto/orderbook_summary.proto"], &["proto"])
         .unwrap_or_else(|e| panic!("Failed to compile protos {:?}", e));
-    // tonic_build::configure()
-    //     .out_dir("proto")
-    //     .compile(&["proto/orderbooksummary.proto"], &["proto"]);
-}
\ No newline at end of file
+}
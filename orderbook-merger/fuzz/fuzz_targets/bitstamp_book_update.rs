@@ -0,0 +1,24 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use orderbook_merger::{
+    exchanges::{bitstamp::BookUpdate, exchange::parse_message},
+    orderbook::orderbook::{OrderBook, Scales},
+    ExchangeName, Symbol,
+};
+use tokio_tungstenite::tungstenite::Message;
+
+const SCALES: Scales = Scales { price_scale: 2, quantity_scale: 8 };
+
+// Any websocket frame must parse to an update or an error. Updates that parse are applied to a
+// book at the scales of ETHUSDT, the way a connector applies them.
+fuzz_target!(|data: &[u8]| {
+    let text = Message::Text(String::from_utf8_lossy(data).into_owned());
+    for message in [text, Message::Binary(data.to_vec())] {
+        if let Ok(update) = parse_message::<BookUpdate>(&message, SCALES) {
+            let mut book = OrderBook::new_orderbook(ExchangeName::BITSTAMP, Symbol::ETHUSDT, 2, 8, 10);
+            let _ = book.update(&update);
+            let _ = book.get_book_levels();
+        }
+    }
+});
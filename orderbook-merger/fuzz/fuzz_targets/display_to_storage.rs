@@ -0,0 +1,28 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use orderbook_merger::{
+    display_to_storage, orderbook::orderbook::OrderBook, str_to_storage, ExchangeName, Symbol, ToDisplay,
+};
+use rust_decimal::Decimal;
+
+// Amounts are converted at any scale an exchange may report, and converted back when they fit.
+// Parsing the amount as an exchange would send it gives the same storage amount.
+fuzz_target!(|input: (i128, u32, u32)| {
+    let (mantissa, decimal_scale, scale) = input;
+    let Ok(amount) = Decimal::try_from_i128_with_scale(mantissa, decimal_scale % 29) else {
+        return;
+    };
+    let storage = display_to_storage(amount, scale);
+    if let Ok(storage) = storage {
+        let _ = storage.to_display(scale);
+    }
+    // Above 19 decimals `display_to_storage` fails on the scale even for amounts that fit.
+    if amount.is_sign_positive() && scale <= 19 {
+        assert_eq!(str_to_storage(&amount.to_string(), scale).ok(), storage.ok(), "{amount} at scale {scale}");
+    }
+    let mut book = OrderBook::new_orderbook(ExchangeName::BINANCE, Symbol::ETHUSDT, scale, scale, 1);
+    if book.add_bid([amount, amount]).is_ok() {
+        let _ = book.get_book_levels();
+    }
+});
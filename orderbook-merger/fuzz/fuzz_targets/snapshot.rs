@@ -0,0 +1,24 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use orderbook_merger::{
+    exchanges::{binance, bitstamp},
+    orderbook::orderbook::{OrderBook, Scales, Update},
+    ExchangeName, Symbol,
+};
+
+const SCALES: Scales = Scales { price_scale: 2, quantity_scale: 8 };
+
+// REST snapshots go through the same price and quantity parser as the websocket updates.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(snapshot) = binance::Snapshot::parse(data, SCALES) {
+        let mut book = OrderBook::new_orderbook(ExchangeName::BINANCE, Symbol::ETHUSDT, 2, 8, 10);
+        let _ = book.update(&binance::BookUpdate::from(snapshot));
+        let _ = book.get_book_levels();
+    }
+    if let Ok(snapshot) = bitstamp::Snapshot::parse(data, SCALES) {
+        let mut book = OrderBook::new_orderbook(ExchangeName::BITSTAMP, Symbol::ETHUSDT, 2, 8, 10);
+        let _ = book.update(&bitstamp::BookUpdate::from(snapshot));
+        let _ = book.get_book_levels();
+    }
+});
@@ -0,0 +1,118 @@
+use tonic::{Request, Response, Status};
+use crate::{
+    auth::ClientPermissions,
+    orderbook_summary::{
+        admin_server::Admin, ConnectorRequest, ConnectorStatus, Empty, Pipeline, PipelineList,
+    },
+    pipeline::Pipelines,
+    supervisor, ExchangeName, Symbol,
+};
+
+impl From<&supervisor::ConnectorStatus> for ConnectorStatus {
+    fn from(status: &supervisor::ConnectorStatus) -> Self {
+        Self {
+            exchange: status.exchange.to_string(),
+            symbol: status.symbol.to_string(),
+            state: status.state.to_string(),
+            restarts: status.restarts,
+            last_error: status.last_error.clone().unwrap_or_default(),
+        }
+    }
+}
+
+// The `AdminService` struct lets operators start, stop and resync exchange connectors without
+// restarting the server.
+#[derive(Debug, Clone)]
+pub struct AdminService {
+    pipelines: Pipelines,
+}
+
+impl AdminService {
+    pub fn new(pipelines: Pipelines) -> Self {
+        Self { pipelines }
+    }
+}
+
+// The `authorize` function only lets admin tokens through. Requests without permissions are
+// rejected, so the service fails closed if it is ever served without authentication.
+fn authorize<T>(request: &Request<T>) -> Result<(), Status> {
+    match request.extensions().get::<ClientPermissions>() {
+        Some(permissions) if permissions.admin => Ok(()),
+        Some(_) => Err(Status::permission_denied("token is not allowed to use the admin service")),
+        None => Err(Status::unauthenticated("the admin service requires a bearer token")),
+    }
+}
+
+fn parse_connector(request: &ConnectorRequest) -> Result<(ExchangeName, Symbol), Status> {
+    let exchange = request
+        .exchange
+        .parse()
+        .map_err(|e: anyhow::Error| Status::invalid_argument(e.to_string()))?;
+    let symbol = request
+        .symbol
+        .parse()
+        .map_err(|e: anyhow::Error| Status::invalid_argument(e.to_string()))?;
+    Ok((exchange, symbol))
+}
+
+fn not_running(exchange: ExchangeName, symbol: Symbol) -> Status {
+    Status::not_found(format!("no {exchange} connector for {symbol}"))
+}
+
+#[tonic::async_trait]
+impl Admin for AdminService {
+    async fn start_connector(
+        &self,
+        request: Request<ConnectorRequest>,
+    ) -> Result<Response<ConnectorStatus>, Status> {
+        authorize(&request)?;
+        let (exchange, symbol) = parse_connector(request.get_ref())?;
+        if !self.pipelines.start_connector(exchange, symbol) {
+            return Err(Status::already_exists(format!("{exchange} connector for {symbol} is already running")));
+        }
+        tracing::info!("started {} connector for {}", exchange, symbol);
+        let status = self
+            .pipelines
+            .pipelines()
+            .iter()
+            .flat_map(|pipeline| pipeline.connectors.iter())
+            .find(|status| status.exchange == exchange && status.symbol == symbol)
+            .map(ConnectorStatus::from)
+            .ok_or_else(|| not_running(exchange, symbol))?;
+        Ok(Response::new(status))
+    }
+
+    async fn stop_connector(&self, request: Request<ConnectorRequest>) -> Result<Response<Empty>, Status> {
+        authorize(&request)?;
+        let (exchange, symbol) = parse_connector(request.get_ref())?;
+        if !self.pipelines.stop_connector(exchange, symbol).await {
+            return Err(not_running(exchange, symbol));
+        }
+        tracing::info!("stopped {} connector for {}", exchange, symbol);
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn list_pipelines(&self, request: Request<Empty>) -> Result<Response<PipelineList>, Status> {
+        authorize(&request)?;
+        let pipelines = self
+            .pipelines
+            .pipelines()
+            .iter()
+            .map(|pipeline| Pipeline {
+                symbol: pipeline.symbol.to_string(),
+                connectors: pipeline.connectors.iter().map(ConnectorStatus::from).collect(),
+            })
+            .collect();
+        Ok(Response::new(PipelineList { pipelines }))
+    }
+
+    async fn resync_connector(&self, request: Request<ConnectorRequest>) -> Result<Response<Empty>, Status> {
+        authorize(&request)?;
+        let (exchange, symbol) = parse_connector(request.get_ref())?;
+        if !self.pipelines.resync_connector(exchange, symbol).await {
+            return Err(not_running(exchange, symbol));
+        }
+        tracing::info!("resyncing {} connector for {}", exchange, symbol);
+        Ok(Response::new(Empty {}))
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::{ensure, Context, Result};
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path, sync::Arc};
+use tonic::{metadata::MetadataValue, service::Interceptor, Request, Status};
+use crate::{
+    subscription::{CandlesOptions, ExchangeBookOptions, ExchangeFilter, SubscriptionOptions},
+    ExchangeName, Symbol,
+};
+
+// The `ClientPermissions` struct lists what the holder of a token may subscribe to.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ClientPermissions {
+    pub symbols: Vec<Symbol>,
+    pub exchanges: Vec<ExchangeName>,
+    pub max_depth: usize,
+    // Whether the token may use the `Admin` service.
+    #[serde(default)]
+    pub admin: bool,
+}
+
+impl ClientPermissions {
+    // The `authorize` function checks a subscription against the permissions, narrowing the
+    // exchange filter and depth to what is allowed when the subscriber asked for "everything".
+    pub fn authorize(&self, symbol: &Symbol, options: &mut SubscriptionOptions) -> Result<()> {
+        ensure!(self.symbols.contains(symbol), "symbol {symbol} is not allowed");
+        self.authorize_exchanges(&mut options.exchanges)?;
+        self.authorize_depth(&mut options.depth)
+    }
+
+    // The `authorize_venues` function checks a subscription to the trades or the best bid and
+    // offer of a symbol, narrowing the exchange filter like `authorize` does.
+    pub fn authorize_venues(&self, symbol: &Symbol, exchanges: &mut ExchangeFilter) -> Result<()> {
+        ensure!(self.symbols.contains(symbol), "symbol {symbol} is not allowed");
+        self.authorize_exchanges(exchanges)
+    }
+
+    // The `authorize_exchange_book` function checks a subscription to the book of one exchange
+    // the same way, narrowing a request for every level to the allowed depth.
+    pub fn authorize_exchange_book(&self, symbol: &Symbol, options: &mut ExchangeBookOptions) -> Result<()> {
+        ensure!(self.symbols.contains(symbol), "symbol {symbol} is not allowed");
+        ensure!(self.exchanges.contains(&options.exchange), "exchange {} is not allowed", options.exchange);
+        self.authorize_depth(&mut options.depth)
+    }
+
+    // The `authorize_candles` function checks a subscription to bars. Consolidated bars are built
+    // from every exchange, so they need every exchange to be allowed.
+    pub fn authorize_candles(&self, symbol: &Symbol, options: &CandlesOptions) -> Result<()> {
+        ensure!(self.symbols.contains(symbol), "symbol {symbol} is not allowed");
+        match options.exchange {
+            Some(exchange) => ensure!(self.exchanges.contains(&exchange), "exchange {exchange} is not allowed"),
+            None => ensure!(
+                [ExchangeName::BINANCE, ExchangeName::BITSTAMP]
+                    .iter()
+                    .all(|exchange| self.exchanges.contains(exchange)),
+                "consolidated candles need every exchange to be allowed"
+            ),
+        }
+        Ok(())
+    }
+
+    fn authorize_exchanges(&self, filter: &mut ExchangeFilter) -> Result<()> {
+        if filter.include.is_empty() {
+            filter.include = self.exchanges.iter().copied().collect();
+        }
+        if let Some(exchange) = filter.include.iter().find(|e| !self.exchanges.contains(e)) {
+            anyhow::bail!("exchange {exchange} is not allowed");
+        }
+        Ok(())
+    }
+
+    fn authorize_depth(&self, depth: &mut usize) -> Result<()> {
+        if *depth == 0 {
+            *depth = self.max_depth;
+        }
+        ensure!(
+            *depth <= self.max_depth,
+            "depth {} exceeds the allowed depth of {}",
+            depth,
+            self.max_depth
+        );
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenEntry {
+    token: String,
+    #[serde(flatten)]
+    permissions: ClientPermissions,
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyFile {
+    tokens: Vec<TokenEntry>,
+}
+
+// The `load_tokens` function reads the key file that maps bearer tokens to permissions, e.g.
+//
+// [[tokens]]
+// token = "secret"
+// symbols = ["ETHUSDT"]
+// exchanges = ["BINANCE", "BITSTAMP"]
+// max-depth = 10
+// admin = true
+pub fn load_tokens(path: &Path) -> Result<HashMap<String, ClientPermissions>> {
+    let key_file = config::Config::builder()
+        .add_source(config::File::from(path))
+        .build()
+        .with_context(|| format!("Failed to read key file {}", path.display()))?
+        .try_deserialize::<KeyFile>()
+        .context("Failed to deserialize key file")?;
+    Ok(key_file
+        .tokens
+        .into_iter()
+        .map(|entry| (entry.token, entry.permissions))
+        .collect())
+}
+
+// The `AuthInterceptor` validates the bearer token of every request and attaches the matching
+// `ClientPermissions` to the request extensions. Without a token map every request is let through
+// without permissions, which services treat as unrestricted.
+#[derive(Debug, Clone, Default)]
+pub struct AuthInterceptor {
+    tokens: Option<Arc<HashMap<String, ClientPermissions>>>,
+}
+
+impl AuthInterceptor {
+    pub fn new(tokens: HashMap<String, ClientPermissions>) -> Self {
+        Self {
+            tokens: Some(Arc::new(tokens)),
+        }
+    }
+
+    // The `permissions` function resolves the value of an `authorization` header. It returns
+    // `None` when authentication is disabled.
+    pub fn permissions(&self, authorization: Option<&str>) -> Result<Option<ClientPermissions>, Status> {
+        let Some(tokens) = &self.tokens else {
+            return Ok(None);
+        };
+        let token = authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("missing bearer token"))?;
+        // Every token is compared in constant time, so response times do not reveal how much of a
+        // guess matches a real token.
+        let permissions = tokens
+            .iter()
+            .fold(None, |found, (known, permissions)| {
+                if constant_time_eq(known.as_bytes(), token.as_bytes()) { Some(permissions) } else { found }
+            })
+            .cloned()
+            .ok_or_else(|| Status::unauthenticated("invalid bearer token"))?;
+        Ok(Some(permissions))
+    }
+}
+
+// The `constant_time_eq` function compares two secrets in a time that only depends on their
+// lengths, which are not secret.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let authorization = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok());
+        if let Some(permissions) = self.permissions(authorization)? {
+            request.extensions_mut().insert(permissions);
+        }
+        Ok(request)
+    }
+}
+
+// The `with_bearer_token` function adds the `authorization` header clients authenticate with.
+pub fn with_bearer_token<T>(mut request: Request<T>, token: &str) -> Result<Request<T>> {
+    let value = MetadataValue::try_from(format!("Bearer {token}")).context("Invalid token")?;
+    request.metadata_mut().insert("authorization", value);
+    Ok(request)
+}
@@ -0,0 +1,238 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    sync::{broadcast, watch},
+    time::Instant,
+};
+use crate::{
+    orderbook_summary::{BestBidOffer, Candle, CandleInterval, Level, Trade},
+    trades::unix_micros,
+    AggregatedBooks, ExchangeName, Symbol,
+};
+
+pub const INTERVALS: [CandleInterval; 3] =
+    [CandleInterval::OneSecond, CandleInterval::OneMinute, CandleInterval::FiveMinutes];
+
+// How often the builder closes the bars whose interval has passed, when prices stop changing.
+const CLOSE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// The bars of one exchange, or of the merged book when the exchange is `None`, over one interval.
+pub type SeriesKey = (Option<ExchangeName>, CandleInterval);
+
+pub fn interval_micros(interval: CandleInterval) -> u64 {
+    match interval {
+        CandleInterval::OneSecond => 1_000_000,
+        CandleInterval::OneMinute => 60_000_000,
+        CandleInterval::FiveMinutes => 300_000_000,
+    }
+}
+
+// The `Series` struct holds the most recent closed bars of one series and the channel its bars are
+// broadcast on as they close.
+#[derive(Debug)]
+struct Series {
+    bars: VecDeque<Candle>,
+    tx: broadcast::Sender<Candle>,
+}
+
+// The `CandleHistory` struct keeps the most recent closed bars of every series of a symbol in
+// ring buffers and broadcasts each bar as it closes. Each series has its own channel of `capacity`
+// bars, so a busy series does not make the subscribers of another one fall behind. Clones share
+// the same history.
+#[derive(Debug, Clone)]
+pub struct CandleHistory {
+    series: Arc<Mutex<HashMap<SeriesKey, Series>>>,
+    capacity: usize,
+}
+
+impl CandleHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            series: Arc::default(),
+            capacity,
+        }
+    }
+
+    fn series<'a>(&self, series: &'a mut HashMap<SeriesKey, Series>, key: SeriesKey) -> &'a mut Series {
+        series.entry(key).or_insert_with(|| Series {
+            bars: VecDeque::with_capacity(self.capacity),
+            tx: broadcast::channel(self.capacity).0,
+        })
+    }
+
+    fn push(&self, key: SeriesKey, candle: Candle) {
+        let mut series = self.series.lock().unwrap();
+        let series = self.series(&mut series, key);
+        if series.bars.len() == self.capacity {
+            series.bars.pop_front();
+        }
+        series.bars.push_back(candle.clone());
+        // Sent while locked, so a subscriber sees every bar either in its history or on the channel.
+        let _ = series.tx.send(candle);
+    }
+
+    // The `subscribe` function returns the last `count` closed bars of a series, or all of them when
+    // `count` is 0, and a receiver of the bars of the series closed afterwards.
+    pub fn subscribe(&self, key: SeriesKey, count: usize) -> (Vec<Candle>, broadcast::Receiver<Candle>) {
+        let mut series = self.series.lock().unwrap();
+        let series = self.series(&mut series, key);
+        let skipped = if count == 0 { 0 } else { series.bars.len().saturating_sub(count) };
+        (series.bars.iter().skip(skipped).cloned().collect(), series.tx.subscribe())
+    }
+}
+
+// The `CandleBuilder` struct folds the mid-prices and trades of a symbol into the open bar of every
+// series, and moves each bar to the history once its interval has passed. Times are microseconds
+// since the Unix epoch, so bars start on whole seconds and minutes.
+#[derive(Debug)]
+pub struct CandleBuilder {
+    symbol: Symbol,
+    history: CandleHistory,
+    open: HashMap<SeriesKey, Candle>,
+    // Last mid-price of each exchange and of the merged book.
+    last_price: HashMap<Option<ExchangeName>, f64>,
+    // When the book of each exchange last changed, as of the last books added.
+    last_updated: HashMap<ExchangeName, Instant>,
+}
+
+impl CandleBuilder {
+    pub fn new(symbol: Symbol, history: CandleHistory) -> Self {
+        Self {
+            symbol,
+            history,
+            open: HashMap::new(),
+            last_price: HashMap::new(),
+            last_updated: HashMap::new(),
+        }
+    }
+
+    // The `books` function adds the mid-prices of the merged book and of the exchanges whose book
+    // changed since the last books were added, so bars of an exchange only move with its own book.
+    pub fn books(&mut self, books: &AggregatedBooks, at_us: u64) {
+        for (exchange, price) in mid_prices(books) {
+            let unchanged = exchange.is_some_and(|exchange| {
+                let updated = books.last_updated.get(&exchange);
+                updated.is_some() && self.last_updated.get(&exchange) == updated
+            });
+            if !unchanged {
+                self.mid_price(exchange, price, at_us);
+            }
+        }
+        self.last_updated.clone_from(&books.last_updated);
+    }
+
+    // The `mid_price` function adds the mid-price of an exchange, or of the merged book when
+    // `exchange` is `None`, to the bars of every interval.
+    pub fn mid_price(&mut self, exchange: Option<ExchangeName>, price: f64, at_us: u64) {
+        self.last_price.insert(exchange, price);
+        for interval in INTERVALS {
+            let candle = self.bar((exchange, interval), at_us, price);
+            candle.high = candle.high.max(price);
+            candle.low = candle.low.min(price);
+            candle.close = price;
+        }
+    }
+
+    // The `trade` function adds a trade to the volume of the bars of its exchange and of the merged
+    // book. Trades do not move the price of bars, which follow the mid-price, but a trade received
+    // before any bar of its interval opens one at the last mid-price, or at the trade's price.
+    pub fn trade(&mut self, trade: &Trade, at_us: u64) {
+        let Ok(exchange) = trade.exchange.parse::<ExchangeName>() else {
+            return;
+        };
+        for series in [Some(exchange), None] {
+            let price = self.last_price.get(&series).copied().unwrap_or(trade.price);
+            for interval in INTERVALS {
+                let candle = self.bar((series, interval), at_us, price);
+                candle.volume += trade.quantity;
+                candle.trades += 1;
+            }
+        }
+    }
+
+    // The `close_expired` function moves the bars whose interval ended by `now_us` to the history.
+    pub fn close_expired(&mut self, now_us: u64) {
+        let expired: Vec<SeriesKey> = self
+            .open
+            .iter()
+            .filter(|(key, candle)| candle.open_time_us + interval_micros(key.1) <= now_us)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in expired {
+            if let Some(candle) = self.open.remove(&key) {
+                self.history.push(key, candle);
+            }
+        }
+    }
+
+    // The `bar` function returns the bar of a series that `at_us` falls in, closing the open bar
+    // when its interval has passed and opening the new one at `price`.
+    fn bar(&mut self, key: SeriesKey, at_us: u64, price: f64) -> &mut Candle {
+        let open_time_us = at_us - at_us % interval_micros(key.1);
+        if self.open.get(&key).is_some_and(|candle| candle.open_time_us < open_time_us) {
+            if let Some(candle) = self.open.remove(&key) {
+                self.history.push(key, candle);
+            }
+        }
+        self.open.entry(key).or_insert_with(|| Candle {
+            exchange: key.0.map(|exchange| exchange.to_string()).unwrap_or_default(),
+            symbol: self.symbol.to_string(),
+            interval: key.1 as i32,
+            open_time_us,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            ..Default::default()
+        })
+    }
+}
+
+// The `mid_prices` function returns the mid-price of the book of each exchange and of the merged
+// book, leaving out books without bids or asks.
+pub fn mid_prices(books: &AggregatedBooks) -> Vec<(Option<ExchangeName>, f64)> {
+    let mid = |bids: &[Level], asks: &[Level]| -> Option<f64> {
+        Some((bids.first()?.price + asks.first()?.price) / 2.0)
+    };
+    let merged_mid = |bbo: &BestBidOffer| -> Option<f64> {
+        Some((bbo.best_bid.as_ref()?.price + bbo.best_ask.as_ref()?.price) / 2.0)
+    };
+    books
+        .books
+        .iter()
+        .filter_map(|(exchange, book_levels)| Some((Some(*exchange), mid(&book_levels.bids, &book_levels.asks)?)))
+        .chain(merged_mid(&books.best_bid_offer_for(|_| true)).map(|price| (None, price)))
+        .collect()
+}
+
+// The `build_candles` function feeds the books and trades of a pipeline to its candle builder until
+// the pipeline is dropped. Bars are also closed on a timer, so they are published when prices stop
+// changing.
+pub async fn build_candles(
+    mut rx_books: watch::Receiver<AggregatedBooks>,
+    mut rx_trades: broadcast::Receiver<Trade>,
+    mut builder: CandleBuilder,
+) {
+    let mut close_check = tokio::time::interval(CLOSE_CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            changed = rx_books.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                builder.books(&rx_books.borrow_and_update(), unix_micros(SystemTime::now()));
+            }
+            trade = rx_trades.recv() => match trade {
+                Ok(trade) => builder.trade(&trade, unix_micros(SystemTime::now())),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("candle builder skipped {} trades", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = close_check.tick() => builder.close_expired(unix_micros(SystemTime::now())),
+        }
+    }
+}
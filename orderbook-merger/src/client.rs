@@ -1,15 +1,19 @@
 use anyhow::Result;
-use config::Config;
-use std::collections::HashMap;
 use tokio_stream::StreamExt;
 use tonic::Request;
 
-use orderbook_merger::orderbook_summary::{orderbook_aggregator_client::OrderbookAggregatorClient, Empty};
+use orderbook_merger::{
+    auth::with_bearer_token,
+    orderbook_summary::{orderbook_aggregator_client::OrderbookAggregatorClient, SummaryRequest},
+    settings::Settings,
+    tls::client_endpoint,
+};
 use tonic::transport::Channel;
 
-async fn get_orderbook_summary(mut client: OrderbookAggregatorClient<Channel>) -> Result<()> {
-    let request = Request::new(Empty{});
-    
+async fn get_orderbook_summary(
+    mut client: OrderbookAggregatorClient<Channel>,
+    request: Request<SummaryRequest>,
+) -> Result<()> {
     let mut stream = client.book_summary(request).await?.into_inner();
 
     while let Some(result) = stream.next().await {
@@ -26,22 +30,21 @@ async fn get_orderbook_summary(mut client: OrderbookAggregatorClient<Channel>) -
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let config = Config::builder()
-        // read the setting.toml
-        .add_source(config::File::with_name("orderbook-merger/src/setting"))
-        .build()
-        .unwrap()
-        .try_deserialize::<HashMap<String, String>>()
-        .unwrap();
+    let settings = Settings::from_args()?;
 
-    // client uses https://IP:Port
-    let address = format!("https://{}:{}", config.get("server-ip").unwrap(), config.get("server-port").unwrap());
+    // client uses https://IP:Port when TLS is configured, http://IP:Port otherwise
+    let endpoint = client_endpoint(&settings)?;
 
-    println!("{:?}", address);
+    println!("{:?}", endpoint.uri());
 
-    let client = OrderbookAggregatorClient::connect(address).await?;
+    let client = OrderbookAggregatorClient::connect(endpoint).await?;
 
-    get_orderbook_summary(client).await?;
+    let mut request = Request::new(SummaryRequest::default());
+    if let Some(token) = &settings.auth_token {
+        request = with_bearer_token(request, token)?;
+    }
+
+    get_orderbook_summary(client, request).await?;
 
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,47 @@
+use anyhow::Result;
+use std::{future::Future, time::Duration};
+use tokio_util::sync::CancellationToken;
+use crate::pipeline::Pipelines;
+
+// Streams still open this long after a shutdown signal are dropped, and connectors are waited for
+// as long after the server returned.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The `serve_until_drained` function runs `server` until `shutdown` is cancelled. The server then
+// stops accepting connections while the connectors of `pipelines` close their websockets. That
+// ends the pipelines, so open streams get a final UNAVAILABLE status and finish, and the server
+// returns once they are drained. Streams and connectors are each waited for at most `timeout`.
+pub async fn serve_until_drained<F, E>(
+    server: F,
+    pipelines: Pipelines,
+    shutdown: CancellationToken,
+    timeout: Duration,
+) -> Result<()>
+where
+    F: Future<Output = std::result::Result<(), E>>,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let connectors_stopped = {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            shutdown.cancelled().await;
+            pipelines.shutdown().await;
+            tracing::info!("exchange connectors stopped");
+        })
+    };
+
+    tokio::select! {
+        result = server => result?,
+        _ = async {
+            shutdown.cancelled().await;
+            tokio::time::sleep(timeout).await;
+        } => tracing::warn!("streams were not drained within {:?}", timeout),
+    }
+    // Wait for the connectors in case the server drained before they closed their websockets, but
+    // not forever if an exchange does not answer the close handshake.
+    match tokio::time::timeout(timeout, connectors_stopped).await {
+        Ok(stopped) => stopped?,
+        Err(_) => tracing::warn!("exchange connectors did not stop within {:?}", timeout),
+    }
+    Ok(())
+}
@@ -1,37 +1,21 @@
 use anyhow::{ensure, Context, Result};
-use crate::{Symbol, ExchangeName, orderbook::orderbook::{OrderBook, Update}};
-use super::exchange::Exchange;
-use serde::{Deserialize, Deserializer, Serialize};
-use std::{
-    collections::BTreeMap,
-    sync::Arc,
+use crate::{
+    Symbol, ExchangeName,
+    orderbook::orderbook::{Scales, StorageLevel, Update},
+    orderbook_summary::{AggressorSide, Trade},
+    trades::{display_amount, unix_micros},
 };
+use super::{exchange::{ConnectorConfig, Exchange}, parse::{parse, BookSeed}};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use rust_decimal::Decimal;
-use std::str::FromStr;
-use tokio::sync::Mutex;
+use std::{str::FromStr, time::SystemTime};
+use futures::SinkExt;
 use async_trait::async_trait;
 use url::Url;
 use tokio::net::TcpStream;
 use tokio_tungstenite::{tungstenite::Message, connect_async, MaybeTlsStream, WebSocketStream};
 
-fn from_str<'de, D>(deserializer: D) -> Result<BTreeMap<Decimal, Decimal>, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let v: Vec<[&str; 2]> = Deserialize::deserialize(deserializer)?;
-    let mut map = BTreeMap::new();
-    for s in v {
-        match (s[0].parse::<Decimal>(), s[1].parse::<Decimal>()) {
-            (Ok(key), Ok(value)) => {
-                map.insert(key, value);
-            }
-            _ => return Err(serde::de::Error::custom("Failed to parse Decimal")),
-        }
-    }
-    Ok(map)
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(super) struct SymbolData {
@@ -46,103 +30,100 @@ pub(super) struct ExchangeInfoBinance {
     pub symbols: Vec<SymbolData>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, Default)]
 pub struct Snapshot {
     pub last_update_id: u64,
-    #[serde(deserialize_with = "from_str")]
-    pub bids: BTreeMap<Decimal, Decimal>,
-    #[serde(deserialize_with = "from_str")]
-    pub asks: BTreeMap<Decimal, Decimal>,
+    pub bids: Vec<StorageLevel>,
+    pub asks: Vec<StorageLevel>,
 }
 
 impl Update for Snapshot {
-    fn validate(&self, _: u64) -> Result<()> {
+    fn parse(bytes: &[u8], scales: Scales) -> Result<Self> {
+        let seed = BookSeed {
+            scales,
+            first_update_id: &[],
+            last_update_id: &["lastUpdateId"],
+        };
+        let book = parse(bytes, seed).context("Failed to deserialize snapshot")?;
+        Ok(Self {
+            last_update_id: book.last_update_id,
+            bids: book.bids,
+            asks: book.asks,
+        })
+    }
+    fn validate(&self, _: u64, _: u64) -> Result<()> {
         Ok(())
     }
     fn last_update_id(&self) -> u64 {
         self.last_update_id
     }
-    fn bids_mut(&mut self) -> &mut BTreeMap<Decimal, Decimal> {
-        &mut self.bids
+    fn bids(&self) -> &[StorageLevel] {
+        &self.bids
     }
-
-    fn asks_mut(&mut self) -> &mut BTreeMap<Decimal, Decimal> {
-        &mut self.asks
+    fn asks(&self) -> &[StorageLevel] {
+        &self.asks
     }
 }
 
-impl Snapshot {
-    pub(crate) async fn fetch(url: Url) -> Result<Self> {
-        let snapshot = reqwest::get(url)
-            .await
-            .context("Failed to get snapshot")?
-            .json::<Self>()
-            .await
-            .context("Failed to deserialize snapshot")?;
-        Ok(snapshot)
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize, Default)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, Default)]
 pub struct BookUpdate {
-    #[serde(alias = "U")]
     pub first_update_id: u64,
-    #[serde(alias = "u")]
     pub last_update_id: u64,
-    #[serde(alias = "b", deserialize_with = "from_str")]
-    pub bids: BTreeMap<Decimal, Decimal>,
-    #[serde(alias = "a", deserialize_with = "from_str")]
-    pub asks: BTreeMap<Decimal, Decimal>,
+    pub bids: Vec<StorageLevel>,
+    pub asks: Vec<StorageLevel>,
 }
 
 impl Update for BookUpdate {
-    fn validate(&self, last_id: u64) -> Result<()> {
+    fn parse(bytes: &[u8], scales: Scales) -> Result<Self> {
+        let seed = BookSeed {
+            scales,
+            first_update_id: &["U", "firstUpdateId"],
+            last_update_id: &["u", "lastUpdateId"],
+        };
+        let book = parse(bytes, seed).context("Failed to deserialize update")?;
+        Ok(Self {
+            first_update_id: book.first_update_id.context("Failed to deserialize update: missing field `U`")?,
+            last_update_id: book.last_update_id,
+            bids: book.bids,
+            asks: book.asks,
+        })
+    }
+    fn validate(&self, last_id: u64, snapshot_id: u64) -> Result<()> {
         let first_update_id = self.first_update_id;
         if last_id == 0 {
             return Ok(());
         }
-        // println!("{} {}", first_update_id, last_id + 1);
-        ensure!(
-            first_update_id == last_id + 1,
-            "first_update_id: {first_update_id} != last_id: {last_id} + 1"
-        );
-        // println!("wtf2");
+        // The first update applied after the snapshot may overlap it, every later update must
+        // start right after the previous one. Anything else means updates were missed.
+        if last_id == snapshot_id {
+            ensure!(
+                first_update_id <= last_id + 1,
+                "first_update_id: {first_update_id} > last_id: {last_id} + 1"
+            );
+        } else {
+            ensure!(
+                first_update_id == last_id + 1,
+                "first_update_id: {first_update_id} != last_id: {last_id} + 1"
+            );
+        }
         ensure!(
-            last_id < self.first_update_id,
-            "last_id: {last_id} >= first_update_id: {first_update_id}"
+            last_id < self.last_update_id,
+            "last_id: {last_id} >= last_update_id: {}",
+            self.last_update_id
         );
         Ok(())
     }
+    fn is_stale(&self, last_id: u64) -> bool {
+        last_id != 0 && self.last_update_id <= last_id
+    }
     fn last_update_id(&self) -> u64 {
         self.last_update_id
     }
-    fn bids_mut(&mut self) -> &mut BTreeMap<Decimal, Decimal> {
-        &mut self.bids
+    fn bids(&self) -> &[StorageLevel] {
+        &self.bids
     }
-
-    fn asks_mut(&mut self) -> &mut BTreeMap<Decimal, Decimal> {
-        &mut self.asks
-    }
-}
-
-impl TryFrom<Message> for BookUpdate {
-    type Error = anyhow::Error;
-    fn try_from(item: Message) -> Result<Self> {
-        // serde_json::from_slice::<Self>(&item.into_data()).context("Failed to deserialize update")
-        match serde_json::from_slice::<Self>(&item.clone().into_data()) { // remove clone
-            Ok(update) => {
-                tracing::debug!("original: {:?}", &item.clone().into_text());
-                tracing::debug!("update: {:?}", update);
-                Ok(update)
-            },
-            Err(e) => {
-                tracing::error!("Failed to deserialize update: {}", e);
-                tracing::error!("{:?}", &item.clone().into_text());
-                Err(anyhow::Error::new(e).context("Failed to deserialize update"))
-            }
-        }
+    fn asks(&self) -> &[StorageLevel] {
+        &self.asks
     }
 }
 
@@ -157,33 +138,64 @@ impl From<Snapshot> for BookUpdate {
     }
 }
 
+// A `TradeEvent` is a message of the `@trade` stream.
+#[derive(Debug, Deserialize)]
+struct TradeEvent<'a> {
+    #[serde(rename = "e")]
+    event: &'a str,
+    #[serde(rename = "t")]
+    trade_id: u64,
+    #[serde(rename = "p")]
+    price: &'a str,
+    #[serde(rename = "q")]
+    quantity: &'a str,
+    // Trade time in milliseconds.
+    #[serde(rename = "T")]
+    trade_time: u64,
+    // The buyer was the maker, so the seller took liquidity.
+    #[serde(rename = "m")]
+    buyer_is_maker: bool,
+}
+
 pub struct Binance {
-    pub orderbook: Arc<Mutex<OrderBook>>,
+    pub symbol: Symbol,
+    pub scales: Scales,
+    pub config: ConnectorConfig,
 }
 
 #[async_trait]
 impl Exchange<Snapshot, BookUpdate> for Binance {
 
+    const EXCHANGE: ExchangeName = ExchangeName::BINANCE;
     const BASE_URL_HTTPS: &'static str = "https://api.binance.com/api/v3/";
     const BASE_URL_WSS: &'static str = "wss://stream.binance.com:9443/ws/";
 
-    fn orderbook(&self) -> Arc<Mutex<OrderBook>> {
-        self.orderbook.clone()
+    fn config(&self) -> &ConnectorConfig {
+        &self.config
+    }
+
+    fn symbol(&self) -> Symbol {
+        self.symbol
     }
 
-    async fn new_exchange(symbol: Symbol) -> Result<Self>
+    fn scales(&self) -> Scales {
+        self.scales
+    }
+
+    async fn new_exchange(symbol: Symbol, config: ConnectorConfig) -> Result<Self>
     {
-        let orderbook = Self::new_orderbook(ExchangeName::BINANCE, symbol).await?;
+        let (price_scale, quantity_scale) = Self::get_scales(&symbol, &config.rest_url).await?;
         Ok(
             Self {
-                orderbook: Arc::new(Mutex::new(orderbook))
+                symbol,
+                scales: Scales { price_scale, quantity_scale },
+                config,
             }
         )
     }
 
-    async fn get_scales(symbol: &Symbol) -> Result<(u32, u32)> {
-        let url = Self::base_url_https();
-        let mut endpoint = url.join("exchangeInfo").unwrap();
+    async fn get_scales(symbol: &Symbol, rest_url: &Url) -> Result<(u32, u32)> {
+        let mut endpoint = rest_url.join("exchangeInfo").unwrap();
         
         endpoint
             .query_pairs_mut()
@@ -238,28 +250,61 @@ impl Exchange<Snapshot, BookUpdate> for Binance {
     }
 
     async fn get_snapshot(&self) -> Result<Snapshot> {
-        let symbol = self.orderbook().lock().await.symbol;
-        let mut url = Self::base_url_https().join("depth").unwrap();
+        let mut url = self.config.rest_url.join("depth").unwrap();
         url.query_pairs_mut()
-            .append_pair("symbol", &symbol.to_string())
+            .append_pair("symbol", &self.symbol.to_string())
             .append_pair("limit", "1000")
             .finish();
-        Snapshot::fetch(url).await
+        let body = self.fetch_snapshot_body(url).await?;
+        Snapshot::parse(&body, self.scales)
     }
 
     async fn get_websocket_stream(&self) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
         let symbol = self
-            .orderbook()
-            .lock()
-            .await
             .symbol
             .to_string()
             .to_lowercase();
         let endpoint = format!("{}@depth@100ms", symbol);
-        let url = Self::base_url_wss().join(&endpoint).unwrap();
-        let (stream, _) = connect_async(url)
+        let url = self.config.websocket_url.join(&endpoint).unwrap();
+        let (mut stream, _) = connect_async(url)
             .await
             .context("Failed to connect to wss endpoint")?;
+
+        // Trades are subscribed to on the same connection, so their messages are not wrapped like
+        // on combined streams.
+        if self.config.trades.is_some() {
+            let subscribe_msg = serde_json::json!({
+                "method": "SUBSCRIBE",
+                "params": [format!("{}@trade", symbol)],
+                "id": 1
+            });
+            stream
+                .send(Message::Text(subscribe_msg.to_string()))
+                .await
+                .context("Failed to send trade subscribe message to binance")?;
+        }
         Ok(stream)
     }
+
+    // Binance replies `{"result":null,"id":1}` to a successful `SUBSCRIBE`.
+    fn is_subscription_reply(bytes: &[u8]) -> bool {
+        serde_json::from_slice::<Value>(bytes)
+            .is_ok_and(|reply| reply.get("result").is_some_and(Value::is_null) && reply.get("id").is_some())
+    }
+
+    fn parse_trade(bytes: &[u8], symbol: Symbol, scales: Scales, received_at: SystemTime) -> Result<Trade> {
+        let event: TradeEvent = serde_json::from_slice(bytes).context("Failed to deserialize trade")?;
+        ensure!(event.event == "trade", "not a trade: {}", event.event);
+        let side = if event.buyer_is_maker { AggressorSide::Sell } else { AggressorSide::Buy };
+        Ok(Trade {
+            exchange: Self::EXCHANGE.to_string(),
+            symbol: symbol.to_string(),
+            trade_id: event.trade_id,
+            price: display_amount(event.price, scales.price_scale)?,
+            quantity: display_amount(event.quantity, scales.quantity_scale)?,
+            aggressor_side: side as i32,
+            traded_at_us: event.trade_time * 1_000,
+            received_at_us: unix_micros(received_at),
+        })
+    }
 }
\ No newline at end of file
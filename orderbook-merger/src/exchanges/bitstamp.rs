@@ -1,37 +1,20 @@
-use anyhow::{Context, Result};
-use crate::{DisplayAmount, Symbol, ExchangeName, orderbook::orderbook::{OrderBook, Update}};
-use super::exchange::Exchange;
-use serde::{Deserialize, Deserializer, Serialize};
-use std::{
-    collections::BTreeMap,
-    sync::Arc,
+use anyhow::{bail, ensure, Context, Result};
+use crate::{
+    Symbol, ExchangeName,
+    orderbook::orderbook::{Scales, StorageLevel, Update},
+    orderbook_summary::{AggressorSide, Trade},
+    trades::{display_amount, unix_micros},
 };
+use super::{exchange::{ConnectorConfig, Exchange}, parse::{parse, BookSeed, Field}};
+use serde::{Deserialize, Serialize};
+use serde_aux::field_attributes::deserialize_number_from_string;
+use std::time::SystemTime;
 use futures::SinkExt;
-use rust_decimal::Decimal;
-use tokio::sync::Mutex;
 use async_trait::async_trait;
 use url::Url;
 use tokio::net::TcpStream;
-use serde_aux::field_attributes::deserialize_number_from_string;
 use tokio_tungstenite::{tungstenite::Message, connect_async, MaybeTlsStream, WebSocketStream};
 
-fn from_str<'de, D>(deserializer: D) -> Result<BTreeMap<Decimal, Decimal>, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let v: Vec<[&str; 2]> = Deserialize::deserialize(deserializer)?;
-    let mut map = BTreeMap::new();
-    for s in v {
-        match (s[0].parse::<Decimal>(), s[1].parse::<Decimal>()) {
-            (Ok(key), Ok(value)) => {
-                map.insert(key, value);
-            }
-            _ => return Err(serde::de::Error::custom("Failed to parse Decimal")),
-        }
-    }
-    Ok(map)
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(super) struct SymbolData {
     pub url_symbol: String,
@@ -40,96 +23,90 @@ pub(super) struct SymbolData {
     pub instant_order_counter_decimals: u32,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+// Bitstamp books are versioned by the microsecond timestamp of their last change.
+const MICROTIMESTAMP: &[&str] = &["microtimestamp"];
+
+#[derive(Debug, Default)]
 pub struct Snapshot {
-    #[serde(
-        alias = "microtimestamp",
-        deserialize_with = "deserialize_number_from_string"
-    )]
     pub last_update_id: u64,
-    #[serde(deserialize_with = "from_str")]
-    pub bids: BTreeMap<Decimal, Decimal>,
-    #[serde(deserialize_with = "from_str")]
-    pub asks: BTreeMap<Decimal, Decimal>,
+    pub bids: Vec<StorageLevel>,
+    pub asks: Vec<StorageLevel>,
 }
 
 impl Update for Snapshot {
-    fn validate(&self, _: u64) -> Result<()> {
+    fn parse(bytes: &[u8], scales: Scales) -> Result<Self> {
+        let seed = BookSeed {
+            scales,
+            first_update_id: &[],
+            last_update_id: MICROTIMESTAMP,
+        };
+        let book = parse(bytes, seed).context("Failed to deserialize snapshot")?;
+        Ok(Self {
+            last_update_id: book.last_update_id,
+            bids: book.bids,
+            asks: book.asks,
+        })
+    }
+    fn validate(&self, _: u64, _: u64) -> Result<()> {
         Ok(())
     }
     fn last_update_id(&self) -> u64 {
         self.last_update_id
     }
-    fn bids_mut(&mut self) -> &mut BTreeMap<Decimal, Decimal> {
-        &mut self.bids
+    fn bids(&self) -> &[StorageLevel] {
+        &self.bids
     }
-
-    fn asks_mut(&mut self) -> &mut BTreeMap<Decimal, Decimal> {
-        &mut self.asks
+    fn asks(&self) -> &[StorageLevel] {
+        &self.asks
     }
 }
 
-impl Snapshot {
-    pub(crate) async fn fetch(url: Url) -> Result<Self> {
-        let snapshot = reqwest::get(url)
-            .await
-            .context("Failed to get snapshot")?
-            .json::<Self>()
-            .await
-            .context("Failed to deserialize snapshot")?;
-        Ok(snapshot)
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Default)]
 pub struct BookUpdateData {
-    #[serde(
-        alias = "microtimestamp",
-        deserialize_with = "deserialize_number_from_string"
-    )]
     pub last_update_id: u64,
-    #[serde(alias = "b", deserialize_with = "from_str")]
-    pub bids: BTreeMap<DisplayAmount, DisplayAmount>,
-    #[serde(alias = "a", deserialize_with = "from_str")]
-    pub asks: BTreeMap<DisplayAmount, DisplayAmount>,
+    pub bids: Vec<StorageLevel>,
+    pub asks: Vec<StorageLevel>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+// A `BookUpdate` is the `data` of a `diff_order_book_` channel message.
+#[derive(Debug, Default)]
 pub struct BookUpdate {
     data: BookUpdateData,
 }
 
 impl Update for BookUpdate {
-    fn validate(&self, _: u64) -> Result<()> {
+    fn parse(bytes: &[u8], scales: Scales) -> Result<Self> {
+        let seed = Field {
+            name: "data",
+            seed: BookSeed {
+                scales,
+                first_update_id: &[],
+                last_update_id: MICROTIMESTAMP,
+            },
+        };
+        let book = parse(bytes, seed).context("Failed to deserialize update")?;
+        Ok(Self {
+            data: BookUpdateData {
+                last_update_id: book.last_update_id,
+                bids: book.bids,
+                asks: book.asks,
+            },
+        })
+    }
+    fn validate(&self, _: u64, _: u64) -> Result<()> {
         Ok(())
     }
+    fn is_stale(&self, last_id: u64) -> bool {
+        self.data.last_update_id <= last_id
+    }
     fn last_update_id(&self) -> u64 {
         self.data.last_update_id
     }
-    fn bids_mut(&mut self) -> &mut BTreeMap<DisplayAmount, DisplayAmount> {
-        &mut self.data.bids
+    fn bids(&self) -> &[StorageLevel] {
+        &self.data.bids
     }
-
-    fn asks_mut(&mut self) -> &mut BTreeMap<DisplayAmount, DisplayAmount> {
-        &mut self.data.asks
-    }
-}
-
-impl TryFrom<Message> for BookUpdate {
-    type Error = anyhow::Error;
-    fn try_from(item: Message) -> Result<Self> {
-        match serde_json::from_slice::<Self>(&item.clone().into_data()) { // remove clone
-            Ok(update) => {
-                tracing::debug!("original: {:?}", &item.clone().into_text());
-                tracing::debug!("update: {:?}", update);
-                Ok(update)
-            },
-            Err(e) => {
-                tracing::error!("Failed to deserialize update: {}", e);
-                tracing::error!("{:?}", &item.clone().into_text());
-                Err(anyhow::Error::new(e).context("Failed to deserialize update"))
-            }
-        }
+    fn asks(&self) -> &[StorageLevel] {
+        &self.data.asks
     }
 }
 
@@ -145,34 +122,72 @@ impl From<Snapshot> for BookUpdate {
     }
 }
 
+// An `EventMessage` is any message of the websocket, read only for its event.
+#[derive(Debug, Deserialize)]
+struct EventMessage<'a> {
+    event: &'a str,
+}
+
+// A `TradeMessage` is a message of the `live_trades_` channel.
+#[derive(Debug, Deserialize)]
+struct TradeMessage<'a> {
+    event: &'a str,
+    #[serde(borrow)]
+    data: TradeData<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TradeData<'a> {
+    id: u64,
+    price_str: &'a str,
+    amount_str: &'a str,
+    // 0 when the buyer took liquidity, 1 when the seller did.
+    #[serde(rename = "type")]
+    side: u8,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    microtimestamp: u64,
+}
+
 pub struct Bitstamp {
-    pub orderbook: Arc<Mutex<OrderBook>>,
+    pub symbol: Symbol,
+    pub scales: Scales,
+    pub config: ConnectorConfig,
 }
 
 #[async_trait]
 impl Exchange<Snapshot, BookUpdate> for Bitstamp {
 
+    const EXCHANGE: ExchangeName = ExchangeName::BITSTAMP;
     const BASE_URL_HTTPS: &'static str = "https://www.bitstamp.net/api/v2/";
     const BASE_URL_WSS: &'static str = "wss://ws.bitstamp.net/";
 
-    fn orderbook(&self) -> Arc<Mutex<OrderBook>> {
-        self.orderbook.clone()
+    fn config(&self) -> &ConnectorConfig {
+        &self.config
     }
-    async fn new_exchange(symbol: Symbol) -> Result<Self>
+
+    fn symbol(&self) -> Symbol {
+        self.symbol
+    }
+
+    fn scales(&self) -> Scales {
+        self.scales
+    }
+    async fn new_exchange(symbol: Symbol, config: ConnectorConfig) -> Result<Self>
     where
         Self: Sized,
     {
-        let orderbook = Self::new_orderbook(ExchangeName::BITSTAMP, symbol).await?;
+        let (price_scale, quantity_scale) = Self::get_scales(&symbol, &config.rest_url).await?;
         Ok(
             Self {
-                orderbook: Arc::new(Mutex::new(orderbook))
+                symbol,
+                scales: Scales { price_scale, quantity_scale },
+                config,
             }
         )
     }
 
-    async fn get_scales(symbol: &Symbol) -> Result<(u32, u32)> {
-        let url = Self::base_url_https();
-        let endpoint = url.join("trading-pairs-info").unwrap();
+    async fn get_scales(symbol: &Symbol, rest_url: &Url) -> Result<(u32, u32)> {
+        let endpoint = rest_url.join("trading-pairs-info").unwrap();
         
         let symbols = reqwest::get(endpoint)
             .await
@@ -183,8 +198,7 @@ impl Exchange<Snapshot, BookUpdate> for Bitstamp {
 
         let symbol = symbols
             .into_iter()
-            .filter(|s| s.url_symbol == symbol.to_string().to_lowercase())
-            .next()
+            .find(|s| s.url_symbol == symbol.to_string().to_lowercase())
             .context("Failed to get symbol")?;
 
         let price_scale = symbol.counter_decimals;
@@ -194,35 +208,84 @@ impl Exchange<Snapshot, BookUpdate> for Bitstamp {
     }
 
     async fn get_snapshot(&self) -> Result<Snapshot> {
-        let symbol = self.orderbook().lock().await.symbol.to_string().to_lowercase();
-        let url = Self::base_url_https().join(format!("order_book/{}", symbol).as_str())?;
-        Snapshot::fetch(url).await
+        let symbol = self.symbol.to_string().to_lowercase();
+        let url = self.config.rest_url.join(format!("order_book/{}", symbol).as_str())?;
+        let body = self.fetch_snapshot_body(url).await?;
+        Snapshot::parse(&body, self.scales)
     }
 
     async fn get_websocket_stream(&self) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
-        let symbol = self
-            .orderbook()
-            .lock()
-            .await
-            .symbol
-            .to_string()
-            .to_lowercase();
-
-        let subscribe_msg = serde_json::json!({
-            "event": "bts:subscribe",
-            "data": {
-                "channel": format!("diff_order_book_{}", symbol)
-            }
-        });
-
-        let (mut stream, _) = connect_async(&Self::base_url_wss())
+        let (mut stream, _) = connect_async(&self.config.websocket_url)
             .await
             .context("Failed to connect to bit stamp wss endpoint")?;
 
-        stream
-            .start_send_unpin(Message::Text(subscribe_msg.to_string()))
-            .context("Failed to send subscribe message to bitstamp")?;
+        for channel in self.channels() {
+            let subscribe_msg = serde_json::json!({
+                "event": "bts:subscribe",
+                "data": {
+                    "channel": channel
+                }
+            });
+            stream
+                .send(Message::Text(subscribe_msg.to_string()))
+                .await
+                .context("Failed to send subscribe message to bitstamp")?;
+        }
 
         Ok(stream)
     }
-}
\ No newline at end of file
+
+    fn unsubscribe_messages(&self) -> Vec<Message> {
+        self.channels()
+            .into_iter()
+            .map(|channel| {
+                let unsubscribe_msg = serde_json::json!({
+                    "event": "bts:unsubscribe",
+                    "data": {
+                        "channel": channel
+                    }
+                });
+                Message::Text(unsubscribe_msg.to_string())
+            })
+            .collect()
+    }
+
+    fn is_subscription_reply(bytes: &[u8]) -> bool {
+        serde_json::from_slice::<EventMessage>(bytes).is_ok_and(|message| {
+            matches!(message.event, "bts:subscription_succeeded" | "bts:unsubscription_succeeded")
+        })
+    }
+
+    fn parse_trade(bytes: &[u8], symbol: Symbol, scales: Scales, received_at: SystemTime) -> Result<Trade> {
+        let message: TradeMessage = serde_json::from_slice(bytes).context("Failed to deserialize trade")?;
+        ensure!(message.event == "trade", "not a trade: {}", message.event);
+        let trade = message.data;
+        let side = match trade.side {
+            0 => AggressorSide::Buy,
+            1 => AggressorSide::Sell,
+            side => bail!("unknown trade type: {side}"),
+        };
+        Ok(Trade {
+            exchange: Self::EXCHANGE.to_string(),
+            symbol: symbol.to_string(),
+            trade_id: trade.id,
+            price: display_amount(trade.price_str, scales.price_scale)?,
+            quantity: display_amount(trade.amount_str, scales.quantity_scale)?,
+            aggressor_side: side as i32,
+            traded_at_us: trade.microtimestamp,
+            received_at_us: unix_micros(received_at),
+        })
+    }
+}
+
+impl Bitstamp {
+    // The `channels` function returns the channels subscribed to after connecting.
+    fn channels(&self) -> Vec<String> {
+        let symbol = self.symbol.to_string().to_lowercase();
+        let mut channels = vec![format!("diff_order_book_{}", symbol)];
+        if self.config.trades.is_some() {
+            channels.push(format!("live_trades_{}", symbol));
+        }
+        channels
+    }
+}
@@ -1,16 +1,64 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use url::Url;
-use crate::orderbook::orderbook::{OrderBook, OrderBookOnlyLevels, Update};
+use crate::orderbook::orderbook::{OrderBook, OrderBookOnlyLevels, PublishedBook, Scales, Update};
 use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
 use async_trait::async_trait;
+use futures::SinkExt;
 use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
 use tokio::{
     net::TcpStream,
-    sync::{mpsc, Mutex},
+    sync::{mpsc, oneshot},
     task::JoinHandle,
+    time::Instant,
 };
-use std::{thread, time::Duration, sync::Arc};
-use crate::{Symbol, ExchangeName};
+use std::{thread, time::{Duration, SystemTime}};
+use crate::{metrics, orderbook_summary::Trade, recorder::Recorder, trades::TradeTape, Symbol, ExchangeName};
+
+// The `ConnectorConfig` struct holds the endpoints and tuning of one exchange connector.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectorConfig {
+    pub rest_url: Url,
+    pub websocket_url: Url,
+    // Number of bid and ask levels of the exchange's book sent to the aggregator.
+    pub depth: usize,
+    // Capacity of the channel between the websocket reader and the book.
+    pub update_channel_size: usize,
+    // Records every raw message and snapshot when set.
+    pub recorder: Option<Recorder>,
+    // Publishes a snapshot of the full book after every update when set.
+    pub book: Option<PublishedBook>,
+    // Subscribes to the trades of the exchange and publishes them when set.
+    pub trades: Option<TradeTape>,
+}
+
+// The `message_bytes` function returns the payload of a text or binary frame.
+pub fn message_bytes(message: &Message) -> Option<&[u8]> {
+    match message {
+        Message::Text(text) => Some(text.as_bytes()),
+        Message::Binary(data) => Some(data),
+        _ => None,
+    }
+}
+
+// The `parse_message` function parses an update straight from the payload of a text or binary
+// frame, without copying it.
+pub fn parse_message<U: Update>(message: &Message, scales: Scales) -> Result<U> {
+    match message_bytes(message) {
+        Some(bytes) => U::parse(bytes, scales),
+        None => bail!("not a data frame"),
+    }
+}
+
+// The `AbortOnDrop` struct aborts the websocket reader of a connector when the update loop returns
+// early or is itself aborted, so the reader never outlives its connector.
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
 
 #[async_trait]
 pub trait Exchange<
@@ -18,88 +66,178 @@ pub trait Exchange<
     U: std::fmt::Debug
         + Update
         + From<S>
-        + TryFrom<Message, Error = anyhow::Error>
         + Send
         + Sync
         + 'static,
     Error = anyhow::Error,>
 {
+    const EXCHANGE: ExchangeName;
     const BASE_URL_HTTPS: &'static str;
     const BASE_URL_WSS: &'static str;
 
-    fn base_url_https() -> Url {
-        Url::parse(Self::BASE_URL_HTTPS).unwrap()
-    }
-    fn base_url_wss() -> Url {
-        Url::parse(Self::BASE_URL_WSS).unwrap()
+    // The `default_config` function returns the public endpoints of the exchange with the
+    // default depth and channel size.
+    fn default_config() -> ConnectorConfig {
+        ConnectorConfig {
+            rest_url: Url::parse(Self::BASE_URL_HTTPS).unwrap(),
+            websocket_url: Url::parse(Self::BASE_URL_WSS).unwrap(),
+            depth: 10,
+            update_channel_size: 100,
+            recorder: None,
+            book: None,
+            trades: None,
+        }
     }
-    fn orderbook(&self) -> Arc<Mutex<OrderBook>>;
+    fn config(&self) -> &ConnectorConfig;
+    fn symbol(&self) -> Symbol;
+    fn scales(&self) -> Scales;
 
-    async fn new_exchange(symbol: Symbol) -> Result<Self>
+    async fn new_exchange(symbol: Symbol, config: ConnectorConfig) -> Result<Self>
     where
         Self: Sized;
 
-    async fn new_orderbook(
-        exchange: ExchangeName,
-        symbol: Symbol
-    ) -> Result<OrderBook>
-    where
-        Self: Sized,
-    {
-        let (price_scale, quantity_scale) = Self::get_scales(&symbol).await?;
+    async fn get_scales(symbol: &Symbol, rest_url: &Url) -> Result<(u32, u32)>;
+    async fn get_snapshot(&self) -> Result<S>;
 
-        let orderbook = OrderBook::new_orderbook(
-            exchange,
-            symbol,
-            price_scale,
-            quantity_scale,
-        );
+    // The `fetch_snapshot_body` function downloads a REST snapshot, recording its body when a
+    // recorder is configured.
+    async fn fetch_snapshot_body(&self, url: Url) -> Result<Vec<u8>> {
+        let body = reqwest::get(url)
+            .await
+            .context("Failed to get snapshot")?
+            .bytes()
+            .await
+            .context("Failed to read snapshot")?;
+        if let Some(recorder) = &self.config().recorder {
+            recorder.record_snapshot(Self::EXCHANGE, self.symbol(), &body);
+        }
+        Ok(body.to_vec())
+    }
+    // The `get_websocket_stream` function connects to the book updates of the exchange, and to
+    // its trades when `trades` is configured.
+    async fn get_websocket_stream(&self) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>>;
+    // The `unsubscribe_messages` function returns the messages sent before closing the websocket,
+    // for exchanges that subscribe after connecting.
+    fn unsubscribe_messages(&self) -> Vec<Message> {
+        Vec::new()
+    }
+    // The `parse_trade` function parses a message of the trade channel, received at `received_at`.
+    fn parse_trade(bytes: &[u8], symbol: Symbol, scales: Scales, received_at: SystemTime) -> Result<Trade>;
+    // The `is_subscription_reply` function returns true for the replies of the exchange to
+    // subscribing and unsubscribing, which carry no data.
+    fn is_subscription_reply(_bytes: &[u8]) -> bool {
+        false
+    }
 
-        tracing::debug!(
-            "returning orderbook for {} {}",
-            exchange,
-            symbol
-        );
-        Ok(orderbook)
+    // The `start` function streams the book of the exchange to `tx_summary` until the websocket
+    // ends, an update fails, or `shutdown` is cancelled, which closes the websocket cleanly. The
+    // book is owned by the update loop and rebuilt from a new snapshot on every start, readers
+    // only see the snapshots it publishes.
+    async fn start(
+        &self,
+        tx_summary: mpsc::Sender<OrderBookOnlyLevels>,
+        shutdown: CancellationToken,
+    ) -> Result<()> {
+        self.run(tx_summary, shutdown, None).await
     }
 
-    async fn get_scales(symbol: &Symbol) -> Result<(u32, u32)>;
-    async fn get_snapshot(&self) -> Result<S>;
-    async fn get_websocket_stream(&self) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>>;
+    // The `run` function is `start`, signalling `ready` once the websocket is connected and the
+    // snapshot has been applied to the book.
+    async fn run(
+        &self,
+        tx_summary: mpsc::Sender<OrderBookOnlyLevels>,
+        shutdown: CancellationToken,
+        mut ready: Option<oneshot::Sender<()>>,
+    ) -> Result<()> {
+        // Updates are sent with the time their message was received to measure publish latency.
+        let (tx_update, mut rx_update) = mpsc::channel::<(Instant, U)>(self.config().update_channel_size);
 
-    async fn start(&self, tx_summary: mpsc::Sender<OrderBookOnlyLevels>) -> Result<()> {
-        let (tx_update, mut rx_update) = mpsc::channel::<U>(100);
+        let (exchange, symbol, scales) = (Self::EXCHANGE, self.symbol(), self.scales());
+        let published = self.config().book.clone();
+        let had_data = published.as_ref().is_some_and(|book| book.load().last_update_id != 0);
+        let unsubscribe = self.unsubscribe_messages();
+        let recorder = self.config().recorder.clone();
+        let trades = self.config().trades.clone();
+        let parse_trade: fn(&[u8], Symbol, Scales, SystemTime) -> Result<Trade> = Self::parse_trade;
+        let is_subscription_reply: fn(&[u8]) -> bool = Self::is_subscription_reply;
+        let exchange_label = exchange.to_string();
+        let update_channel = format!("{}_updates", exchange).to_lowercase();
+
+        if let Some(recorder) = &recorder {
+            recorder.record_scales(exchange, symbol, scales);
+        }
 
         let mut websocket_stream = self.get_websocket_stream().await?;
         thread::sleep(Duration::from_millis(1000));
         let snapshot = self.get_snapshot().await?;
+        if had_data {
+            // Levels of the previous run may be gone by now, the book is rebuilt from the snapshot.
+            metrics::RESYNCS.with_label_values(&[&exchange_label]).inc();
+        }
         let snapshot_update = U::from(snapshot);
 
-        let fetcher: JoinHandle<std::result::Result<(), anyhow::Error>> =
-            tokio::spawn(async move {
+        let mut fetcher: AbortOnDrop<Result<()>> =
+            AbortOnDrop(tokio::spawn(async move {
                 tx_update
-                    .send(snapshot_update)
+                    .send((Instant::now(), snapshot_update))
                     .await
                     .context("failed to send snapshot")?;
                 
-                while let Some(response) = websocket_stream.next().await {
+                loop {
+                    let response = tokio::select! {
+                        response = websocket_stream.next() => response,
+                        _ = shutdown.cancelled() => {
+                            for message in unsubscribe {
+                                websocket_stream.send(message).await.context("failed to unsubscribe")?;
+                            }
+                            websocket_stream.close(None).await.context("failed to close websocket")?;
+                            tracing::info!("closed {} websocket", exchange_label);
+                            break;
+                        }
+                    };
+                    let Some(response) = response else {
+                        break;
+                    };
                     match response {
-                        Ok(message) => match U::try_from(message) {
-                            Ok(mut update) => {
-                                tracing::debug!(
-                                    "sending update with {} bids and {} asks",
-                                    update.bids_mut().len(),
-                                    update.asks_mut().len()
-                                );
-                                tx_update
-                                    .send(update)
-                                    .await
-                                    .context("failed to send update")?;
+                        Ok(message) => {
+                            let received_at = Instant::now();
+                            if let Some(recorder) = &recorder {
+                                recorder.record_message(exchange, symbol, &message);
                             }
-                            Err(_) => {
-                                tracing::error!("failed to get update from message");
+                            metrics::MESSAGES_RECEIVED.with_label_values(&[&exchange_label]).inc();
+                            match parse_message::<U>(&message, scales) {
+                                Ok(update) => {
+                                    tracing::debug!(
+                                        "sending update with {} bids and {} asks",
+                                        update.bids().len(),
+                                        update.asks().len()
+                                    );
+                                    tx_update
+                                        .send((received_at, update))
+                                        .await
+                                        .context("failed to send update")?;
+                                    metrics::record_backlog(&update_channel, &tx_update);
+                                }
+                                Err(_) if message_bytes(&message).is_some_and(is_subscription_reply) => {
+                                    tracing::debug!("{} subscription reply: {:?}", exchange_label, message);
+                                }
+                                Err(err) => {
+                                    // Messages that are not updates may be trades.
+                                    let trade = trades.as_ref().zip(message_bytes(&message)).and_then(|(tape, bytes)| {
+                                        let trade = parse_trade(bytes, symbol, scales, SystemTime::now()).ok()?;
+                                        Some((tape, trade))
+                                    });
+                                    if let Some((tape, trade)) = trade {
+                                        metrics::TRADES_RECEIVED.with_label_values(&[&exchange_label]).inc();
+                                        tape.publish(trade);
+                                        continue;
+                                    }
+                                    metrics::PARSE_FAILURES.with_label_values(&[&exchange_label]).inc();
+                                    tracing::error!("failed to get update from message: {:#}", err);
+                                    tracing::debug!("unparsed message: {:?}", message);
+                                }
                             }
-                        },
+                        }
                         Err(e) => {
                             tracing::error!("failed to get message, {:?}", e);
                             continue;
@@ -107,31 +245,53 @@ pub trait Exchange<
                     }
                 }
                 Ok(())
-            });
-        let orderbook = self.orderbook();
-        while let Some(mut update) = rx_update.recv().await {
-            let mut ob = orderbook.lock().await;
-            let exchange = ob.exchange;
-            let symbol = ob.symbol;
+            }));
+        let mut ob = OrderBook::new_orderbook(
+            exchange,
+            symbol,
+            scales.price_scale,
+            scales.quantity_scale,
+            self.config().depth,
+        );
+        let exchange_label = exchange.to_string();
+        let best_bid_gauge = metrics::BEST_BID.with_label_values(&[&exchange_label, &symbol.to_string()]);
+        let best_ask_gauge = metrics::BEST_ASK.with_label_values(&[&exchange_label, &symbol.to_string()]);
+        while let Some((received_at, update)) = rx_update.recv().await {
             tracing::debug!(
                 "updating: {} {} {}",
                 exchange,
                 symbol,
                 update.last_update_id()
             );
-            if let Err(err) = ob.update(&mut update) {
-                tracing::error!(
-                    "failed to update orderbook: {} {} {}",
-                    exchange,
-                    symbol,
-                    err
-                );
-            } else if let Some(book_levels) = ob.get_book_levels() {
-                println!("{} send", exchange);
-                tx_summary.send(book_levels.clone()).await.unwrap();
+            if let Err(err) = ob.update(&update) {
+                // The book can not be trusted after a failed update. Returning lets the caller
+                // restart the connector, which resyncs the book from a new snapshot.
+                metrics::VALIDATION_FAILURES.with_label_values(&[&exchange_label]).inc();
+                return Err(err).context(format!("failed to update orderbook: {} {}", exchange, symbol));
+            }
+            // The snapshot is the first update.
+            if let Some(ready) = ready.take() {
+                let _ = ready.send(());
+            }
+            if let Some(published) = &published {
+                published.publish(&ob);
+            }
+            if let Some(mut book_levels) = ob.get_book_levels() {
+                if let Some(best_bid) = book_levels.bids.first() {
+                    best_bid_gauge.set(best_bid.price);
+                }
+                if let Some(best_ask) = book_levels.asks.first() {
+                    best_ask_gauge.set(best_ask.price);
+                }
+                book_levels.received_at = Some(received_at);
+                tx_summary
+                    .send(book_levels)
+                    .await
+                    .context("failed to send book levels")?;
+                metrics::record_backlog("orderbook_levels", &tx_summary);
             }
         }
-        let _ = fetcher.await?;
-        Ok(())
+        // The updates end once the reader returned, with the errors of unsubscribing and closing.
+        (&mut fetcher.0).await.context("websocket reader panicked")?
     }
 }
@@ -1,3 +1,4 @@
 pub mod binance;
 pub mod bitstamp;
-pub mod exchange;
\ No newline at end of file
+pub mod exchange;
+pub(crate) mod parse;
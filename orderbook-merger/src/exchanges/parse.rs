@@ -0,0 +1,149 @@
+use std::fmt;
+use anyhow::Result;
+use serde::{
+    de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserialize,
+};
+use serde_aux::field_attributes::deserialize_number_from_string;
+use crate::{orderbook::orderbook::{Scales, StorageLevel}, str_to_storage};
+
+// Books are parsed with `DeserializeSeed`s carrying the scales of the book, so the levels of a
+// message go straight from its bytes to storage amounts. Prices and quantities are borrowed from
+// the message, and no `Decimal` or intermediate map is built.
+
+// The `parse` function deserializes `bytes` with `seed`, failing on trailing characters.
+pub(crate) fn parse<'de, S: DeserializeSeed<'de>>(bytes: &'de [u8], seed: S) -> Result<S::Value> {
+    let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+    let value = seed.deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+// The `Levels` seed reads `[["price", "quantity"], ...]` levels.
+#[derive(Debug, Clone, Copy)]
+struct Levels(Scales);
+
+impl<'de> DeserializeSeed<'de> for Levels {
+    type Value = Vec<StorageLevel>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Levels {
+    type Value = Vec<StorageLevel>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of [price, quantity] levels")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut levels = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+        while let Some([price, quantity]) = seq.next_element::<[&'de str; 2]>()? {
+            let price = str_to_storage(price, self.0.price_scale).map_err(de::Error::custom)?;
+            let quantity = str_to_storage(quantity, self.0.quantity_scale).map_err(de::Error::custom)?;
+            levels.push((price, quantity));
+        }
+        Ok(levels)
+    }
+}
+
+// Update ids are numbers on Binance and numbers in strings on Bitstamp.
+#[derive(Deserialize)]
+struct UpdateId(#[serde(deserialize_with = "deserialize_number_from_string")] u64);
+
+// The `Book` struct holds the fields snapshots and diffs of every exchange are made of.
+#[derive(Debug, Default)]
+pub(crate) struct Book {
+    pub first_update_id: Option<u64>,
+    pub last_update_id: u64,
+    pub bids: Vec<StorageLevel>,
+    pub asks: Vec<StorageLevel>,
+}
+
+// The `BookSeed` reads a `Book` from an object with `bids` and `asks`, or `b` and `a`, and the
+// update ids under the given names. Other fields are skipped.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BookSeed {
+    pub scales: Scales,
+    pub first_update_id: &'static [&'static str],
+    pub last_update_id: &'static [&'static str],
+}
+
+impl<'de> DeserializeSeed<'de> for BookSeed {
+    type Value = Book;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for BookSeed {
+    type Value = Book;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an orderbook")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let (mut first_update_id, mut last_update_id, mut bids, mut asks) = (None, None, None, None);
+        while let Some(key) = map.next_key::<&'de str>()? {
+            match key {
+                "bids" | "b" => bids = Some(map.next_value_seed(Levels(self.scales))?),
+                "asks" | "a" => asks = Some(map.next_value_seed(Levels(self.scales))?),
+                key if self.first_update_id.contains(&key) => {
+                    first_update_id = Some(map.next_value::<UpdateId>()?.0)
+                }
+                key if self.last_update_id.contains(&key) => {
+                    last_update_id = Some(map.next_value::<UpdateId>()?.0)
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(Book {
+            first_update_id,
+            last_update_id: last_update_id.ok_or_else(|| de::Error::missing_field(self.last_update_id[0]))?,
+            bids: bids.ok_or_else(|| de::Error::missing_field("bids"))?,
+            asks: asks.ok_or_else(|| de::Error::missing_field("asks"))?,
+        })
+    }
+}
+
+// The `Field` seed reads one field of an object with its seed, skipping the others.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Field<S> {
+    pub name: &'static str,
+    pub seed: S,
+}
+
+impl<'de, S: DeserializeSeed<'de> + Copy> DeserializeSeed<'de> for Field<S> {
+    type Value = S::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, S: DeserializeSeed<'de> + Copy> Visitor<'de> for Field<S> {
+    type Value = S::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "an object with a `{}` field", self.name)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut value = None;
+        while let Some(key) = map.next_key::<&'de str>()? {
+            if key == self.name && value.is_none() {
+                value = Some(map.next_value_seed(self.seed)?);
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        value.ok_or_else(|| de::Error::missing_field(self.name))
+    }
+}
+
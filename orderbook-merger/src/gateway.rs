@@ -0,0 +1,183 @@
+use anyhow::Result;
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    response::{sse::{Event, KeepAlive, Sse}, IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use rustls::ServerConfig;
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+use tonic::{Code, Status};
+use tokio_util::sync::CancellationToken;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use crate::{
+    auth::AuthInterceptor,
+    orderbook_summary::SummaryRequest,
+    service::OrderbookSummary,
+    subscription::{parse_conflation, SubscriptionOptions},
+};
+
+// The `cors_layer` function allows browsers on the `origins` to call both the gRPC-Web service and
+// the JSON endpoints, including with an `authorization` header. Pages on any other origin can not
+// read the responses.
+pub fn cors_layer(origins: Vec<HeaderValue>) -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            HeaderName::from_static("x-grpc-web"),
+            HeaderName::from_static("x-user-agent"),
+            HeaderName::from_static("grpc-timeout"),
+        ])
+        .expose_headers([
+            HeaderName::from_static("grpc-status"),
+            HeaderName::from_static("grpc-message"),
+            HeaderName::from_static("grpc-status-details-bin"),
+        ])
+}
+
+// The `SummaryQuery` struct mirrors `SummaryRequest` as query parameters, with exchanges given as a
+// comma separated list, e.g. `/summary?include_exchanges=BINANCE&depth=5`.
+#[derive(Debug, Default, Deserialize)]
+pub struct SummaryQuery {
+    pub symbol: Option<String>,
+    pub include_exchanges: Option<String>,
+    pub exclude_exchanges: Option<String>,
+    pub depth: Option<u32>,
+    pub max_updates_per_second: Option<u32>,
+    pub conflation: Option<String>,
+    pub top_n: Option<u32>,
+}
+
+impl TryFrom<SummaryQuery> for SummaryRequest {
+    type Error = Status;
+
+    fn try_from(query: SummaryQuery) -> Result<Self, Status> {
+        fn split(list: Option<String>) -> Vec<String> {
+            list.map(|list| list.split(',').map(|item| item.trim().to_string()).collect())
+                .unwrap_or_default()
+        }
+        let conflation = parse_conflation(query.conflation.as_deref())?;
+        Ok(SummaryRequest {
+            max_updates_per_second: query.max_updates_per_second.unwrap_or_default(),
+            conflation: conflation as i32,
+            top_n: query.top_n.unwrap_or_default(),
+            include_exchanges: split(query.include_exchanges),
+            exclude_exchanges: split(query.exclude_exchanges),
+            symbol: query.symbol.unwrap_or_default(),
+            depth: query.depth.unwrap_or_default(),
+        })
+    }
+}
+
+// The `StatusError` wrapper turns a gRPC status into the matching HTTP response.
+pub struct StatusError(pub Status);
+
+impl IntoResponse for StatusError {
+    fn into_response(self) -> Response {
+        let code = match self.0.code() {
+            Code::InvalidArgument => StatusCode::BAD_REQUEST,
+            Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+            Code::PermissionDenied => StatusCode::FORBIDDEN,
+            Code::NotFound => StatusCode::NOT_FOUND,
+            Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (code, self.0.message().to_string()).into_response()
+    }
+}
+
+impl From<Status> for StatusError {
+    fn from(status: Status) -> Self {
+        Self(status)
+    }
+}
+
+#[derive(Clone)]
+struct GatewayState {
+    service: OrderbookSummary,
+    auth: AuthInterceptor,
+}
+
+impl GatewayState {
+    fn subscription_options(&self, headers: &HeaderMap, query: SummaryQuery) -> Result<SubscriptionOptions, Status> {
+        let authorization = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        let permissions = self.auth.permissions(authorization)?;
+        let request = SummaryRequest::try_from(query)?;
+        self.service.subscription_options(&request, permissions.as_ref())
+    }
+}
+
+async fn latest_summary(
+    State(state): State<GatewayState>,
+    headers: HeaderMap,
+    Query(query): Query<SummaryQuery>,
+) -> Result<impl IntoResponse, StatusError> {
+    let options = state.subscription_options(&headers, query)?;
+    Ok(Json(state.service.latest_summary(&options)?))
+}
+
+async fn summary_stream(
+    State(state): State<GatewayState>,
+    headers: HeaderMap,
+    Query(query): Query<SummaryQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusError> {
+    let options = state.subscription_options(&headers, query)?;
+    let events = state.service.subscribe(options)?.map(|summary| {
+        let event = match summary {
+            Ok(summary) => Event::default()
+                .event("summary")
+                .json_data(summary)
+                .unwrap_or_else(|e| Event::default().event("error").data(e.to_string())),
+            Err(status) => Event::default().event("error").data(status.message()),
+        };
+        Ok(event)
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+// The `router` function builds the JSON gateway:
+// - `GET /summary` returns the latest `Summary`,
+// - `GET /summary/stream` streams summaries as server-sent events named `summary`.
+// Both accept the `SummaryQuery` parameters and the same bearer tokens as the gRPC service.
+pub fn router(service: OrderbookSummary, auth: AuthInterceptor, cors_origins: Vec<HeaderValue>) -> Router {
+    Router::new()
+        .route("/summary", get(latest_summary))
+        .route("/summary/stream", get(summary_stream))
+        .layer(cors_layer(cors_origins))
+        .with_state(GatewayState { service, auth })
+}
+
+// The `serve` function serves `router`, over TLS when `tls` is given, until `shutdown` is
+// cancelled, then waits for open requests and streams to finish.
+pub async fn serve(
+    address: SocketAddr,
+    router: Router,
+    tls: Option<Arc<ServerConfig>>,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let handle = axum_server::Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            shutdown.cancelled().await;
+            handle.graceful_shutdown(None);
+        }
+    });
+    let service = router.into_make_service();
+    match tls {
+        Some(tls) => {
+            let config = axum_server::tls_rustls::RustlsConfig::from_config(tls);
+            axum_server::bind_rustls(address, config).handle(handle).serve(service).await?
+        }
+        None => axum_server::bind(address).handle(handle).serve(service).await?,
+    }
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use std::time::Duration;
+use tokio::time::Instant;
+use tonic_health::{server::HealthReporter, ServingStatus};
+use crate::{pipeline::Pipelines, AggregatedBooks, ExchangeName};
+
+// The `is_serving` function returns true when every configured exchange has delivered a book and
+// none of them has been silent for longer than `stale_after`.
+pub fn is_serving(
+    books: &AggregatedBooks,
+    exchanges: &[ExchangeName],
+    stale_after: Duration,
+    now: Instant,
+) -> bool {
+    exchanges.iter().all(|exchange| {
+        books
+            .last_updated
+            .get(exchange)
+            .is_some_and(|updated| now.duration_since(*updated) <= stale_after)
+    })
+}
+
+// The `report_health` function periodically checks the books of every pipeline and reports the serving
+// status of `service_name` and of the server as a whole (the empty service name) to the
+// `grpc.health.v1` service. Both start as NOT_SERVING.
+pub async fn report_health(
+    mut reporter: HealthReporter,
+    service_name: &'static str,
+    pipelines: Pipelines,
+) {
+    let mut last_status = ServingStatus::NotServing;
+    reporter.set_service_status(service_name, last_status).await;
+    reporter.set_service_status("", last_status).await;
+
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let status = if pipelines.is_serving(Instant::now()) {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        };
+        if status != last_status {
+            tracing::info!("health status changed to {:?}", status);
+            reporter.set_service_status(service_name, status).await;
+            reporter.set_service_status("", status).await;
+            last_status = status;
+        }
+    }
+}
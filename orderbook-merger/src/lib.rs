@@ -1,14 +1,40 @@
+// `tonic::Status` is the error type of every gRPC facing function.
+#![allow(clippy::result_large_err)]
+
+pub mod admin;
+pub mod auth;
+pub mod candles;
+pub mod drain;
 pub mod exchanges;
+pub mod gateway;
+pub mod health;
+pub mod metrics;
 pub mod orderbook;
+pub mod pipeline;
+pub mod recorder;
+pub mod reload;
+pub mod replay;
+pub mod service;
+pub mod settings;
+pub mod subscription;
+pub mod supervisor;
+pub mod tls;
+pub mod trades;
+pub mod websocket;
 
 use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::{Arc, OnceLock}};
+use tokio::time::Instant;
 use crate::orderbook::orderbook::OrderBookOnlyLevels;
-use orderbook_summary::{Level, Summary};
-use anyhow::{ensure, Result};
+use orderbook_summary::{BestBidOffer, Level, Summary, VenueBbo};
+use anyhow::{bail, ensure, Context, Result};
 use rust_decimal::Decimal;
 
 pub mod orderbook_summary {
     tonic::include_proto!("orderbook_summary");
+
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("orderbook_summary_descriptor");
 }
 
 pub type DisplayAmount = Decimal;
@@ -43,19 +69,64 @@ pub fn display_to_storage(mut display_quantity: Decimal, scale: u32) -> Result<S
 
     // Returns a new Decimal number with the specific decimal points for fractional portion.
     display_quantity = display_quantity.round_dp(scale);
-    display_quantity.set_scale(0)?;
 
-    let unpacked = display_quantity.unpack();
-    ensure!(unpacked.hi == 0, "quantity is too large");
+    // Rounding never adds decimals, so amounts given with fewer decimals than `scale`, e.g. `1.5`
+    // for a scale of 8, are scaled up to `scale` decimals.
+    let factor = 10u64
+        .checked_pow(scale - display_quantity.scale())
+        .context("scale is too large")?;
+    u64::try_from(display_quantity.mantissa())
+        .ok()
+        .and_then(|mantissa| mantissa.checked_mul(factor))
+        .context("quantity is too large")
+}
+
+// The `str_to_storage` function parses an amount as exchanges send it, e.g. `"2000.01000000"`,
+// straight into a storage amount without going through a `Decimal`. Decimals beyond `scale` are
+// rounded half to even, like `display_to_storage` does.
+pub fn str_to_storage(display_amount: &str, scale: u32) -> Result<StorageAmount> {
+    let (integer, fraction) = display_amount.split_once('.').unwrap_or((display_amount, ""));
+    let kept = fraction.len().min(scale as usize);
+    let (fraction, dropped) = fraction.as_bytes().split_at(kept);
+    let invalid = || anyhow::anyhow!("invalid amount: {display_amount:?}");
+    if integer.is_empty() && fraction.is_empty() {
+        return Err(invalid());
+    }
 
-    let mut storage = unpacked.lo as u64;
-    if unpacked.mid > 0 {
-        storage += (unpacked.mid as u64) << 32;
+    let mut storage_amount = 0u64;
+    let mut overflow = false;
+    for &digit in integer.as_bytes().iter().chain(fraction) {
+        let digit = digit.wrapping_sub(b'0');
+        if digit > 9 {
+            return Err(invalid());
+        }
+        let (shifted, shift_overflow) = storage_amount.overflowing_mul(10);
+        let (added, add_overflow) = shifted.overflowing_add(u64::from(digit));
+        storage_amount = added;
+        overflow |= shift_overflow | add_overflow;
+    }
+    if !dropped.iter().all(u8::is_ascii_digit) {
+        return Err(invalid());
+    }
+    // Amounts given with fewer decimals than `scale` are scaled up, like in `display_to_storage`.
+    let mut storage_amount = 10u64
+        .checked_pow(scale - kept as u32)
+        .and_then(|factor| storage_amount.checked_mul(factor))
+        .filter(|_| !overflow);
+    let round_up = match dropped.split_first() {
+        Some((b'6'..=b'9', _)) => true,
+        Some((b'5', rest)) => {
+            rest.iter().any(|&digit| digit != b'0') || storage_amount.is_some_and(|amount| amount % 2 == 1)
+        }
+        _ => false,
+    };
+    if round_up {
+        storage_amount = storage_amount.and_then(|amount| amount.checked_add(1));
     }
-    Ok(storage)
+    storage_amount.context("quantity is too large")
 }
 
-#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Hash, Eq)]
 pub enum Symbol {
     #[default]
     BTCUSDT,
@@ -71,6 +142,18 @@ impl std::fmt::Display for Symbol {
     }
 }
 
+impl std::str::FromStr for Symbol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_uppercase().as_str() {
+            "BTCUSDT" => Ok(Symbol::BTCUSDT),
+            "ETHUSDT" => Ok(Symbol::ETHUSDT),
+            _ => bail!("unknown symbol: {s}"),
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Hash, Eq)]
 pub enum ExchangeName {
     #[default]
@@ -87,15 +170,109 @@ impl std::fmt::Display for ExchangeName {
     }
 }
 
+impl std::str::FromStr for ExchangeName {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_uppercase().as_str() {
+            "BINANCE" => Ok(ExchangeName::BINANCE),
+            "BITSTAMP" => Ok(ExchangeName::BITSTAMP),
+            _ => bail!("unknown exchange: {s}"),
+        }
+    }
+}
+
+// The `AggregatedBooks` struct is what the aggregator publishes on every update: the latest book
+// levels of each exchange, when each of them was last received, and the summary merged from all
+// of them.
+#[derive(Debug, Default, Clone)]
+pub struct AggregatedBooks {
+    pub books: HashMap<ExchangeName, OrderBookOnlyLevels>,
+    pub last_updated: HashMap<ExchangeName, Instant>,
+    // Merged on first use, so updates nobody reads the full summary of are not merged, and shared
+    // by every subscriber of these books.
+    summary: Arc<OnceLock<Summary>>,
+}
+
+impl AggregatedBooks {
+    pub fn new(books: HashMap<ExchangeName, OrderBookOnlyLevels>, last_updated: HashMap<ExchangeName, Instant>) -> Self {
+        Self { books, last_updated, summary: Arc::default() }
+    }
+
+    // The `summary` function returns the summary merged from every book.
+    pub fn summary(&self) -> &Summary {
+        self.summary.get_or_init(|| make_summary(self.books.values().cloned().collect()))
+    }
+
+    // The `summary_for` function merges only the books of the exchanges accepted by `filter`,
+    // reusing the summary merged from every book when every exchange is accepted.
+    pub fn summary_for<F: Fn(&ExchangeName) -> bool>(&self, filter: F) -> Summary {
+        if self.books.keys().all(&filter) {
+            return self.summary().clone();
+        }
+        let book_levels_vec = self
+            .books
+            .iter()
+            .filter(|(exchange, _)| filter(exchange))
+            .map(|(_, book_levels)| book_levels.clone())
+            .collect();
+        make_summary(book_levels_vec)
+    }
+
+    // The `best_bid_offer_for` function takes the top of the books of the exchanges accepted by
+    // `filter` without merging them. Ties between exchanges go to the larger quantity, like in the
+    // merged book.
+    pub fn best_bid_offer_for<F: Fn(&ExchangeName) -> bool>(&self, filter: F) -> BestBidOffer {
+        let mut venues: Vec<VenueBbo> = self
+            .books
+            .iter()
+            .filter(|(exchange, _)| filter(exchange))
+            .map(|(exchange, book_levels)| VenueBbo {
+                exchange: exchange.to_string(),
+                bid: book_levels.bids.first().cloned(),
+                ask: book_levels.asks.first().cloned(),
+            })
+            .collect();
+        venues.sort_by(|a, b| a.exchange.cmp(&b.exchange));
+
+        let best_bid = venues
+            .iter()
+            .filter_map(|venue| venue.bid.as_ref())
+            .max_by(|a, b| a.price.total_cmp(&b.price).then(a.quantity.total_cmp(&b.quantity)))
+            .cloned();
+        let best_ask = venues
+            .iter()
+            .filter_map(|venue| venue.ask.as_ref())
+            .min_by(|a, b| a.price.total_cmp(&b.price).then(b.quantity.total_cmp(&a.quantity)))
+            .cloned();
+        let spread = match (&best_ask, &best_bid) {
+            (Some(best_ask), Some(best_bid)) => best_ask.price - best_bid.price,
+            _ => 0.0,
+        };
+
+        BestBidOffer {
+            best_bid,
+            best_ask,
+            spread,
+            venues,
+        }
+    }
+}
+
 pub fn make_summary(mut book_levels_vec: Vec<OrderBookOnlyLevels>) -> Summary {
-    let levels_count = book_levels_vec[0].bids.len();
-    let exchange_count = book_levels_vec.len();
+    // Every book holds up to the same number of levels, the depth, which also limits the merged
+    // book. The deepest side of any book is used, so a thin book does not truncate the others.
+    let levels_count = book_levels_vec
+        .iter()
+        .map(|book_levels| book_levels.bids.len().max(book_levels.asks.len()))
+        .max()
+        .unwrap_or_default();
 
     let mut bids = Vec::<Level>::with_capacity(levels_count);
     let mut asks = Vec::<Level>::with_capacity(levels_count);
-    for i in 0..exchange_count {
-        bids.append(&mut book_levels_vec[i].bids);
-        asks.append(&mut book_levels_vec[i].asks);
+    for book_levels in book_levels_vec.iter_mut() {
+        bids.append(&mut book_levels.bids);
+        asks.append(&mut book_levels.asks);
     }
 
     // Sort bids: descending by price, then descending by quantity for equal prices
@@ -120,8 +297,13 @@ pub fn make_summary(mut book_levels_vec: Vec<OrderBookOnlyLevels>) -> Summary {
     let take_bids = bids.into_iter().take(levels_count).collect::<Vec<Level>>();
     let take_asks = asks.into_iter().take(levels_count).collect::<Vec<Level>>();
 
+    let spread = match (take_asks.first(), take_bids.first()) {
+        (Some(best_ask), Some(best_bid)) => best_ask.price - best_bid.price,
+        _ => 0.0,
+    };
+
     Summary {
-        spread: take_asks[0].price - take_bids[0].price,
+        spread,
         bids: take_bids,
         asks: take_asks,
     }
@@ -0,0 +1,135 @@
+use anyhow::Result;
+use axum::{http::header, response::IntoResponse, routing::get, Router};
+use prometheus::{
+    register_gauge_vec, register_histogram, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, Encoder, GaugeVec, Histogram, IntCounter, IntCounterVec,
+    IntGauge, IntGaugeVec, TextEncoder,
+};
+use std::{net::SocketAddr, sync::LazyLock};
+use tokio::sync::mpsc;
+
+// Metrics are registered in the default prometheus registry the first time they are used.
+
+pub static MESSAGES_RECEIVED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "orderbook_messages_received_total",
+        "Websocket messages received from each exchange",
+        &["exchange"]
+    )
+    .unwrap()
+});
+
+pub static PARSE_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "orderbook_parse_failures_total",
+        "Websocket messages from each exchange that could not be parsed into an update or a trade",
+        &["exchange"]
+    )
+    .unwrap()
+});
+
+pub static TRADES_RECEIVED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "orderbook_trades_received_total",
+        "Trades received from each exchange",
+        &["exchange"]
+    )
+    .unwrap()
+});
+
+pub static VALIDATION_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "orderbook_validation_failures_total",
+        "Updates from each exchange rejected by the orderbook, e.g. because of a sequence gap",
+        &["exchange"]
+    )
+    .unwrap()
+});
+
+pub static RESYNCS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "orderbook_resyncs_total",
+        "Snapshots fetched to resynchronise an orderbook that already had data",
+        &["exchange"]
+    )
+    .unwrap()
+});
+
+pub static UPDATE_TO_PUBLISH_SECONDS: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "orderbook_update_to_publish_seconds",
+        "Time from receiving an exchange message to publishing the merged summary",
+        vec![0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25]
+    )
+    .unwrap()
+});
+
+pub static SPREAD: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!("orderbook_spread", "Spread of the merged book of each symbol", &["symbol"]).unwrap()
+});
+
+pub static BEST_BID: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!("orderbook_best_bid", "Best bid price of each exchange and symbol", &["exchange", "symbol"]).unwrap()
+});
+
+pub static BEST_ASK: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!("orderbook_best_ask", "Best ask price of each exchange and symbol", &["exchange", "symbol"]).unwrap()
+});
+
+pub static CONNECTOR_UP: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "orderbook_connector_up",
+        "Whether the supervised connector of each exchange and symbol is running",
+        &["exchange", "symbol"]
+    )
+    .unwrap()
+});
+
+pub static SUBSCRIBERS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "orderbook_subscribers",
+        "Connected summary subscribers over gRPC, server-sent events and websockets"
+    )
+    .unwrap()
+});
+
+pub static CHANNEL_BACKLOG: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "orderbook_channel_backlog",
+        "Messages waiting in each internal mpsc channel",
+        &["channel"]
+    )
+    .unwrap()
+});
+
+pub static RECORDS_DROPPED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "orderbook_recorder_dropped_total",
+        "Raw messages not recorded because the recorder fell behind"
+    )
+    .unwrap()
+});
+
+// The `record_backlog` function samples how many messages are queued in `sender`'s channel.
+pub fn record_backlog<T>(channel: &str, sender: &mpsc::Sender<T>) {
+    let backlog = sender.max_capacity() - sender.capacity();
+    CHANNEL_BACKLOG.with_label_values(&[channel]).set(backlog as i64);
+}
+
+async fn metrics() -> impl IntoResponse {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(err) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        tracing::error!("failed to encode metrics: {:?}", err);
+    }
+    ([(header::CONTENT_TYPE, encoder.format_type().to_string())], buffer)
+}
+
+// The `serve` function exposes the metrics for prometheus at `GET /metrics`.
+pub async fn serve(address: SocketAddr) -> Result<()> {
+    let router = Router::new().route("/metrics", get(metrics));
+    axum::Server::bind(&address)
+        .serve(router.into_make_service())
+        .await?;
+    Ok(())
+}
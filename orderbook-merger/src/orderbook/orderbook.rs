@@ -1,8 +1,11 @@
 use crate::{*, orderbook_summary::Level};
 use anyhow::Result;
-use std::collections::BTreeMap;
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, sync::Arc};
 use rust_decimal::Decimal;
 use rust_decimal::prelude::ToPrimitive;
+use tokio::time::Instant;
 
 // The `OrderBookOnlyLevels` struct represents an order book with bids and asks for a specific symbol
 // on an exchange.
@@ -13,15 +16,118 @@ pub struct OrderBookOnlyLevels {
     pub bids: Vec<Level>,
     pub asks: Vec<Level>,
     pub last_update_id: u64,
+    // When the exchange message that produced these levels was received.
+    pub received_at: Option<Instant>,
+}
+
+// A `StorageLevel` is the price and quantity of one level in storage amounts.
+pub type StorageLevel = (StorageAmount, StorageAmount);
+
+// The `Scales` struct holds the number of decimals of the prices and quantities of a book.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Scales {
+    pub price_scale: u32,
+    pub quantity_scale: u32,
+}
+
+// The `storage_to_display` function takes in storage_level and converts them to
+// a displayable format by rounding and scaling the price and quantity.
+fn storage_to_display(exchange: ExchangeName, scales: Scales, (price, quantity): StorageLevel) -> Result<Level> {
+    // `to_display` fails for scales above 28, so the factor can not overflow.
+    let display = |amount: StorageAmount, scale: u32| -> Result<f64> {
+        let display_amount = amount.to_display(scale)?.to_f64().unwrap_or_default();
+        let factor = 10f64.powi(scale as i32);
+        Ok((display_amount * factor).round() / factor)
+    };
+    let level = Level {
+        exchange: exchange.to_string(),
+        price: display(price, scales.price_scale)?,
+        quantity: display(quantity, scales.quantity_scale)?,
+    };
+
+    Ok(level)
+}
+
+// The `BookSnapshot` struct is an immutable copy of a full book, with the best bid and ask first.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BookSnapshot {
+    pub exchange: ExchangeName,
+    pub symbol: Symbol,
+    pub scales: Scales,
+    pub bids: Vec<StorageLevel>,
+    pub asks: Vec<StorageLevel>,
+    pub last_update_id: u64,
+}
+
+impl BookSnapshot {
+    // The `display_levels` function returns up to `depth` levels of each side, best first, in
+    // display amounts. A depth of 0 returns every level.
+    pub fn display_levels(&self, depth: usize) -> Result<(Vec<Level>, Vec<Level>)> {
+        let depth = if depth == 0 { usize::MAX } else { depth };
+        let side = |levels: &[StorageLevel]| -> Result<Vec<Level>> {
+            levels
+                .iter()
+                .take(depth)
+                .map(|&level| storage_to_display(self.exchange, self.scales, level))
+                .collect()
+        };
+        Ok((side(&self.bids)?, side(&self.asks)?))
+    }
+}
+
+impl From<&OrderBook> for BookSnapshot {
+    fn from(orderbook: &OrderBook) -> Self {
+        Self {
+            exchange: orderbook.exchange,
+            symbol: orderbook.symbol,
+            scales: orderbook.scales(),
+            bids: orderbook.bids.iter().rev().map(|(&price, &quantity)| (price, quantity)).collect(),
+            asks: orderbook.asks.iter().map(|(&price, &quantity)| (price, quantity)).collect(),
+            last_update_id: orderbook.last_update_id,
+        }
+    }
+}
+
+// The `PublishedBook` struct is where the task owning a book publishes it after every update.
+// Readers load the latest snapshot without locking, so they never wait for the writer nor see a
+// book halfway through an update. Clones share the same book.
+#[derive(Debug, Default, Clone)]
+pub struct PublishedBook(Arc<ArcSwap<BookSnapshot>>);
+
+impl PublishedBook {
+    pub fn publish(&self, orderbook: &OrderBook) {
+        self.0.store(Arc::new(BookSnapshot::from(orderbook)));
+    }
+
+    pub fn load(&self) -> Arc<BookSnapshot> {
+        self.0.load_full()
+    }
+}
+
+impl PartialEq for PublishedBook {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
 }
 
 // The `Update` trait defines methods that should be implemented by types that represent
 // updates to an orderbook.
 pub trait Update {
-    fn validate(&self, last_id: u64) -> Result<()>;
+    // The `parse` function parses an exchange message straight into storage amounts at `scales`.
+    fn parse(bytes: &[u8], scales: Scales) -> Result<Self>
+    where
+        Self: Sized;
+    // The `validate` function checks that the update follows the book at `last_id`, which was
+    // built from the snapshot at `snapshot_id`.
+    fn validate(&self, last_id: u64, snapshot_id: u64) -> Result<()>;
+    // Updates already contained in the book, e.g. diffs buffered before the snapshot was taken,
+    // are stale and skipped instead of failing validation.
+    fn is_stale(&self, _last_id: u64) -> bool {
+        false
+    }
     fn last_update_id(&self) -> u64;
-    fn bids_mut(&mut self) -> &mut BTreeMap<DisplayAmount, DisplayAmount>;
-    fn asks_mut(&mut self) -> &mut BTreeMap<DisplayAmount, DisplayAmount>;
+    fn bids(&self) -> &[StorageLevel];
+    fn asks(&self) -> &[StorageLevel];
 }
 
 // The `OrderBook` struct represents an orderbook for a specific exchange and symbol.
@@ -30,9 +136,13 @@ pub struct OrderBook {
     pub symbol: Symbol,
     pub price_scale: u32,
     pub quantity_scale: u32,
+    // Number of bid and ask levels returned by `get_book_levels`.
+    pub depth: usize,
     pub bids: BTreeMap<StorageAmount, StorageAmount>,
     pub asks: BTreeMap<StorageAmount, StorageAmount>,
     pub last_update_id: u64,
+    // The last update id of the snapshot the book was built from.
+    pub snapshot_update_id: u64,
 }
 
 impl OrderBook {
@@ -43,6 +153,7 @@ impl OrderBook {
         symbol: Symbol,
         price_scale: u32,
         quantity_scale: u32,
+        depth: usize,
     ) -> Self {
         let bids: BTreeMap<StorageAmount, StorageAmount> = BTreeMap::new();
         let asks: BTreeMap<StorageAmount, StorageAmount> = BTreeMap::new();
@@ -52,30 +163,23 @@ impl OrderBook {
             symbol,
             price_scale,
             quantity_scale,
+            depth,
             bids,
             asks,
-            last_update_id: u64::MIN
+            last_update_id: u64::MIN,
+            snapshot_update_id: u64::MIN,
         }
     }
 
-    // The `storage_to_display` function takes in storage_level and converts them to
-    // a displayable format by rounding and scaling the price and quantity.
-    fn storage_to_display(&self, storage_level: [StorageAmount; 2]) -> Result<Level> {
-        let price = (storage_level[0].to_display(self.price_scale)?.to_f64().unwrap()
-            * 10u32.pow(self.price_scale) as f64)
-            .round()
-            / 10u32.pow(self.price_scale) as f64;
-        let quantity = (storage_level[1].to_display(self.quantity_scale)?.to_f64().unwrap()
-            * 10u32.pow(self.quantity_scale) as f64)
-            .round()
-            / 10u32.pow(self.quantity_scale) as f64;
-        let level = Level {
-            exchange: self.exchange.to_string(),
-            price,
-            quantity,
-        };
+    pub fn scales(&self) -> Scales {
+        Scales {
+            price_scale: self.price_scale,
+            quantity_scale: self.quantity_scale,
+        }
+    }
 
-        Ok(level)
+    fn storage_to_display(&self, storage_level: [StorageAmount; 2]) -> Result<Level> {
+        storage_to_display(self.exchange, self.scales(), (storage_level[0], storage_level[1]))
     }
 
     fn bids(&self) -> &BTreeMap<StorageAmount, StorageAmount> {
@@ -94,31 +198,25 @@ impl OrderBook {
         &mut self.asks
     }
     
-    pub fn add_bid(&mut self, level: [Decimal; 2]) -> Result<()> {
-        let price = level[0].to_storage(self.price_scale)?;
-        let quantity = level[1].to_storage(self.quantity_scale)?;
-        
-        let bids = self.bids_mut();
+    fn set_level(levels: &mut BTreeMap<StorageAmount, StorageAmount>, (price, quantity): StorageLevel) {
         if quantity > 0 {
-            bids.insert(price, quantity);
+            levels.insert(price, quantity);
         } else {
-            bids.remove(&price);
+            levels.remove(&price);
         }
+    }
 
+    pub fn add_bid(&mut self, level: [Decimal; 2]) -> Result<()> {
+        let price = level[0].to_storage(self.price_scale)?;
+        let quantity = level[1].to_storage(self.quantity_scale)?;
+        Self::set_level(self.bids_mut(), (price, quantity));
         Ok(())
     }
 
     pub fn add_ask(&mut self, level: [Decimal; 2]) -> Result<()> {
         let price = level[0].to_storage(self.price_scale)?;
         let quantity = level[1].to_storage(self.quantity_scale)?;
-
-        let asks = self.asks_mut();
-        if quantity > 0 {
-            asks.insert(price, quantity);
-        } else {
-            asks.remove(&price);
-        }
-
+        Self::set_level(self.asks_mut(), (price, quantity));
         Ok(())
     }
     pub fn get_bids_levels(&self) -> Result<Vec<Level>> {
@@ -127,8 +225,8 @@ impl OrderBook {
         let summary_bids = if bids.is_empty() {
             Vec::new()
         } else {
-            let mut summary_bids = Vec::<Level>::with_capacity(10);
-            for (&price, &quantity) in bids.iter().rev().take(10) {
+            let mut summary_bids = Vec::<Level>::with_capacity(self.depth);
+            for (&price, &quantity) in bids.iter().rev().take(self.depth) {
                 let level = self.storage_to_display([price, quantity])?;
                 summary_bids.push(level);
             }
@@ -141,8 +239,8 @@ impl OrderBook {
         let summary_asks = if asks.is_empty() {
             Vec::new()
         } else {
-            let mut summary_asks = Vec::<Level>::with_capacity(10);
-            for (&price, &quantity) in asks.iter().take(10) {
+            let mut summary_asks = Vec::<Level>::with_capacity(self.depth);
+            for (&price, &quantity) in asks.iter().take(self.depth) {
                 let level = self.storage_to_display([price, quantity])?;
                 summary_asks.push(level);
             }
@@ -163,24 +261,36 @@ impl OrderBook {
                 last_update_id: self.last_update_id,
                 bids,
                 asks,
+                received_at: None,
             })
         }
     }
-    pub fn update<U: Update + std::fmt::Debug>(&mut self, update: &mut U) -> Result<()> {
-        // tracing::debug!("update {:#?}", update);
+    // The `reset` function empties the book so a new snapshot can be applied when resyncing.
+    pub fn reset(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+        self.last_update_id = u64::MIN;
+        self.snapshot_update_id = u64::MIN;
+    }
 
-        update.validate(self.last_update_id)?;
+    pub fn update<U: Update>(&mut self, update: &U) -> Result<()> {
+        if update.is_stale(self.last_update_id) {
+            tracing::debug!("skipping stale update {}", update.last_update_id());
+            return Ok(());
+        }
+        update.validate(self.last_update_id, self.snapshot_update_id)?;
 
-        for (price, quantity) in update.bids_mut().iter() {
-            // tracing::debug!("adding bid: {:?}", [*price, *quantity]);
-            self.add_bid([*price, *quantity])?;
+        for &level in update.bids() {
+            Self::set_level(self.bids_mut(), level);
+        }
+        for &level in update.asks() {
+            Self::set_level(self.asks_mut(), level);
         }
-        for (price, quantity) in update.asks_mut().iter() {
-            // tracing::debug!("adding ask: {:?}", [*price, *quantity]);
-            self.add_ask([*price, *quantity])?;
+        // The first update applied to an empty book is its snapshot.
+        if self.last_update_id == u64::MIN {
+            self.snapshot_update_id = update.last_update_id();
         }
-        tracing::debug!("Update done!");
         self.last_update_id = update.last_update_id();
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,376 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::{mpsc, watch}, time::Instant};
+use crate::{
+    candles::{build_candles, CandleBuilder, CandleHistory},
+    exchanges::{binance::Binance, bitstamp::Bitstamp, exchange::{ConnectorConfig, Exchange}},
+    health, metrics,
+    orderbook::orderbook::{OrderBookOnlyLevels, PublishedBook},
+    recorder::Recorder,
+    supervisor::{ConnectorStatus, Supervisor},
+    trades::TradeTape,
+    AggregatedBooks, ExchangeName, Symbol,
+};
+
+// The `aggregate_and_broadcast_data` function collects the book levels sent by the connectors of
+// `symbol` and publishes them to be merged by their subscribers. Levels without any bids or asks
+// are sent when a connector is stopped and remove that exchange's book. The staleness threshold is read from
+// `rx_config` so it can be changed while running.
+pub async fn aggregate_and_broadcast_data(
+    symbol: Symbol,
+    mut rx: mpsc::Receiver<OrderBookOnlyLevels>,
+    tx_books: watch::Sender<AggregatedBooks>,
+    rx_config: watch::Receiver<PipelineConfig>,
+) {
+    let mut exchange_to_orderbook = HashMap::<ExchangeName, OrderBookOnlyLevels>::new();
+    let mut last_updated = HashMap::<ExchangeName, Instant>::new();
+    let spread = metrics::SPREAD.with_label_values(&[&symbol.to_string()]);
+    while let Some(orderbook) = rx.recv().await {
+        let now = Instant::now();
+        let stale_after = rx_config.borrow().stale_after;
+        let received_at = orderbook.received_at;
+        if orderbook.bids.is_empty() && orderbook.asks.is_empty() {
+            last_updated.remove(&orderbook.exchange);
+            exchange_to_orderbook.remove(&orderbook.exchange);
+        } else {
+            last_updated.insert(orderbook.exchange, now);
+            exchange_to_orderbook.insert(orderbook.exchange, orderbook);
+        }
+
+        // Books of exchanges whose connector is down are left out, so the summary keeps being
+        // published from the remaining exchanges in degraded mode.
+        last_updated.retain(|_, updated| now.duration_since(*updated) <= stale_after);
+        exchange_to_orderbook.retain(|exchange, _| last_updated.contains_key(exchange));
+
+        // The books are published as they are, the merged summary is only built once a subscriber
+        // reads it. The spread only needs the top of each book.
+        let books = AggregatedBooks::new(exchange_to_orderbook.clone(), last_updated.clone());
+        spread.set(books.best_bid_offer_for(|_| true).spread);
+        tx_books.send_replace(books);
+        if let Some(received_at) = received_at {
+            metrics::UPDATE_TO_PUBLISH_SECONDS.observe(received_at.elapsed().as_secs_f64());
+        }
+    }
+}
+
+// A `Pipeline` is the aggregation of the books of every connector of one symbol. Pipelines are kept
+// once created, so subscribers stay connected while its connectors are stopped and started.
+#[derive(Debug)]
+struct Pipeline {
+    tx_orderbook: mpsc::Sender<OrderBookOnlyLevels>,
+    rx_books: watch::Receiver<AggregatedBooks>,
+    exchanges: Vec<ExchangeName>,
+    // The full book of each exchange, as last published by its connector.
+    books: HashMap<ExchangeName, PublishedBook>,
+    trades: TradeTape,
+    candles: CandleHistory,
+}
+
+impl Pipeline {
+    // The `with_outputs` function makes a connector publish to the book of `exchange` and the
+    // trades of the pipeline, which are kept across restarts of the connector so readers keep
+    // following them.
+    fn with_outputs(&mut self, exchange: ExchangeName, mut config: ConnectorConfig) -> ConnectorConfig {
+        config.book = Some(self.books.entry(exchange).or_default().clone());
+        config.trades = Some(self.trades.clone());
+        config
+    }
+}
+
+// The `PipelineInfo` struct describes a pipeline and the state of its connectors.
+#[derive(Debug, Clone)]
+pub struct PipelineInfo {
+    pub symbol: Symbol,
+    pub connectors: Vec<ConnectorStatus>,
+}
+
+// The `PipelineConfig` struct holds the tuning of the pipelines and the configuration of every
+// exchange a connector can be started for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipelineConfig {
+    // Exchanges that have not sent a book for this long are left out of the merged book.
+    pub stale_after: Duration,
+    // Capacity of the channel between the connectors and the aggregation of a symbol.
+    pub channel_size: usize,
+    // Number of trades of a symbol kept for subscribers that fall behind.
+    pub trade_channel_size: usize,
+    // Number of closed bars of each interval kept for each exchange and the consolidated book.
+    pub candle_history: usize,
+    pub connectors: HashMap<ExchangeName, ConnectorConfig>,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            stale_after: Duration::from_secs(10),
+            channel_size: 20,
+            trade_channel_size: 1000,
+            candle_history: 300,
+            connectors: HashMap::from([
+                (ExchangeName::BINANCE, Binance::default_config()),
+                (ExchangeName::BITSTAMP, Bitstamp::default_config()),
+            ]),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    pipelines: HashMap<Symbol, Pipeline>,
+    supervisor: Supervisor,
+}
+
+// The `Pipelines` struct is the registry of running pipelines and the supervisor of their
+// connectors. It is shared by the services that read books and the admin service that changes
+// them at runtime.
+#[derive(Debug, Clone)]
+pub struct Pipelines {
+    inner: Arc<Mutex<Inner>>,
+    config: Arc<watch::Sender<PipelineConfig>>,
+    recorder: Option<Recorder>,
+}
+
+impl Pipelines {
+    pub fn new(config: PipelineConfig) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner::default())),
+            config: Arc::new(watch::channel(config).0),
+            recorder: None,
+        }
+    }
+
+    // The `with_recorder` function records the raw messages of every connector started afterwards.
+    pub fn with_recorder(mut self, recorder: Option<Recorder>) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    fn connector_config_in(&self, config: &PipelineConfig, exchange: ExchangeName) -> Option<ConnectorConfig> {
+        let mut connector_config = config.connectors.get(&exchange).cloned()?;
+        connector_config.recorder = self.recorder.clone();
+        Some(connector_config)
+    }
+
+    fn connector_config(&self, exchange: ExchangeName) -> Option<ConnectorConfig> {
+        self.connector_config_in(&self.config.borrow(), exchange)
+    }
+
+    // The `reconfigure` function applies a new configuration to the running pipelines. Connectors
+    // whose configuration changed are stopped and started again once they closed their websockets,
+    // which keeps their pipeline and its subscribers. New channel sizes only apply to pipelines
+    // created afterwards.
+    pub async fn reconfigure(&self, config: PipelineConfig) {
+        let mut restarts = Vec::new();
+        {
+            let mut inner = self.inner.lock().unwrap();
+            let Inner { pipelines, supervisor } = &mut *inner;
+            for status in supervisor.statuses() {
+                let (exchange, symbol) = (status.exchange, status.symbol);
+                let Some(connector_config) = self.connector_config_in(&config, exchange) else {
+                    continue;
+                };
+                if self.connector_config(exchange).as_ref() == Some(&connector_config) {
+                    continue;
+                }
+                if pipelines.contains_key(&symbol) {
+                    tracing::info!("restarting {} {} connector with the new configuration", exchange, symbol);
+                    if let Some(stopping) = supervisor.stop(exchange, symbol) {
+                        restarts.push((exchange, symbol, connector_config, stopping));
+                    }
+                }
+            }
+            self.config.send_replace(config);
+        }
+        for (exchange, symbol, connector_config, stopping) in restarts {
+            let _ = stopping.await;
+            let mut inner = self.inner.lock().unwrap();
+            let Inner { pipelines, supervisor } = &mut *inner;
+            if let Some(pipeline) = pipelines.get_mut(&symbol) {
+                let connector_config = pipeline.with_outputs(exchange, connector_config);
+                supervisor.spawn(exchange, symbol, connector_config, pipeline.tx_orderbook.clone());
+            }
+        }
+    }
+
+    // The `start_connector` function starts a supervised connector, creating the pipeline of
+    // `symbol` if needed. It returns false when the connector was already running or the exchange
+    // is not configured.
+    pub fn start_connector(&self, exchange: ExchangeName, symbol: Symbol) -> bool {
+        let Some(config) = self.connector_config(exchange) else {
+            return false;
+        };
+        let mut inner = self.inner.lock().unwrap();
+        let Inner { pipelines, supervisor } = &mut *inner;
+        let pipeline = self.pipeline(pipelines, exchange, symbol);
+        let config = pipeline.with_outputs(exchange, config);
+        supervisor.spawn(exchange, symbol, config, pipeline.tx_orderbook.clone())
+    }
+
+    // The `pipeline` function returns the pipeline of `symbol`, creating it if needed, with
+    // `exchange` among the exchanges it expects books from.
+    fn pipeline<'a>(
+        &self,
+        pipelines: &'a mut HashMap<Symbol, Pipeline>,
+        exchange: ExchangeName,
+        symbol: Symbol,
+    ) -> &'a mut Pipeline {
+        let pipeline = pipelines.entry(symbol).or_insert_with(|| {
+            let channel_size = self.config.borrow().channel_size;
+            let (tx_orderbook, rx_orderbook) = mpsc::channel::<OrderBookOnlyLevels>(channel_size);
+            let (tx_books, rx_books) = watch::channel(AggregatedBooks::default());
+            tokio::spawn(aggregate_and_broadcast_data(symbol, rx_orderbook, tx_books, self.config.subscribe()));
+            let config = self.config.borrow();
+            let trades = TradeTape::new(config.trade_channel_size);
+            let candles = CandleHistory::new(config.candle_history);
+            let builder = CandleBuilder::new(symbol, candles.clone());
+            tokio::spawn(build_candles(rx_books.clone(), trades.subscribe(), builder));
+            Pipeline {
+                tx_orderbook,
+                rx_books,
+                exchanges: Vec::new(),
+                books: HashMap::new(),
+                trades,
+                candles,
+            }
+        });
+        if !pipeline.exchanges.contains(&exchange) {
+            pipeline.exchanges.push(exchange);
+        }
+        pipeline
+    }
+
+    // The `feed` function returns the sender of the pipeline of `symbol` for a source of books
+    // other than a connector, such as a replay.
+    pub fn feed(&self, exchange: ExchangeName, symbol: Symbol) -> mpsc::Sender<OrderBookOnlyLevels> {
+        let mut inner = self.inner.lock().unwrap();
+        self.pipeline(&mut inner.pipelines, exchange, symbol).tx_orderbook.clone()
+    }
+
+    // The `publisher` function returns where a source of books other than a connector publishes
+    // the full book of `exchange` and its trades.
+    pub fn publisher(&self, exchange: ExchangeName, symbol: Symbol) -> (PublishedBook, TradeTape) {
+        let mut inner = self.inner.lock().unwrap();
+        let pipeline = self.pipeline(&mut inner.pipelines, exchange, symbol);
+        (pipeline.books.entry(exchange).or_default().clone(), pipeline.trades.clone())
+    }
+
+    // The `stop_connector` function stops a connector and, once it closed its websocket and can no
+    // longer send levels, removes its book from the pipeline. It returns false when there was no
+    // such connector.
+    pub async fn stop_connector(&self, exchange: ExchangeName, symbol: Symbol) -> bool {
+        let (stopping, tx_orderbook) = {
+            let mut inner = self.inner.lock().unwrap();
+            let Inner { pipelines, supervisor } = &mut *inner;
+            let Some(stopping) = supervisor.stop(exchange, symbol) else {
+                return false;
+            };
+            let tx_orderbook = pipelines.get_mut(&symbol).map(|pipeline| {
+                pipeline.exchanges.retain(|e| *e != exchange);
+                pipeline.books.remove(&exchange);
+                pipeline.tx_orderbook.clone()
+            });
+            (stopping, tx_orderbook)
+        };
+        let _ = stopping.await;
+        if let Some(tx_orderbook) = tx_orderbook {
+            let removed = OrderBookOnlyLevels { exchange, symbol, ..Default::default() };
+            let _ = tx_orderbook.send(removed).await;
+        }
+        true
+    }
+
+    // The `resync_connector` function restarts a connector once it closed its websocket, which
+    // rebuilds its book from a new snapshot. It returns false when there was no such connector.
+    pub async fn resync_connector(&self, exchange: ExchangeName, symbol: Symbol) -> bool {
+        let Some(config) = self.connector_config(exchange) else {
+            return false;
+        };
+        let stopping = {
+            let mut inner = self.inner.lock().unwrap();
+            if !inner.pipelines.contains_key(&symbol) {
+                return false;
+            }
+            let Some(stopping) = inner.supervisor.stop(exchange, symbol) else {
+                return false;
+            };
+            stopping
+        };
+        let _ = stopping.await;
+        let mut inner = self.inner.lock().unwrap();
+        let Inner { pipelines, supervisor } = &mut *inner;
+        let Some(pipeline) = pipelines.get_mut(&symbol) else {
+            return false;
+        };
+        // The book published by the stopped connector is kept, so the new connector counts the
+        // resync once it fetched its snapshot.
+        let config = pipeline.with_outputs(exchange, config);
+        supervisor.spawn(exchange, symbol, config, pipeline.tx_orderbook.clone())
+    }
+
+    // The `shutdown` function stops every connector and waits for them to close their websockets.
+    // The pipelines are dropped, so each aggregation ends and its subscribers receive a final
+    // status, while new subscriptions are refused.
+    pub async fn shutdown(&self) {
+        let tasks = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.pipelines.clear();
+            inner.supervisor.shutdown()
+        };
+        for task in tasks {
+            let _ = task.await;
+        }
+    }
+
+    pub fn books(&self, symbol: Symbol) -> Option<watch::Receiver<AggregatedBooks>> {
+        let inner = self.inner.lock().unwrap();
+        inner.pipelines.get(&symbol).map(|pipeline| pipeline.rx_books.clone())
+    }
+
+    pub fn trades(&self, symbol: Symbol) -> Option<TradeTape> {
+        let inner = self.inner.lock().unwrap();
+        inner.pipelines.get(&symbol).map(|pipeline| pipeline.trades.clone())
+    }
+
+    pub fn candles(&self, symbol: Symbol) -> Option<CandleHistory> {
+        let inner = self.inner.lock().unwrap();
+        inner.pipelines.get(&symbol).map(|pipeline| pipeline.candles.clone())
+    }
+
+    // The `exchange_book` function returns the full book of `exchange` for `symbol`. Snapshots are
+    // loaded from it without locking, so readers can hold on to it.
+    pub fn exchange_book(&self, exchange: ExchangeName, symbol: Symbol) -> Option<PublishedBook> {
+        let inner = self.inner.lock().unwrap();
+        inner.pipelines.get(&symbol)?.books.get(&exchange).cloned()
+    }
+
+    pub fn pipelines(&self) -> Vec<PipelineInfo> {
+        let inner = self.inner.lock().unwrap();
+        let statuses = inner.supervisor.statuses();
+        inner
+            .pipelines
+            .keys()
+            .map(|symbol| PipelineInfo {
+                symbol: *symbol,
+                connectors: statuses
+                    .iter()
+                    .filter(|status| status.symbol == *symbol)
+                    .cloned()
+                    .collect(),
+            })
+            .collect()
+    }
+
+    // The `is_serving` function returns true when every started connector of every pipeline is
+    // delivering books.
+    pub fn is_serving(&self, now: Instant) -> bool {
+        let inner = self.inner.lock().unwrap();
+        let stale_after = self.config.borrow().stale_after;
+        !inner.pipelines.is_empty()
+            && inner.pipelines.values().all(|pipeline| {
+                health::is_serving(&pipeline.rx_books.borrow(), &pipeline.exchanges, stale_after, now)
+            })
+    }
+}
@@ -0,0 +1,359 @@
+use anyhow::{bail, ensure, Context, Result};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite::Message;
+use crate::{metrics, ExchangeName, Symbol};
+
+// The `Scales` of a book are the payload of a `RecordKind::Scales` record.
+pub use crate::orderbook::orderbook::Scales;
+
+// Records are at most this long, far more than the largest snapshot. Longer lengths in a
+// recording mean it is corrupt, and are rejected instead of allocated.
+pub const MAX_RECORD_LEN: usize = 16 * 1024 * 1024;
+
+// The `RecordKind` enum tells what a recorded payload is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    Text = 0,
+    Binary = 1,
+    Ping = 2,
+    Pong = 3,
+    Close = 4,
+    // The body of a REST orderbook snapshot.
+    Snapshot = 5,
+    // The price and quantity scales of the book, recorded each time a connector starts so a
+    // recording can be replayed without the exchange.
+    Scales = 6,
+}
+
+impl TryFrom<u8> for RecordKind {
+    type Error = anyhow::Error;
+
+    fn try_from(kind: u8) -> Result<Self> {
+        Ok(match kind {
+            0 => RecordKind::Text,
+            1 => RecordKind::Binary,
+            2 => RecordKind::Ping,
+            3 => RecordKind::Pong,
+            4 => RecordKind::Close,
+            5 => RecordKind::Snapshot,
+            6 => RecordKind::Scales,
+            _ => bail!("unknown record kind: {kind}"),
+        })
+    }
+}
+
+// The `Record` struct is one raw message received by a connector.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub received_at: SystemTime,
+    pub exchange: ExchangeName,
+    pub symbol: Symbol,
+    pub kind: RecordKind,
+    pub payload: Vec<u8>,
+}
+
+impl Record {
+    pub fn from_message(exchange: ExchangeName, symbol: Symbol, message: &Message) -> Option<Self> {
+        let (kind, payload) = match message {
+            Message::Text(text) => (RecordKind::Text, text.as_bytes().to_vec()),
+            Message::Binary(data) => (RecordKind::Binary, data.clone()),
+            Message::Ping(data) => (RecordKind::Ping, data.clone()),
+            Message::Pong(data) => (RecordKind::Pong, data.clone()),
+            Message::Close(frame) => (
+                RecordKind::Close,
+                frame.as_ref().map(|frame| frame.reason.as_bytes().to_vec()).unwrap_or_default(),
+            ),
+            Message::Frame(_) => return None,
+        };
+        Some(Self {
+            received_at: SystemTime::now(),
+            exchange,
+            symbol,
+            kind,
+            payload,
+        })
+    }
+
+    // The `to_message` function rebuilds the websocket message of a recorded message. Snapshots
+    // and scales were not websocket messages and return `None`.
+    pub fn to_message(&self) -> Option<Message> {
+        match self.kind {
+            RecordKind::Text => Some(Message::Text(String::from_utf8_lossy(&self.payload).into_owned())),
+            RecordKind::Binary => Some(Message::Binary(self.payload.clone())),
+            RecordKind::Ping => Some(Message::Ping(self.payload.clone())),
+            RecordKind::Pong => Some(Message::Pong(self.payload.clone())),
+            RecordKind::Close => Some(Message::Close(None)),
+            RecordKind::Snapshot | RecordKind::Scales => None,
+        }
+    }
+
+    // Records are written as a big endian `u32` length followed by that many bytes: the receive
+    // time in nanoseconds since the unix epoch as a `u64`, the kind as a `u8`, the exchange and
+    // symbol as `u8` length prefixed strings, and the payload.
+    pub fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        let nanos = self.received_at.duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+        let (exchange, symbol) = (self.exchange.to_string(), self.symbol.to_string());
+        let len = 8 + 1 + 1 + exchange.len() + 1 + symbol.len() + self.payload.len();
+        ensure!(len <= MAX_RECORD_LEN, "record of {len} bytes exceeds {MAX_RECORD_LEN} bytes");
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+        out.extend_from_slice(&nanos.to_be_bytes());
+        out.push(self.kind as u8);
+        for tag in [exchange, symbol] {
+            out.push(tag.len() as u8);
+            out.extend_from_slice(tag.as_bytes());
+        }
+        out.extend_from_slice(&self.payload);
+        Ok(())
+    }
+
+    // The `decode` function parses a record encoded by `encode`, without its length prefix.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+            ensure!(bytes.len() >= len, "record is truncated");
+            let (head, tail) = bytes.split_at(len);
+            *bytes = tail;
+            Ok(head)
+        }
+        fn tag(bytes: &mut &[u8]) -> Result<String> {
+            let len = take(bytes, 1)?[0] as usize;
+            Ok(String::from_utf8(take(bytes, len)?.to_vec())?)
+        }
+
+        ensure!(bytes.len() <= MAX_RECORD_LEN, "record of {} bytes exceeds {MAX_RECORD_LEN} bytes", bytes.len());
+        let mut bytes = bytes;
+        let nanos = u64::from_be_bytes(take(&mut bytes, 8)?.try_into()?);
+        let kind = RecordKind::try_from(take(&mut bytes, 1)?[0])?;
+        let exchange = tag(&mut bytes)?.parse()?;
+        let symbol = tag(&mut bytes)?.parse()?;
+        Ok(Self {
+            received_at: UNIX_EPOCH + Duration::from_nanos(nanos),
+            exchange,
+            symbol,
+            kind,
+            payload: bytes.to_vec(),
+        })
+    }
+}
+
+// The `RecordReader` reads the records of one recording file in order.
+pub struct RecordReader {
+    reader: MultiGzDecoder<BufReader<File>>,
+}
+
+impl RecordReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open recording {}", path.display()))?;
+        Ok(Self {
+            reader: MultiGzDecoder::new(BufReader::new(file)),
+        })
+    }
+}
+
+impl Iterator for RecordReader {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut len = [0u8; 4];
+        match self.reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return None,
+            Err(err) => return Some(Err(err.into())),
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_RECORD_LEN {
+            return Some(Err(anyhow::anyhow!("record of {len} bytes exceeds {MAX_RECORD_LEN} bytes")));
+        }
+        let mut record = vec![0u8; len];
+        let result = self
+            .reader
+            .read_exact(&mut record)
+            .context("recording is truncated")
+            .and_then(|()| Record::decode(&record));
+        Some(result)
+    }
+}
+
+// The `RecorderConfig` struct sets where recordings are written and when a new file is started.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecorderConfig {
+    pub directory: PathBuf,
+    // Uncompressed bytes written to a file before the next one is started.
+    pub max_file_bytes: u64,
+    // Age of a file after which the next one is started.
+    pub max_file_age: Duration,
+    // Records waiting to be written before new ones are dropped.
+    pub channel_size: usize,
+}
+
+#[derive(Debug)]
+enum Command {
+    Record(Record),
+    // Finishes the current file so every record received so far is on disk.
+    Flush(oneshot::Sender<Result<()>>),
+}
+
+// The `Recorder` writes the raw messages and snapshots of every connector to rotating, gzip
+// compressed files named `market-data-<unix millis>-<sequence>.rec.gz`. Records are queued
+// without waiting, so a slow disk drops records instead of delaying the books.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    tx: mpsc::Sender<Command>,
+}
+
+impl PartialEq for Recorder {
+    fn eq(&self, other: &Self) -> bool {
+        self.tx.same_channel(&other.tx)
+    }
+}
+
+impl Recorder {
+    // The `start` function creates the recording directory and spawns the thread writing to it.
+    pub fn start(config: RecorderConfig) -> Result<Self> {
+        std::fs::create_dir_all(&config.directory)
+            .with_context(|| format!("Failed to create {}", config.directory.display()))?;
+        let (tx, rx) = mpsc::channel(config.channel_size);
+        tokio::task::spawn_blocking(move || write_records(rx, &config));
+        Ok(Self { tx })
+    }
+
+    pub fn record(&self, record: Record) {
+        if self.tx.try_send(Command::Record(record)).is_err() {
+            metrics::RECORDS_DROPPED.inc();
+        }
+    }
+
+    pub fn record_message(&self, exchange: ExchangeName, symbol: Symbol, message: &Message) {
+        if let Some(record) = Record::from_message(exchange, symbol, message) {
+            self.record(record);
+        }
+    }
+
+    pub fn record_snapshot(&self, exchange: ExchangeName, symbol: Symbol, body: &[u8]) {
+        self.record(Record {
+            received_at: SystemTime::now(),
+            exchange,
+            symbol,
+            kind: RecordKind::Snapshot,
+            payload: body.to_vec(),
+        });
+    }
+
+    pub fn record_scales(&self, exchange: ExchangeName, symbol: Symbol, scales: Scales) {
+        match serde_json::to_vec(&scales) {
+            Ok(payload) => self.record(Record {
+                received_at: SystemTime::now(),
+                exchange,
+                symbol,
+                kind: RecordKind::Scales,
+                payload,
+            }),
+            Err(err) => tracing::error!("failed to record scales: {:?}", err),
+        }
+    }
+
+    // The `flush` function waits until every queued record is written and the current file is
+    // complete. Later records start a new file.
+    pub async fn flush(&self) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(Command::Flush(tx)).await.context("recorder stopped")?;
+        rx.await.context("recorder stopped")?
+    }
+}
+
+struct RecordingFile {
+    encoder: GzEncoder<BufWriter<File>>,
+    opened_at: Instant,
+    written: u64,
+}
+
+impl RecordingFile {
+    fn create(directory: &Path, sequence: u64) -> Result<Self> {
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let path = directory.join(format!("market-data-{millis}-{sequence:06}.rec.gz"));
+        let file = File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+        tracing::info!("recording market data to {}", path.display());
+        Ok(Self {
+            encoder: GzEncoder::new(BufWriter::new(file), Compression::default()),
+            opened_at: Instant::now(),
+            written: 0,
+        })
+    }
+
+    fn finish(self) -> Result<()> {
+        self.encoder.finish()?.flush()?;
+        Ok(())
+    }
+}
+
+// The `write_records` function writes records until every `Recorder` is dropped. Records that can
+// not be encoded are dropped. After an I/O error the current file is abandoned and the next record
+// starts a new one, so recording resumes once e.g. disk space is freed.
+fn write_records(mut rx: mpsc::Receiver<Command>, config: &RecorderConfig) {
+    let mut file: Option<RecordingFile> = None;
+    let mut files_created = 0;
+    let mut buffer = Vec::new();
+    while let Some(command) = rx.blocking_recv() {
+        match command {
+            Command::Record(record) => {
+                buffer.clear();
+                if let Err(err) = record.encode(&mut buffer) {
+                    tracing::error!("dropping {} {} record: {:#}", record.exchange, record.symbol, err);
+                    metrics::RECORDS_DROPPED.inc();
+                    continue;
+                }
+                if let Err(err) = write_record(&mut file, &mut files_created, &buffer, config) {
+                    tracing::error!("failed to record market data: {:#}", err);
+                    metrics::RECORDS_DROPPED.inc();
+                    let _ = close(&mut file);
+                }
+            }
+            Command::Flush(done) => {
+                let _ = done.send(close(&mut file));
+            }
+        }
+    }
+    let _ = close(&mut file);
+}
+
+fn write_record(
+    file: &mut Option<RecordingFile>,
+    files_created: &mut u64,
+    bytes: &[u8],
+    config: &RecorderConfig,
+) -> Result<()> {
+    let expired = file.as_ref().is_some_and(|file| {
+        file.written >= config.max_file_bytes || file.opened_at.elapsed() >= config.max_file_age
+    });
+    if expired {
+        file.take().expect("file is open").finish()?;
+    }
+    let current = match file {
+        Some(current) => current,
+        None => {
+            *files_created += 1;
+            file.insert(RecordingFile::create(&config.directory, *files_created)?)
+        }
+    };
+    current.encoder.write_all(bytes)?;
+    current.written += bytes.len() as u64;
+    Ok(())
+}
+
+// The `close` function finishes the current file, if any. Errors are logged and returned.
+fn close(file: &mut Option<RecordingFile>) -> Result<()> {
+    let Some(current) = file.take() else {
+        return Ok(());
+    };
+    let result = current.finish();
+    if let Err(err) = &result {
+        tracing::error!("failed to finish recording: {:#}", err);
+    }
+    result
+}
@@ -0,0 +1,76 @@
+use anyhow::Result;
+use std::{path::PathBuf, time::{Duration, SystemTime}};
+use crate::{pipeline::Pipelines, settings::Settings};
+
+// The `apply` function reconciles the running pipelines with the settings read after a change.
+// Connectors are started and stopped by comparing the connectors the old and new settings ask
+// for, so connectors started or stopped through the admin service are left alone. Pipelines are
+// kept, so existing `BookSummary` streams are not disconnected.
+pub async fn apply(current: &Settings, next: &Settings, pipelines: &Pipelines) -> Result<()> {
+    let config = next.pipeline_config()?;
+    let (before, after) = (current.connectors(), next.connectors());
+    for (exchange, symbol) in before.difference(&after) {
+        tracing::info!("stopping {} connector for {}", exchange, symbol);
+        pipelines.stop_connector(*exchange, *symbol).await;
+    }
+    pipelines.reconfigure(config).await;
+    for (exchange, symbol) in after.difference(&before) {
+        tracing::info!("starting {} connector for {}", exchange, symbol);
+        pipelines.start_connector(*exchange, *symbol);
+    }
+
+    let needs_restart = current.server_ip != next.server_ip
+        || current.server_port != next.server_port
+        || current.http_port != next.http_port
+        || current.ws_port != next.ws_port
+        || current.metrics_port != next.metrics_port
+        || current.admin_port != next.admin_port
+        || current.default_symbol() != next.default_symbol()
+        || current.logging != next.logging
+        || current.recorder != next.recorder
+        || current.replay != next.replay
+        || current.tls_cert != next.tls_cert
+        || current.tls_key != next.tls_key
+        || current.tls_client_ca != next.tls_client_ca
+        || current.auth_tokens != next.auth_tokens;
+    if needs_restart {
+        tracing::warn!("listeners, default symbol, logging, recorder, replay, TLS and authentication settings only apply after a restart");
+    }
+    Ok(())
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// The `watch_settings` function polls the settings file every second and applies its changes to
+// the running pipelines. Settings that fail to load or validate are logged and ignored, leaving
+// the server running with the last valid settings.
+pub async fn watch_settings(path: PathBuf, mut current: Settings, pipelines: Pipelines) {
+    let mut last_modified = modified(&path);
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let modified = modified(&path);
+        if modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+
+        let next = match Settings::load(&path) {
+            Ok(next) => next,
+            Err(err) => {
+                tracing::error!("ignoring invalid settings in {}: {:#}", path.display(), err);
+                continue;
+            }
+        };
+        if next == current {
+            continue;
+        }
+        tracing::info!("reloading settings from {}", path.display());
+        match apply(&current, &next, &pipelines).await {
+            Ok(()) => current = next,
+            Err(err) => tracing::error!("failed to apply settings: {:#}", err),
+        }
+    }
+}
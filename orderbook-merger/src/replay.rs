@@ -0,0 +1,207 @@
+use anyhow::{Context, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{sync::mpsc, time::Instant};
+use tokio_util::sync::CancellationToken;
+use crate::{
+    exchanges::{binance::{self, Binance}, bitstamp::{self, Bitstamp}, exchange::Exchange},
+    orderbook::orderbook::{OrderBook, OrderBookOnlyLevels, PublishedBook, Update},
+    pipeline::Pipelines,
+    recorder::{Record, RecordKind, RecordReader, Scales},
+    trades::TradeTape,
+    ExchangeName, Symbol,
+};
+
+// The `ReplayConfig` struct selects a recording and how fast it is replayed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayConfig {
+    // A recording file, or a directory whose `.rec.gz` files are replayed in name order.
+    pub path: PathBuf,
+    // 1.0 replays at the recorded pace, 10.0 ten times faster and 0.0 as fast as possible.
+    pub speed: f64,
+    // Number of bid and ask levels of each exchange's book merged into the summary.
+    pub depth: usize,
+}
+
+// The `recording_files` function lists the files of a recording in the order they were written.
+pub fn recording_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = std::fs::read_dir(path)
+        .with_context(|| format!("Failed to read recordings in {}", path.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    files.retain(|file| file.to_string_lossy().ends_with(".rec.gz"));
+    files.sort();
+    Ok(files)
+}
+
+// The `ReplayBook` struct is the book of one exchange and symbol rebuilt from a recording.
+struct ReplayBook {
+    orderbook: OrderBook,
+    // False until a snapshot is applied, and again after an update fails, like a connector that
+    // waits for a new snapshot after restarting.
+    synced: bool,
+    tx_orderbook: mpsc::Sender<OrderBookOnlyLevels>,
+    published: PublishedBook,
+    trades: TradeTape,
+}
+
+// The `apply` function applies one record to a book through the same parsing and update code as a
+// live connector, and publishes the recorded trades. It returns true when the book changed.
+fn apply<S, U, E>(book: &mut ReplayBook, record: &Record) -> Result<bool>
+where
+    S: Update + Send,
+    U: std::fmt::Debug + Update + From<S> + Send + Sync + 'static,
+    E: Exchange<S, U>,
+{
+    let scales = book.orderbook.scales();
+    match record.kind {
+        RecordKind::Snapshot => {
+            let snapshot = S::parse(&record.payload, scales)?;
+            book.orderbook.reset();
+            book.synced = true;
+            book.orderbook.update(&U::from(snapshot))?;
+            return Ok(true);
+        }
+        RecordKind::Text | RecordKind::Binary => {}
+        _ => return Ok(false),
+    }
+    // Messages that are neither updates nor trades, e.g. subscription replies, are skipped as they
+    // are live.
+    let Ok(update) = U::parse(&record.payload, scales) else {
+        if let Ok(trade) = E::parse_trade(&record.payload, record.symbol, scales, record.received_at) {
+            book.trades.publish(trade);
+        }
+        return Ok(false);
+    };
+    if !book.synced {
+        return Ok(false);
+    }
+    if let Err(err) = book.orderbook.update(&update) {
+        book.synced = false;
+        return Err(err);
+    }
+    Ok(true)
+}
+
+// The `read_records` function reads the recording on a blocking thread and sends its records in
+// order until the receiver goes away.
+fn read_records(files: Vec<PathBuf>) -> mpsc::Receiver<Result<Record>> {
+    let (tx, rx) = mpsc::channel(1024);
+    tokio::task::spawn_blocking(move || {
+        for file in files {
+            let records = match RecordReader::open(&file) {
+                Ok(records) => records,
+                Err(err) => {
+                    let _ = tx.blocking_send(Err(err));
+                    return;
+                }
+            };
+            for record in records {
+                let failed = record.is_err();
+                if tx.blocking_send(record.with_context(|| format!("in {}", file.display()))).is_err() || failed {
+                    return;
+                }
+            }
+        }
+    });
+    rx
+}
+
+// The `replay` function feeds a recording through the books and the pipelines of `pipelines`, so
+// the merged book is served as if the recorded exchanges were live. It returns when the recording
+// ends or `shutdown` is cancelled.
+pub async fn replay(config: ReplayConfig, pipelines: Pipelines, shutdown: CancellationToken) -> Result<()> {
+    let files = recording_files(&config.path)?;
+    anyhow::ensure!(!files.is_empty(), "no recordings found in {}", config.path.display());
+    tracing::info!("replaying {} recording files from {}", files.len(), config.path.display());
+
+    let mut records = read_records(files);
+    let mut books = HashMap::<(ExchangeName, Symbol), ReplayBook>::new();
+    let mut without_scales = HashSet::<(ExchangeName, Symbol)>::new();
+    let mut start: Option<(std::time::SystemTime, Instant)> = None;
+    let mut replayed = 0u64;
+    loop {
+        let record = tokio::select! {
+            record = records.recv() => record,
+            _ = shutdown.cancelled() => return Ok(()),
+        };
+        let Some(record) = record else {
+            break;
+        };
+        let record = record?;
+        replayed += 1;
+
+        // Records are replayed at their recorded offset from the first record, divided by speed.
+        let (first_received_at, started_at) = *start.get_or_insert((record.received_at, Instant::now()));
+        if config.speed > 0.0 {
+            let offset = record.received_at.duration_since(first_received_at).unwrap_or_default();
+            let due = started_at + Duration::from_secs_f64(offset.as_secs_f64() / config.speed);
+            tokio::select! {
+                _ = tokio::time::sleep_until(due) => {}
+                _ = shutdown.cancelled() => return Ok(()),
+            }
+        }
+
+        let key = (record.exchange, record.symbol);
+        if record.kind == RecordKind::Scales {
+            let scales = serde_json::from_slice::<Scales>(&record.payload).context("Failed to deserialize scales")?;
+            let book = books.get(&key);
+            let unchanged = book.is_some_and(|book| {
+                book.orderbook.price_scale == scales.price_scale
+                    && book.orderbook.quantity_scale == scales.quantity_scale
+            });
+            if !unchanged {
+                let orderbook = OrderBook::new_orderbook(
+                    record.exchange,
+                    record.symbol,
+                    scales.price_scale,
+                    scales.quantity_scale,
+                    config.depth,
+                );
+                let tx_orderbook = pipelines.feed(record.exchange, record.symbol);
+                let (published, trades) = pipelines.publisher(record.exchange, record.symbol);
+                books.insert(key, ReplayBook { orderbook, synced: false, tx_orderbook, published, trades });
+            }
+            continue;
+        }
+        let Some(book) = books.get_mut(&key) else {
+            if without_scales.insert(key) {
+                tracing::warn!("skipping {} {} records recorded before its scales", key.0, key.1);
+            }
+            continue;
+        };
+
+        let applied = match record.exchange {
+            ExchangeName::BINANCE => apply::<binance::Snapshot, binance::BookUpdate, Binance>(book, &record),
+            ExchangeName::BITSTAMP => apply::<bitstamp::Snapshot, bitstamp::BookUpdate, Bitstamp>(book, &record),
+        };
+        match applied {
+            Ok(true) => {
+                book.published.publish(&book.orderbook);
+                if let Some(mut book_levels) = book.orderbook.get_book_levels() {
+                    book_levels.received_at = Some(Instant::now());
+                    book.tx_orderbook
+                        .send(book_levels)
+                        .await
+                        .context("failed to send book levels")?;
+                }
+            }
+            Ok(false) => {}
+            Err(err) => tracing::warn!(
+                "{} {} book failed to update at record {}, waiting for the next snapshot: {:#}",
+                key.0,
+                key.1,
+                replayed,
+                err
+            ),
+        }
+    }
+    tracing::info!("replay finished after {} records", replayed);
+    Ok(())
+}
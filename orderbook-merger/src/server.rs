@@ -1,111 +1,191 @@
 use anyhow::Result;
-use config::Config;
-use std::collections::HashMap;
-use tokio::{sync::mpsc, sync::watch};
-use tokio_stream::wrappers::WatchStream;
-use tonic::{transport::Server, Request, Response, Status};
+use tokio_util::sync::CancellationToken;
+use tonic::{server::NamedService, transport::Server};
+use tonic_web::GrpcWebLayer;
 use orderbook_merger::{
-    orderbook::orderbook::OrderBookOnlyLevels,
-    exchanges::{exchange::Exchange, binance::Binance, bitstamp::Bitstamp},
+    admin::AdminService,
     orderbook_summary::{
-        orderbook_aggregator_server::{OrderbookAggregator, OrderbookAggregatorServer},
-        Empty, Summary,
+        admin_server::AdminServer,
+        orderbook_aggregator_server::OrderbookAggregatorServer,
+        FILE_DESCRIPTOR_SET,
     },
-    make_summary, Symbol, ExchangeName,
+    auth::{load_tokens, AuthInterceptor},
+    drain::{serve_until_drained, DRAIN_TIMEOUT},
+    gateway,
+    health::report_health,
+    metrics,
+    pipeline::Pipelines,
+    recorder::Recorder,
+    reload::watch_settings,
+    replay::replay,
+    service::OrderbookSummary,
+    settings::Settings,
+    tls::{http_tls_config, server_tls_from_settings},
+    websocket,
 };
 
-#[derive(Debug)]
-pub struct OrderbookSummary {
-    summary: watch::Receiver<Result<Summary, Status>>
-}
-
-#[tonic::async_trait]
-impl OrderbookAggregator for OrderbookSummary {
-    type BookSummaryStream = WatchStream<Result<Summary, Status>>;
-    async fn book_summary(
-        &self,
-        _request: Request<Empty>,
-    ) -> Result<Response<Self::BookSummaryStream>, Status> {
-        Ok(Response::new(WatchStream::new(self.summary.clone())))
-    }
-}
-
-async fn aggregate_and_broadcast_data(mut rx: mpsc::Receiver<OrderBookOnlyLevels>, tx_summary: watch::Sender<Result<Summary, Status>>) {
-    let mut exchange_to_orderbook = HashMap::<ExchangeName, OrderBookOnlyLevels>::new();
-    while let Some(orderbook) = rx.recv().await {
-        match orderbook {
-            OrderBookOnlyLevels { exchange: ExchangeName::BITSTAMP, .. } => {
-                exchange_to_orderbook.insert(ExchangeName::BITSTAMP, orderbook);
-            }
-            OrderBookOnlyLevels { exchange: ExchangeName::BINANCE, .. } => {
-                exchange_to_orderbook.insert(ExchangeName::BINANCE, orderbook);
-            }
-        }
+// The `shutdown_signal` function cancels `shutdown` on SIGINT or SIGTERM.
+#[cfg(unix)]
+async fn shutdown_signal(shutdown: CancellationToken) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
 
-        // Book levels are stored in the hashmap above and a new summary created from both exchanges
-        // every time an update is received from either.
-        if exchange_to_orderbook.len() == 2 {
-            // let current_levels = exchange_to_orderbook.values().map(|v| v.clone()).collect::<Vec<OrderBookOnlyLevels>>();
-            let current_levels: Vec<_> = exchange_to_orderbook.values().cloned().collect();
-            let summary = make_summary(current_levels);
-            tx_summary.send_replace(Ok(summary)).unwrap();
-        }
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = terminate.recv() => {}
     }
+    tracing::info!("shutting down");
+    shutdown.cancel();
+    Ok(())
 }
 
-async fn start(symbol: Symbol) -> watch::Receiver<Result<Summary, Status>>{
-    let (tx_orderbook, rx_orderbook) = mpsc::channel::<OrderBookOnlyLevels>(20);
-    let tx2_orderbook = tx_orderbook.clone();
-
-    let (tx_summary, rx_summary) = watch::channel(Ok(Summary::default()));
-
-    let binance = Binance::new_exchange(symbol).await.unwrap();
-    let bitstamp = Bitstamp::new_exchange(symbol).await.unwrap();
-
-    tokio::spawn(async move {
-        binance.start(tx_orderbook).await.unwrap()
-    });
-    tokio::spawn(async move {
-        bitstamp.start(tx2_orderbook).await.unwrap()
-    });
-    tokio::spawn(
-        aggregate_and_broadcast_data(rx_orderbook, tx_summary)
-    );
-
-    rx_summary
+// The `shutdown_signal` function cancels `shutdown` on Ctrl-C, the only signal outside unix.
+#[cfg(not(unix))]
+async fn shutdown_signal(shutdown: CancellationToken) -> Result<()> {
+    tokio::signal::ctrl_c().await?;
+    tracing::info!("shutting down");
+    shutdown.cancel();
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    // The settings are read from `--config <path>`, `orderbook-merger/src/setting.toml` by default.
+    let settings_path = Settings::path_from_args()?;
+    let settings = Settings::load(&settings_path)?;
+
     let subscriber = tracing_subscriber::fmt()
-        .with_line_number(true)
-        .with_max_level(tracing::Level::DEBUG)
+        .with_line_number(settings.logging.line_number)
+        .with_max_level(settings.logging.level()?)
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 
-    let config = Config::builder()
-        // read the setting.toml
-        .add_source(config::File::with_name("orderbook-merger/src/setting"))
-        .build()
-        .unwrap()
-        .try_deserialize::<HashMap<String, String>>()
-        .unwrap();
+    let shutdown = CancellationToken::new();
+    tokio::spawn(shutdown_signal(shutdown.clone()));
 
-    let orderbook_summary = OrderbookSummary {
-        summary: start(Symbol::ETHUSDT).await,
+    // Connectors that can not be started are retried in the background, and more can be started
+    // at runtime through the admin service.
+    let recorder = match &settings.recorder {
+        Some(recorder) => Some(Recorder::start(recorder.recorder_config())?),
+        None => None,
     };
+    let pipelines = Pipelines::new(settings.pipeline_config()?).with_recorder(recorder.clone());
+    if let Some(replay_config) = settings.replay_config() {
+        // A recording is served instead of the exchanges, so no connector is started.
+        let (pipelines, shutdown) = (pipelines.clone(), shutdown.clone());
+        tokio::spawn(async move {
+            if let Err(err) = replay(replay_config, pipelines, shutdown).await {
+                tracing::error!("replay failed: {:#}", err);
+            }
+        });
+    } else {
+        for symbol in &settings.symbols {
+            for exchange in settings.exchanges.enabled() {
+                pipelines.start_connector(exchange, *symbol);
+            }
+        }
+        // Changes to the settings file are applied to the running pipelines.
+        tokio::spawn(watch_settings(settings_path, settings.clone(), pipelines.clone()));
+    }
+
+    let (health_reporter, health_svc) = tonic_health::server::health_reporter();
+    tokio::spawn(report_health(
+        health_reporter,
+        OrderbookAggregatorServer::<OrderbookSummary>::NAME,
+        pipelines.clone(),
+    ));
+
+    let reflection_svc = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .build()?;
+
+    let orderbook_summary = OrderbookSummary::new(settings.default_symbol(), pipelines.clone());
+
+    // Bearer tokens are only required when a key file is configured.
+    let auth = match &settings.auth_tokens {
+        Some(path) => AuthInterceptor::new(load_tokens(path)?),
+        None => AuthInterceptor::default(),
+    };
+    // The JSON gateway for browsers runs on its own port when `http-port` is configured.
+    if let Some(http_port) = settings.http_port {
+        let http_address = format!("{}:{}", settings.server_ip, http_port).parse()?;
+        let router = gateway::router(orderbook_summary.clone(), auth.clone(), settings.cors_origins()?);
+        let (tls, shutdown) = (http_tls_config(&settings)?, shutdown.clone());
+        tokio::spawn(async move {
+            if let Err(err) = gateway::serve(http_address, router, tls, shutdown).await {
+                tracing::error!("json gateway failed: {:?}", err);
+            }
+        });
+    }
+    // Prometheus metrics are served at `/metrics` when `metrics-port` is configured.
+    if let Some(metrics_port) = settings.metrics_port {
+        let metrics_address = format!("{}:{}", settings.server_ip, metrics_port).parse()?;
+        tokio::spawn(async move {
+            if let Err(err) = metrics::serve(metrics_address).await {
+                tracing::error!("metrics endpoint failed: {:?}", err);
+            }
+        });
+    }
 
-    let svc = OrderbookAggregatorServer::new(orderbook_summary);
+    // The websocket publisher runs on its own port when `ws-port` is configured.
+    if let Some(ws_port) = settings.ws_port {
+        let ws_address = format!("{}:{}", settings.server_ip, ws_port).parse()?;
+        let (service, auth, shutdown) = (orderbook_summary.clone(), auth.clone(), shutdown.clone());
+        let tls = http_tls_config(&settings)?;
+        tokio::spawn(async move {
+            if let Err(err) = websocket::serve(ws_address, service, auth, tls, shutdown).await {
+                tracing::error!("websocket publisher failed: {:?}", err);
+            }
+        });
+    }
+    // The admin service has its own listener, without gRPC-Web and CORS, so browsers can not reach
+    // it. Validation makes sure it is only served with authentication.
+    if let Some(admin_port) = settings.admin_port {
+        let admin_address = format!("{}:{}", settings.server_ip, admin_port).parse()?;
+        let admin_svc = AdminServer::with_interceptor(AdminService::new(pipelines.clone()), auth.clone());
+        let admin_reflection_svc = tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+            .build()?;
+        let mut admin_builder = Server::builder();
+        if let Some(tls) = server_tls_from_settings(&settings)? {
+            admin_builder = admin_builder.tls_config(tls)?;
+        }
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let server = admin_builder
+                .add_service(admin_reflection_svc)
+                .add_service(admin_svc)
+                .serve_with_shutdown(admin_address, shutdown.cancelled_owned());
+            if let Err(err) = server.await {
+                tracing::error!("admin service failed: {:?}", err);
+            }
+        });
+    }
+    let svc = OrderbookAggregatorServer::with_interceptor(orderbook_summary, auth);
 
     // Server uses IP:Port (SocketAddr)
-    let address = format!("{}:{}", config.get("server-ip").unwrap(), config.get("server-port").unwrap()).parse()?;
+    let address = format!("{}:{}", settings.server_ip, settings.server_port).parse()?;
 
     println!("{:?}", address);
 
-    Server::builder()
+    let mut builder = Server::builder();
+    if let Some(tls) = server_tls_from_settings(&settings)? {
+        builder = builder.tls_config(tls)?;
+    }
+
+    // HTTP/1 is accepted for gRPC-Web clients such as browsers.
+    let server = builder
+        .accept_http1(true)
+        .layer(gateway::cors_layer(settings.cors_origins()?))
+        .layer(GrpcWebLayer::new())
+        .add_service(health_svc)
+        .add_service(reflection_svc)
         .add_service(svc)
-        .serve(address)
-        .await?;
+        .serve_with_shutdown(address, shutdown.clone().cancelled_owned());
+    serve_until_drained(server, pipelines, shutdown, DRAIN_TIMEOUT).await?;
+    if let Some(recorder) = recorder {
+        recorder.flush().await?;
+    }
 
     Ok(())
 }
\ No newline at end of file
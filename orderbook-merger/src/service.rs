@@ -0,0 +1,192 @@
+use tokio::sync::watch;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use crate::{
+    auth::ClientPermissions,
+    orderbook_summary::{
+        orderbook_aggregator_server::OrderbookAggregator, BboRequest, BestBidOffer, Candle, CandlesRequest,
+        ExchangeBookRequest, ExchangeOrderBook, Summary, SummaryRequest, Trade, TradesRequest,
+    },
+    pipeline::Pipelines,
+    subscription::{
+        subscribe, subscribe_bbo, subscribe_candles, subscribe_exchange_book, subscribe_trades, BboOptions,
+        CandlesOptions, ExchangeBookOptions, SubscriptionOptions, TradesOptions,
+    },
+    AggregatedBooks, Symbol,
+};
+
+// The `OrderbookSummary` struct serves the merged books of the running pipelines. It is shared by
+// the gRPC service and the HTTP gateway so both validate and filter subscriptions the same way.
+#[derive(Debug, Clone)]
+pub struct OrderbookSummary {
+    // Symbol served to requests that do not name one.
+    symbol: Symbol,
+    pipelines: Pipelines,
+}
+
+impl OrderbookSummary {
+    pub fn new(symbol: Symbol, pipelines: Pipelines) -> Self {
+        Self { symbol, pipelines }
+    }
+
+    // The `subscription_options` function validates a request against the served symbols and,
+    // when the client was authenticated, against its permissions.
+    pub fn subscription_options(
+        &self,
+        request: &SummaryRequest,
+        permissions: Option<&ClientPermissions>,
+    ) -> Result<SubscriptionOptions, Status> {
+        let mut options = SubscriptionOptions::try_from(request)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let symbol = *options.symbol.get_or_insert(self.symbol);
+
+        if let Some(permissions) = permissions {
+            permissions
+                .authorize(&symbol, &mut options)
+                .map_err(|e| Status::permission_denied(e.to_string()))?;
+        }
+        self.books(symbol)?;
+        Ok(options)
+    }
+
+    fn books(&self, symbol: Symbol) -> Result<watch::Receiver<AggregatedBooks>, Status> {
+        self.pipelines
+            .books(symbol)
+            .ok_or_else(|| Status::not_found(format!("symbol {symbol} is not served")))
+    }
+
+    pub fn latest_summary(&self, options: &SubscriptionOptions) -> Result<Summary, Status> {
+        let books = self.books(options.symbol.unwrap_or(self.symbol))?;
+        let summary = options.summary(&books.borrow());
+        Ok(summary)
+    }
+
+    pub fn subscribe(&self, options: SubscriptionOptions) -> Result<ReceiverStream<Result<Summary, Status>>, Status> {
+        let books = self.books(options.symbol.unwrap_or(self.symbol))?;
+        Ok(subscribe(books, options))
+    }
+
+    // The `subscribe_exchange_book` function validates a request for the book of one exchange and
+    // streams that book to the subscriber.
+    pub fn subscribe_exchange_book(
+        &self,
+        request: &ExchangeBookRequest,
+        permissions: Option<&ClientPermissions>,
+    ) -> Result<ReceiverStream<Result<ExchangeOrderBook, Status>>, Status> {
+        let mut options = ExchangeBookOptions::try_from(request)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let symbol = *options.symbol.get_or_insert(self.symbol);
+        if let Some(permissions) = permissions {
+            permissions
+                .authorize_exchange_book(&symbol, &mut options)
+                .map_err(|e| Status::permission_denied(e.to_string()))?;
+        }
+        let books = self.books(symbol)?;
+        let book = self
+            .pipelines
+            .exchange_book(options.exchange, symbol)
+            .ok_or_else(|| Status::not_found(format!("{} {symbol} book is not served", options.exchange)))?;
+        Ok(subscribe_exchange_book(books, book, options))
+    }
+
+    // The `subscribe_trades` function validates a request for trades and streams the trades of
+    // the accepted exchanges to the subscriber.
+    pub fn subscribe_trades(
+        &self,
+        request: &TradesRequest,
+        permissions: Option<&ClientPermissions>,
+    ) -> Result<ReceiverStream<Result<Trade, Status>>, Status> {
+        let mut options = TradesOptions::try_from(request).map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let symbol = *options.symbol.get_or_insert(self.symbol);
+        if let Some(permissions) = permissions {
+            permissions
+                .authorize_venues(&symbol, &mut options.exchanges)
+                .map_err(|e| Status::permission_denied(e.to_string()))?;
+        }
+        let trades = self
+            .pipelines
+            .trades(symbol)
+            .ok_or_else(|| Status::not_found(format!("symbol {symbol} is not served")))?;
+        Ok(subscribe_trades(trades.subscribe(), options))
+    }
+
+    // The `subscribe_bbo` function validates a request for the best bid and offer and streams it
+    // to the subscriber.
+    pub fn subscribe_bbo(
+        &self,
+        request: &BboRequest,
+        permissions: Option<&ClientPermissions>,
+    ) -> Result<ReceiverStream<Result<BestBidOffer, Status>>, Status> {
+        let mut options = BboOptions::try_from(request).map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let symbol = *options.symbol.get_or_insert(self.symbol);
+        if let Some(permissions) = permissions {
+            permissions
+                .authorize_venues(&symbol, &mut options.exchanges)
+                .map_err(|e| Status::permission_denied(e.to_string()))?;
+        }
+        Ok(subscribe_bbo(self.books(symbol)?, options))
+    }
+
+    // The `subscribe_candles` function validates a request for bars and streams their history, then
+    // the new bars, to the subscriber.
+    pub fn subscribe_candles(
+        &self,
+        request: &CandlesRequest,
+        permissions: Option<&ClientPermissions>,
+    ) -> Result<ReceiverStream<Result<Candle, Status>>, Status> {
+        let options = CandlesOptions::try_from(request).map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let symbol = options.symbol.unwrap_or(self.symbol);
+        if let Some(permissions) = permissions {
+            permissions
+                .authorize_candles(&symbol, &options)
+                .map_err(|e| Status::permission_denied(e.to_string()))?;
+        }
+        let candles = self
+            .pipelines
+            .candles(symbol)
+            .ok_or_else(|| Status::not_found(format!("symbol {symbol} is not served")))?;
+        let (history, rx_candles) = candles.subscribe(options.series(), options.history);
+        Ok(subscribe_candles(history, rx_candles, options))
+    }
+}
+
+#[tonic::async_trait]
+impl OrderbookAggregator for OrderbookSummary {
+    type BookSummaryStream = ReceiverStream<Result<Summary, Status>>;
+    async fn book_summary(
+        &self,
+        request: Request<SummaryRequest>,
+    ) -> Result<Response<Self::BookSummaryStream>, Status> {
+        // Permissions are only attached when the server is configured with a key file.
+        let permissions = request.extensions().get::<ClientPermissions>();
+        let options = self.subscription_options(request.get_ref(), permissions)?;
+        Ok(Response::new(self.subscribe(options)?))
+    }
+
+    type ExchangeBookStream = ReceiverStream<Result<ExchangeOrderBook, Status>>;
+    async fn exchange_book(
+        &self,
+        request: Request<ExchangeBookRequest>,
+    ) -> Result<Response<Self::ExchangeBookStream>, Status> {
+        let permissions = request.extensions().get::<ClientPermissions>();
+        Ok(Response::new(self.subscribe_exchange_book(request.get_ref(), permissions)?))
+    }
+
+    type TradesStream = ReceiverStream<Result<Trade, Status>>;
+    async fn trades(&self, request: Request<TradesRequest>) -> Result<Response<Self::TradesStream>, Status> {
+        let permissions = request.extensions().get::<ClientPermissions>();
+        Ok(Response::new(self.subscribe_trades(request.get_ref(), permissions)?))
+    }
+
+    type BboStream = ReceiverStream<Result<BestBidOffer, Status>>;
+    async fn bbo(&self, request: Request<BboRequest>) -> Result<Response<Self::BboStream>, Status> {
+        let permissions = request.extensions().get::<ClientPermissions>();
+        Ok(Response::new(self.subscribe_bbo(request.get_ref(), permissions)?))
+    }
+
+    type CandlesStream = ReceiverStream<Result<Candle, Status>>;
+    async fn candles(&self, request: Request<CandlesRequest>) -> Result<Response<Self::CandlesStream>, Status> {
+        let permissions = request.extensions().get::<ClientPermissions>();
+        Ok(Response::new(self.subscribe_candles(request.get_ref(), permissions)?))
+    }
+}
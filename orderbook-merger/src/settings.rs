@@ -0,0 +1,439 @@
+use anyhow::{bail, ensure, Context, Result};
+use axum::http::HeaderValue;
+use serde::Deserialize;
+use std::{collections::{HashMap, HashSet}, path::{Path, PathBuf}, time::Duration};
+use url::Url;
+use crate::{
+    exchanges::{binance::Binance, bitstamp::Bitstamp, exchange::{ConnectorConfig, Exchange}},
+    pipeline::PipelineConfig,
+    recorder::RecorderConfig,
+    replay::ReplayConfig,
+    ExchangeName, Symbol,
+};
+
+// Settings are read from this file when no `--config` flag is given. The path is absolute, so the
+// binaries find it whatever directory they are started from.
+pub const DEFAULT_CONFIG_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/setting.toml");
+// Environment variables starting with this prefix override the configuration file, e.g.
+// `ORDERBOOK_SERVER_PORT=5560` or `ORDERBOOK_EXCHANGES__BITSTAMP__ENABLED=false`.
+const ENV_PREFIX: &str = "ORDERBOOK_";
+
+// The `ExchangeSettings` struct configures the connector of one exchange.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ExchangeSettings {
+    // Whether connectors for this exchange are started with the server. Disabled exchanges can
+    // still be started through the admin service.
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    pub rest_url: String,
+    pub websocket_url: String,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl ExchangeSettings {
+    fn from_config(config: &ConnectorConfig) -> Self {
+        Self {
+            enabled: true,
+            rest_url: config.rest_url.to_string(),
+            websocket_url: config.websocket_url.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct ExchangesSettings {
+    pub binance: ExchangeSettings,
+    pub bitstamp: ExchangeSettings,
+}
+
+impl Default for ExchangesSettings {
+    fn default() -> Self {
+        Self {
+            binance: ExchangeSettings::from_config(&Binance::default_config()),
+            bitstamp: ExchangeSettings::from_config(&Bitstamp::default_config()),
+        }
+    }
+}
+
+impl ExchangesSettings {
+    pub fn get(&self, exchange: ExchangeName) -> &ExchangeSettings {
+        match exchange {
+            ExchangeName::BINANCE => &self.binance,
+            ExchangeName::BITSTAMP => &self.bitstamp,
+        }
+    }
+
+    pub fn enabled(&self) -> Vec<ExchangeName> {
+        [ExchangeName::BINANCE, ExchangeName::BITSTAMP]
+            .into_iter()
+            .filter(|exchange| self.get(*exchange).enabled)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct ChannelSettings {
+    // Capacity of the channel between the connectors and the aggregation of a symbol.
+    pub orderbook_levels: usize,
+    // Capacity of the channel between the websocket reader and the book of each connector.
+    pub updates: usize,
+    // Number of trades of a symbol kept for subscribers that fall behind.
+    pub trades: usize,
+}
+
+impl Default for ChannelSettings {
+    fn default() -> Self {
+        Self {
+            orderbook_levels: 20,
+            updates: 100,
+            trades: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct LoggingSettings {
+    // One of `trace`, `debug`, `info`, `warn` or `error`.
+    pub level: String,
+    pub line_number: bool,
+}
+
+impl Default for LoggingSettings {
+    fn default() -> Self {
+        Self {
+            level: "debug".to_string(),
+            line_number: true,
+        }
+    }
+}
+
+impl LoggingSettings {
+    pub fn level(&self) -> Result<tracing::Level> {
+        self.level
+            .parse()
+            .map_err(|_| anyhow::anyhow!("unknown log level: {}", self.level))
+    }
+}
+
+// The `RecorderSettings` struct enables the raw market data recorder.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct RecorderSettings {
+    pub directory: PathBuf,
+    pub max_file_mb: u64,
+    pub max_file_secs: u64,
+    pub channel_size: usize,
+}
+
+impl Default for RecorderSettings {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("recordings"),
+            max_file_mb: 100,
+            max_file_secs: 3600,
+            channel_size: 10_000,
+        }
+    }
+}
+
+impl RecorderSettings {
+    pub fn recorder_config(&self) -> RecorderConfig {
+        RecorderConfig {
+            directory: self.directory.clone(),
+            max_file_bytes: self.max_file_mb * 1024 * 1024,
+            max_file_age: Duration::from_secs(self.max_file_secs),
+            channel_size: self.channel_size,
+        }
+    }
+}
+
+// The `ReplaySettings` struct replays a recording instead of connecting to the exchanges.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct ReplaySettings {
+    // A recording file, or a directory of recording files replayed in name order.
+    pub path: PathBuf,
+    // 1.0 replays at the recorded pace, 10.0 ten times faster and 0.0 as fast as possible.
+    pub speed: f64,
+}
+
+impl Default for ReplaySettings {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("recordings"),
+            speed: 1.0,
+        }
+    }
+}
+
+// The `Settings` struct is the configuration shared by the server and the clients. Every field
+// has a default, so a file only needs the settings it changes.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Settings {
+    pub server_ip: String,
+    pub server_port: u16,
+    pub http_port: Option<u16>,
+    pub ws_port: Option<u16>,
+    pub metrics_port: Option<u16>,
+    // The admin service is served on its own port, and only with `auth-tokens`.
+    pub admin_port: Option<u16>,
+
+    // Symbols a pipeline is started for, and the one served to requests that do not name one.
+    pub symbols: Vec<Symbol>,
+    pub default_symbol: Option<Symbol>,
+    // Number of bid and ask levels of each exchange's book merged into the summary.
+    pub depth: usize,
+    // Exchanges that have not sent a book for this long are left out of the merged book and make
+    // the server report NOT_SERVING.
+    pub stale_after_secs: u64,
+    // Number of closed bars of each interval kept for each exchange and the consolidated book.
+    pub candle_history: usize,
+    pub exchanges: ExchangesSettings,
+    pub channels: ChannelSettings,
+    pub logging: LoggingSettings,
+    // Raw messages and snapshots are only recorded when a `[recorder]` table is present.
+    pub recorder: Option<RecorderSettings>,
+    // When a `[replay]` table is present the server serves a recording instead of live data.
+    pub replay: Option<ReplaySettings>,
+
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub tls_client_ca: Option<PathBuf>,
+    pub tls_ca: Option<PathBuf>,
+    pub tls_domain: Option<String>,
+    pub tls_client_cert: Option<PathBuf>,
+    pub tls_client_key: Option<PathBuf>,
+
+    pub auth_tokens: Option<PathBuf>,
+    pub auth_token: Option<String>,
+    // Origins of the web pages allowed to call the gRPC-Web service and the JSON gateway, e.g.
+    // "https://dashboard.example.com".
+    pub cors_origins: Vec<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            server_ip: "127.0.0.1".to_string(),
+            server_port: 5556,
+            http_port: None,
+            ws_port: None,
+            metrics_port: None,
+            admin_port: None,
+            symbols: vec![Symbol::ETHUSDT],
+            default_symbol: None,
+            depth: 10,
+            stale_after_secs: 10,
+            candle_history: 300,
+            exchanges: ExchangesSettings::default(),
+            channels: ChannelSettings::default(),
+            logging: LoggingSettings::default(),
+            recorder: None,
+            replay: None,
+            tls_cert: None,
+            tls_key: None,
+            tls_client_ca: None,
+            tls_ca: None,
+            tls_domain: None,
+            tls_client_cert: None,
+            tls_client_key: None,
+            auth_tokens: None,
+            auth_token: None,
+            cors_origins: Vec::new(),
+        }
+    }
+}
+
+// The `env_overrides` function maps `ORDERBOOK_` environment variables to setting keys: `__`
+// separates nested tables and `_` stands for `-`, so `ORDERBOOK_CHANNELS__ORDERBOOK_LEVELS`
+// overrides `orderbook-levels` in the `[channels]` table.
+pub fn env_overrides(vars: impl Iterator<Item = (String, String)>) -> HashMap<String, String> {
+    vars.filter_map(|(name, value)| {
+        let key = name
+            .strip_prefix(ENV_PREFIX)?
+            .split("__")
+            .map(|part| part.to_lowercase().replace('_', "-"))
+            .collect::<Vec<_>>()
+            .join(".");
+        Some((key, value))
+    })
+    .collect()
+}
+
+impl Settings {
+    // The `load` function reads the settings from `path`, applies the environment overrides and
+    // validates the result.
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::load_with_env(path, std::env::vars())
+    }
+
+    // The `load_with_env` function is `load` with the environment variables `vars`.
+    pub fn load_with_env(path: &Path, vars: impl Iterator<Item = (String, String)>) -> Result<Self> {
+        let mut builder = config::Config::builder().add_source(config::File::from(path));
+        for (key, value) in env_overrides(vars) {
+            builder = builder
+                .set_override(key.as_str(), value)
+                .with_context(|| format!("Invalid environment override for {key}"))?;
+        }
+        let settings = builder
+            .build()
+            .with_context(|| format!("Failed to read settings from {}", path.display()))?
+            .try_deserialize::<Settings>()
+            .context("Failed to deserialize settings")?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    // The `path_from_args` function returns the path given with `--config <path>`, or
+    // `DEFAULT_CONFIG_PATH`.
+    pub fn path_from_args() -> Result<PathBuf> {
+        Self::parse_config_path(std::env::args().skip(1))
+    }
+
+    // The `parse_config_path` function is `path_from_args` for the arguments `args`, without the
+    // program name.
+    pub fn parse_config_path(mut args: impl Iterator<Item = String>) -> Result<PathBuf> {
+        let mut path = PathBuf::from(DEFAULT_CONFIG_PATH);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" | "-c" => path = args.next().context("--config needs a path")?.into(),
+                _ => match arg.strip_prefix("--config=") {
+                    Some(value) => path = value.into(),
+                    None => bail!("unknown argument: {arg}"),
+                },
+            }
+        }
+        Ok(path)
+    }
+
+    pub fn from_args() -> Result<Self> {
+        Self::load(&Self::path_from_args()?)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        ensure!(!self.symbols.is_empty(), "at least one symbol must be configured");
+        if let Some(symbol) = self.default_symbol {
+            ensure!(self.symbols.contains(&symbol), "default-symbol {symbol} is not in symbols");
+        }
+        ensure!(!self.exchanges.enabled().is_empty(), "at least one exchange must be enabled");
+        ensure!(self.depth > 0, "depth must be greater than 0");
+        ensure!(self.stale_after_secs > 0, "stale-after-secs must be greater than 0");
+        ensure!(self.candle_history > 0, "candle-history must be greater than 0");
+        ensure!(self.channels.orderbook_levels > 0, "channels.orderbook-levels must be greater than 0");
+        ensure!(self.channels.updates > 0, "channels.updates must be greater than 0");
+        ensure!(self.channels.trades > 0, "channels.trades must be greater than 0");
+        self.logging.level()?;
+        if let Some(recorder) = &self.recorder {
+            ensure!(recorder.max_file_mb > 0, "recorder.max-file-mb must be greater than 0");
+            ensure!(recorder.max_file_secs > 0, "recorder.max-file-secs must be greater than 0");
+            ensure!(recorder.channel_size > 0, "recorder.channel-size must be greater than 0");
+        }
+        if let Some(replay) = &self.replay {
+            ensure!(
+                replay.speed.is_finite() && replay.speed >= 0.0,
+                "replay.speed must be 0 or greater"
+            );
+        }
+        self.connector_configs()?;
+
+        let mut ports = vec![self.server_port];
+        for port in [self.http_port, self.ws_port, self.metrics_port, self.admin_port].into_iter().flatten() {
+            ensure!(!ports.contains(&port), "port {port} is configured more than once");
+            ports.push(port);
+        }
+        ensure!(
+            self.tls_cert.is_some() == self.tls_key.is_some(),
+            "tls-cert and tls-key must be set together"
+        );
+        self.cors_origins()?;
+        ensure!(
+            self.admin_port.is_none() || self.auth_tokens.is_some(),
+            "admin-port requires auth-tokens"
+        );
+        // Without a certificate the server would run plaintext, silently dropping the mutual TLS asked for.
+        ensure!(
+            self.tls_client_ca.is_none() || self.tls_cert.is_some(),
+            "tls-client-ca requires tls-cert and tls-key"
+        );
+        ensure!(
+            self.tls_client_cert.is_some() == self.tls_client_key.is_some(),
+            "tls-client-cert and tls-client-key must be set together"
+        );
+        Ok(())
+    }
+
+    pub fn cors_origins(&self) -> Result<Vec<HeaderValue>> {
+        self.cors_origins
+            .iter()
+            .map(|origin| HeaderValue::from_str(origin).with_context(|| format!("invalid cors origin: {origin}")))
+            .collect()
+    }
+
+    pub fn default_symbol(&self) -> Symbol {
+        self.default_symbol.unwrap_or(self.symbols[0])
+    }
+
+    // The `connectors` function lists the connector of every enabled exchange for every symbol.
+    pub fn connectors(&self) -> HashSet<(ExchangeName, Symbol)> {
+        self.symbols
+            .iter()
+            .flat_map(|symbol| self.exchanges.enabled().into_iter().map(|exchange| (exchange, *symbol)))
+            .collect()
+    }
+
+    pub fn stale_after(&self) -> Duration {
+        Duration::from_secs(self.stale_after_secs)
+    }
+
+    // The `connector_configs` function builds the connector configuration of every exchange,
+    // including disabled ones so they can be started at runtime.
+    pub fn connector_configs(&self) -> Result<HashMap<ExchangeName, ConnectorConfig>> {
+        [ExchangeName::BINANCE, ExchangeName::BITSTAMP]
+            .into_iter()
+            .map(|exchange| {
+                let settings = self.exchanges.get(exchange);
+                // Endpoints are joined with relative paths, which needs a trailing slash.
+                let parse = |url: &str| {
+                    let url = if url.ends_with('/') { url.to_string() } else { format!("{url}/") };
+                    Url::parse(&url).with_context(|| format!("invalid {exchange} url: {url}"))
+                };
+                let config = ConnectorConfig {
+                    rest_url: parse(&settings.rest_url)?,
+                    websocket_url: parse(&settings.websocket_url)?,
+                    depth: self.depth,
+                    update_channel_size: self.channels.updates,
+                    recorder: None,
+                    book: None,
+                    trades: None,
+                };
+                Ok((exchange, config))
+            })
+            .collect()
+    }
+
+    pub fn replay_config(&self) -> Option<ReplayConfig> {
+        self.replay.as_ref().map(|replay| ReplayConfig {
+            path: replay.path.clone(),
+            speed: replay.speed,
+            depth: self.depth,
+        })
+    }
+
+    pub fn pipeline_config(&self) -> Result<PipelineConfig> {
+        Ok(PipelineConfig {
+            stale_after: self.stale_after(),
+            channel_size: self.channels.orderbook_levels,
+            trade_channel_size: self.channels.trades,
+            candle_history: self.candle_history,
+            connectors: self.connector_configs()?,
+        })
+    }
+}
@@ -0,0 +1,425 @@
+use anyhow::Result;
+use std::{collections::HashSet, sync::Arc, time::Duration};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream, ReceiverStream},
+    Stream, StreamExt,
+};
+use tonic::Status;
+use crate::{
+    candles::SeriesKey,
+    metrics,
+    orderbook::orderbook::{BookSnapshot, PublishedBook},
+    orderbook_summary::{
+        BboRequest, BestBidOffer, Candle, CandleInterval, CandlesRequest, Conflation, ExchangeBookRequest,
+        ExchangeOrderBook, Level, Summary, SummaryRequest, Trade, TradesRequest,
+    },
+    AggregatedBooks, ExchangeName, Symbol,
+};
+
+// The `ExchangeFilter` struct selects which exchanges' books a subscriber's summary is built from.
+// An empty `include` set means every exchange that is not excluded.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExchangeFilter {
+    pub include: HashSet<ExchangeName>,
+    pub exclude: HashSet<ExchangeName>,
+}
+
+impl ExchangeFilter {
+    // The `parse` function parses the exchanges of a request by name, e.g. "BINANCE".
+    pub fn parse(include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(Self {
+            include: include.iter().map(|exchange| exchange.parse()).collect::<Result<_>>()?,
+            exclude: exclude.iter().map(|exchange| exchange.parse()).collect::<Result<_>>()?,
+        })
+    }
+
+    pub fn accepts(&self, exchange: &ExchangeName) -> bool {
+        (self.include.is_empty() || self.include.contains(exchange)) && !self.exclude.contains(exchange)
+    }
+}
+
+// The `SubscriptionOptions` struct holds the delivery preferences of a single `BookSummary`
+// subscriber: how often it may receive summaries and which changes it cares about.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubscriptionOptions {
+    // Symbol of the book, `None` means the symbol the server runs with.
+    pub symbol: Option<Symbol>,
+    pub min_interval: Option<Duration>,
+    pub conflation: Conflation,
+    pub top_n: usize,
+    pub exchanges: ExchangeFilter,
+    // Number of bid and ask levels sent, 0 sends every level of the merged book.
+    pub depth: usize,
+}
+
+impl Default for SubscriptionOptions {
+    fn default() -> Self {
+        Self {
+            symbol: None,
+            min_interval: None,
+            conflation: Conflation::EveryUpdate,
+            top_n: 0,
+            exchanges: ExchangeFilter::default(),
+            depth: 0,
+        }
+    }
+}
+
+fn min_interval(max_updates_per_second: u32) -> Option<Duration> {
+    match max_updates_per_second {
+        0 => None,
+        rate => Some(Duration::from_secs(1) / rate),
+    }
+}
+
+fn parse_symbol(symbol: &str) -> Result<Option<Symbol>> {
+    match symbol {
+        "" => Ok(None),
+        symbol => Ok(Some(symbol.parse()?)),
+    }
+}
+
+impl TryFrom<&SummaryRequest> for SubscriptionOptions {
+    type Error = anyhow::Error;
+
+    fn try_from(request: &SummaryRequest) -> Result<Self> {
+        let min_interval = min_interval(request.max_updates_per_second);
+        let exchanges = ExchangeFilter::parse(&request.include_exchanges, &request.exclude_exchanges)?;
+        let symbol = parse_symbol(&request.symbol)?;
+        Ok(Self {
+            symbol,
+            min_interval,
+            // Unknown values sent by newer clients fall back to forwarding every update.
+            conflation: Conflation::from_i32(request.conflation).unwrap_or(Conflation::EveryUpdate),
+            top_n: request.top_n as usize,
+            exchanges,
+            depth: request.depth as usize,
+        })
+    }
+}
+
+// The `ExchangeBookOptions` struct holds what a single `ExchangeBook` subscriber asked for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExchangeBookOptions {
+    pub exchange: ExchangeName,
+    // Symbol of the book, `None` means the symbol the server runs with.
+    pub symbol: Option<Symbol>,
+    // Number of bid and ask levels sent, 0 sends every level of the book.
+    pub depth: usize,
+    pub min_interval: Option<Duration>,
+}
+
+impl TryFrom<&ExchangeBookRequest> for ExchangeBookOptions {
+    type Error = anyhow::Error;
+
+    fn try_from(request: &ExchangeBookRequest) -> Result<Self> {
+        Ok(Self {
+            exchange: request.exchange.parse()?,
+            symbol: parse_symbol(&request.symbol)?,
+            depth: request.depth as usize,
+            min_interval: min_interval(request.max_updates_per_second),
+        })
+    }
+}
+
+impl ExchangeBookOptions {
+    // The `exchange_book` function builds the message this subscriber sees from a snapshot.
+    pub fn exchange_book(&self, snapshot: &BookSnapshot) -> Result<ExchangeOrderBook> {
+        let (bids, asks) = snapshot.display_levels(self.depth)?;
+        Ok(ExchangeOrderBook {
+            exchange: snapshot.exchange.to_string(),
+            symbol: snapshot.symbol.to_string(),
+            last_update_id: snapshot.last_update_id,
+            bids,
+            asks,
+        })
+    }
+}
+
+// The `TradesOptions` struct holds what a single `Trades` subscriber asked for.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TradesOptions {
+    // Symbol of the trades, `None` means the symbol the server runs with.
+    pub symbol: Option<Symbol>,
+    pub exchanges: ExchangeFilter,
+}
+
+impl TryFrom<&TradesRequest> for TradesOptions {
+    type Error = anyhow::Error;
+
+    fn try_from(request: &TradesRequest) -> Result<Self> {
+        Ok(Self {
+            symbol: parse_symbol(&request.symbol)?,
+            exchanges: ExchangeFilter::parse(&request.include_exchanges, &request.exclude_exchanges)?,
+        })
+    }
+}
+
+impl TradesOptions {
+    pub fn accepts(&self, trade: &Trade) -> bool {
+        trade.exchange.parse().is_ok_and(|exchange| self.exchanges.accepts(&exchange))
+    }
+}
+
+// The `BboOptions` struct holds what a single `Bbo` subscriber asked for.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BboOptions {
+    // Symbol of the book, `None` means the symbol the server runs with.
+    pub symbol: Option<Symbol>,
+    pub exchanges: ExchangeFilter,
+    pub min_interval: Option<Duration>,
+}
+
+impl TryFrom<&BboRequest> for BboOptions {
+    type Error = anyhow::Error;
+
+    fn try_from(request: &BboRequest) -> Result<Self> {
+        Ok(Self {
+            symbol: parse_symbol(&request.symbol)?,
+            exchanges: ExchangeFilter::parse(&request.include_exchanges, &request.exclude_exchanges)?,
+            min_interval: min_interval(request.max_updates_per_second),
+        })
+    }
+}
+
+// The `CandlesOptions` struct holds what a single `Candles` subscriber asked for.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CandlesOptions {
+    // Symbol of the bars, `None` means the symbol the server runs with.
+    pub symbol: Option<Symbol>,
+    // Exchange of the bars, `None` means the consolidated bars.
+    pub exchange: Option<ExchangeName>,
+    pub interval: CandleInterval,
+    // Number of closed bars sent first, 0 sends every bar kept.
+    pub history: usize,
+}
+
+impl TryFrom<&CandlesRequest> for CandlesOptions {
+    type Error = anyhow::Error;
+
+    fn try_from(request: &CandlesRequest) -> Result<Self> {
+        let exchange = if request.exchange.is_empty() { None } else { Some(request.exchange.parse()?) };
+        Ok(Self {
+            symbol: parse_symbol(&request.symbol)?,
+            exchange,
+            interval: CandleInterval::from_i32(request.interval)
+                .ok_or_else(|| anyhow::anyhow!("unknown candle interval: {}", request.interval))?,
+            history: request.history as usize,
+        })
+    }
+}
+
+impl CandlesOptions {
+    pub fn series(&self) -> SeriesKey {
+        (self.exchange, self.interval)
+    }
+
+    pub fn accepts(&self, candle: &Candle) -> bool {
+        candle.interval == self.interval as i32
+            && candle.exchange == self.exchange.map(|exchange| exchange.to_string()).unwrap_or_default()
+    }
+}
+
+// The `parse_conflation` function parses a conflation mode by its proto name, e.g. "TOP_OF_BOOK",
+// for the JSON front-ends.
+pub fn parse_conflation(name: Option<&str>) -> Result<Conflation, Status> {
+    match name {
+        None => Ok(Conflation::EveryUpdate),
+        Some(name) => Conflation::from_str_name(&name.to_uppercase())
+            .ok_or_else(|| Status::invalid_argument(format!("unknown conflation: {name}"))),
+    }
+}
+
+impl SubscriptionOptions {
+    // The `summary` function builds the summary this subscriber sees from the published books.
+    pub fn summary(&self, books: &AggregatedBooks) -> Summary {
+        let mut summary = books.summary_for(|exchange| self.exchanges.accepts(exchange));
+        if self.depth > 0 {
+            summary.bids.truncate(self.depth);
+            summary.asks.truncate(self.depth);
+        }
+        summary
+    }
+
+    // The `is_relevant_change` function decides whether `next` should be forwarded to a subscriber
+    // whose last delivered summary was `last`.
+    pub fn is_relevant_change(&self, last: &Summary, next: &Summary) -> bool {
+        match self.conflation {
+            Conflation::EveryUpdate => true,
+            Conflation::TopOfBook => !levels_eq(last, next, 1),
+            Conflation::TopNLevels => {
+                let depth = if self.top_n == 0 { usize::MAX } else { self.top_n };
+                !levels_eq(last, next, depth)
+            }
+        }
+    }
+}
+
+fn levels_eq(a: &Summary, b: &Summary, depth: usize) -> bool {
+    fn side_eq(a: &[Level], b: &[Level], depth: usize) -> bool {
+        a.iter().take(depth).eq(b.iter().take(depth))
+    }
+    side_eq(&a.bids, &b.bids, depth) && side_eq(&a.asks, &b.asks, depth)
+}
+
+// The `spawn_subscriber` function spawns a task that forwards `source` to a single subscriber,
+// counted in `metrics::SUBSCRIBERS` until it goes away, waiting `min_interval` after each message.
+// The sources end when their pipeline is dropped because the server is shutting down, which ends
+// the stream with an UNAVAILABLE status.
+fn spawn_subscriber<T, S>(source: S, min_interval: Option<Duration>) -> ReceiverStream<Result<T, Status>>
+where
+    T: Send + 'static,
+    S: Stream<Item = Result<T, Status>> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(1);
+
+    tokio::spawn(async move {
+        metrics::SUBSCRIBERS.inc();
+        tokio::pin!(source);
+        loop {
+            let next = tokio::select! {
+                next = source.next() => next,
+                _ = tx.closed() => break,
+            };
+            let Some(next) = next else {
+                let _ = tx.send(Err(Status::unavailable("server is shutting down"))).await;
+                break;
+            };
+            if tx.send(next).await.is_err() {
+                // The subscriber went away.
+                break;
+            }
+            if let Some(min_interval) = min_interval {
+                tokio::time::sleep(min_interval).await;
+            }
+        }
+        metrics::SUBSCRIBERS.dec();
+    });
+
+    ReceiverStream::new(rx)
+}
+
+// The `book_changes` function streams what `next` builds from the books of a pipeline, first from
+// the current books and then every time they change. Books `next` returns None for, e.g. because
+// nothing the subscriber asked for changed, are skipped. Books published while the subscriber is
+// throttled or busy are conflated by the watch channel, so it always gets the latest books.
+fn book_changes<T, F>(rx_books: watch::Receiver<AggregatedBooks>, next: F) -> impl Stream<Item = Result<T, Status>>
+where
+    F: FnMut(&AggregatedBooks) -> Option<Result<T, Status>>,
+{
+    futures::stream::unfold((rx_books, next, false), |(mut rx_books, mut next, mut changed)| async move {
+        loop {
+            if changed && rx_books.changed().await.is_err() {
+                return None;
+            }
+            changed = true;
+            let item = next(&rx_books.borrow_and_update());
+            if let Some(item) = item {
+                return Some((item, (rx_books, next, changed)));
+            }
+        }
+    })
+}
+
+// The `broadcast_items` function streams the items of a broadcast channel accepted by `accepts`.
+// A subscriber that falls too far behind misses the oldest `kind`, which is logged.
+fn broadcast_items<T, F>(rx: broadcast::Receiver<T>, kind: &'static str, accepts: F) -> impl Stream<Item = Result<T, Status>>
+where
+    T: Clone + Send + 'static,
+    F: Fn(&T) -> bool,
+{
+    BroadcastStream::new(rx).filter_map(move |item| match item {
+        Ok(item) => accepts(&item).then_some(Ok(item)),
+        Err(BroadcastStreamRecvError::Lagged(missed)) => {
+            tracing::warn!("subscriber fell behind and missed {} {}", missed, kind);
+            None
+        }
+    })
+}
+
+// The `subscribe` function streams summaries built from the books of a pipeline to a single
+// subscriber, applying its exchange filter, depth, rate limit and conflation mode.
+pub fn subscribe(
+    rx_books: watch::Receiver<AggregatedBooks>,
+    options: SubscriptionOptions,
+) -> ReceiverStream<Result<Summary, Status>> {
+    let min_interval = options.min_interval;
+    let mut last_sent: Option<Summary> = None;
+    let summaries = book_changes(rx_books, move |books| {
+        let next = options.summary(books);
+        if last_sent.as_ref().is_some_and(|last| !options.is_relevant_change(last, &next)) {
+            return None;
+        }
+        last_sent = Some(next.clone());
+        Some(Ok(next))
+    });
+    spawn_subscriber(summaries, min_interval)
+}
+
+// The `subscribe_exchange_book` function streams the book published by one connector to a single
+// subscriber. The pipeline's books only change after a connector published its book, so they tell
+// when to load it again; snapshots that were already sent, or published before the first snapshot
+// was applied, are skipped.
+pub fn subscribe_exchange_book(
+    rx_books: watch::Receiver<AggregatedBooks>,
+    book: PublishedBook,
+    options: ExchangeBookOptions,
+) -> ReceiverStream<Result<ExchangeOrderBook, Status>> {
+    let min_interval = options.min_interval;
+    let mut last_sent: Option<Arc<BookSnapshot>> = None;
+    let books = book_changes(rx_books, move |_| {
+        let snapshot = book.load();
+        if snapshot.last_update_id == 0 || last_sent.as_ref().is_some_and(|last| Arc::ptr_eq(last, &snapshot)) {
+            return None;
+        }
+        let next = options.exchange_book(&snapshot).map_err(|e| Status::internal(format!("{e:#}")));
+        last_sent = Some(snapshot);
+        Some(next)
+    });
+    spawn_subscriber(books, min_interval)
+}
+
+// The `subscribe_trades` function streams the trades of the accepted exchanges to a single
+// subscriber, in the order they were received. Trades are never conflated.
+pub fn subscribe_trades(
+    rx_trades: broadcast::Receiver<Trade>,
+    options: TradesOptions,
+) -> ReceiverStream<Result<Trade, Status>> {
+    spawn_subscriber(broadcast_items(rx_trades, "trades", move |trade| options.accepts(trade)), None)
+}
+
+// The `subscribe_bbo` function streams the best bid and offer of the accepted exchanges to a single
+// subscriber, whenever the top of the merged book or of one of the exchanges changes. Nothing is
+// sent until one of the accepted exchanges has a bid or an ask.
+pub fn subscribe_bbo(
+    rx_books: watch::Receiver<AggregatedBooks>,
+    options: BboOptions,
+) -> ReceiverStream<Result<BestBidOffer, Status>> {
+    let min_interval = options.min_interval;
+    let mut last_sent: Option<BestBidOffer> = None;
+    let offers = book_changes(rx_books, move |books| {
+        let next = books.best_bid_offer_for(|exchange| options.exchanges.accepts(exchange));
+        let unchanged = match &last_sent {
+            Some(last) => *last == next,
+            None => next.best_bid.is_none() && next.best_ask.is_none(),
+        };
+        if unchanged {
+            return None;
+        }
+        last_sent = Some(next.clone());
+        Some(Ok(next))
+    });
+    spawn_subscriber(offers, min_interval)
+}
+
+// The `subscribe_candles` function streams the requested history of a series of bars to a single
+// subscriber, then every bar of the series as it closes.
+pub fn subscribe_candles(
+    history: Vec<Candle>,
+    rx_candles: broadcast::Receiver<Candle>,
+    options: CandlesOptions,
+) -> ReceiverStream<Result<Candle, Status>> {
+    let closed = broadcast_items(rx_candles, "bars", move |candle| options.accepts(candle));
+    spawn_subscriber(futures::stream::iter(history.into_iter().map(Ok)).chain(closed), None)
+}
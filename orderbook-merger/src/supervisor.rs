@@ -0,0 +1,264 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+use crate::{
+    exchanges::{binance::{self, Binance}, bitstamp::{self, Bitstamp}, exchange::{ConnectorConfig, Exchange}},
+    metrics,
+    orderbook::orderbook::{OrderBookOnlyLevels, Update},
+    ExchangeName, Symbol,
+};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// A connector that ran at least this long before failing starts again from the minimum backoff.
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+// A stopped connector that has not closed its websocket within this time is aborted.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The `Backoff` struct returns the delays before each restart of a connector, doubling from
+// `MIN_BACKOFF` up to `MAX_BACKOFF`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    next: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self { next: MIN_BACKOFF }
+    }
+}
+
+impl Backoff {
+    // The `delay` function returns how long to wait after a connector failed having run for
+    // `ran_for`. A connector that ran for `STABLE_AFTER` succeeded, so it starts over from
+    // `MIN_BACKOFF`.
+    pub fn delay(&mut self, ran_for: Duration) -> Duration {
+        if ran_for >= STABLE_AFTER {
+            self.next = MIN_BACKOFF;
+        }
+        let delay = self.next;
+        self.next = (self.next * 2).min(MAX_BACKOFF);
+        delay
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectorState {
+    // Fetching scales, connecting the websocket and fetching the snapshot.
+    Starting,
+    // The snapshot has been applied and updates are streaming.
+    Running,
+    // Waiting for the backoff to elapse after a failure.
+    Restarting,
+    Stopped,
+}
+
+impl std::fmt::Display for ConnectorState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConnectorState::Starting => write!(f, "STARTING"),
+            ConnectorState::Running => write!(f, "RUNNING"),
+            ConnectorState::Restarting => write!(f, "RESTARTING"),
+            ConnectorState::Stopped => write!(f, "STOPPED"),
+        }
+    }
+}
+
+// The `ConnectorStatus` struct describes one exchange connector owned by the supervisor.
+#[derive(Debug, Clone)]
+pub struct ConnectorStatus {
+    pub exchange: ExchangeName,
+    pub symbol: Symbol,
+    pub state: ConnectorState,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+type ConnectorKey = (ExchangeName, Symbol);
+
+// A supervised connector task and the token that asks it to stop.
+#[derive(Debug)]
+struct Connector {
+    task: JoinHandle<()>,
+    stop: CancellationToken,
+}
+type Statuses = Arc<Mutex<HashMap<ConnectorKey, ConnectorStatus>>>;
+
+fn set_state(statuses: &Statuses, key: ConnectorKey, state: ConnectorState) {
+    if let Some(status) = statuses.lock().unwrap().get_mut(&key) {
+        if status.state != state {
+            tracing::info!("{} {} connector is {}", key.0, key.1, state);
+        }
+        status.state = state;
+    }
+    let up = if state == ConnectorState::Running { 1 } else { 0 };
+    metrics::CONNECTOR_UP
+        .with_label_values(&[&key.0.to_string(), &key.1.to_string()])
+        .set(up);
+}
+
+// The `Supervisor` owns the exchange connector tasks. Connectors that fail, at startup or while
+// running, are restarted with an exponential backoff, so the server keeps running in degraded mode
+// while some exchanges are unavailable.
+#[derive(Debug, Default)]
+pub struct Supervisor {
+    statuses: Statuses,
+    tasks: HashMap<ConnectorKey, Connector>,
+    // Cancelled on shutdown, which lets every connector close its websocket and stop. Each
+    // connector is stopped on its own through a child token.
+    shutdown: CancellationToken,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The `spawn` function starts a supervised connector sending the book levels of `exchange` for
+    // `symbol` to `tx_orderbook`. It returns false when that connector is already running.
+    pub fn spawn(
+        &mut self,
+        exchange: ExchangeName,
+        symbol: Symbol,
+        config: ConnectorConfig,
+        tx_orderbook: mpsc::Sender<OrderBookOnlyLevels>,
+    ) -> bool {
+        let key = (exchange, symbol);
+        if self.tasks.get(&key).is_some_and(|connector| !connector.task.is_finished()) {
+            return false;
+        }
+        self.statuses.lock().unwrap().insert(
+            key,
+            ConnectorStatus {
+                exchange,
+                symbol,
+                state: ConnectorState::Starting,
+                restarts: 0,
+                last_error: None,
+            },
+        );
+        let statuses = self.statuses.clone();
+        let stop = self.shutdown.child_token();
+        let shutdown = stop.clone();
+        let task = match exchange {
+            ExchangeName::BINANCE => tokio::spawn(
+                supervise::<binance::Snapshot, binance::BookUpdate, Binance>(exchange, symbol, config, tx_orderbook, statuses, shutdown),
+            ),
+            ExchangeName::BITSTAMP => tokio::spawn(
+                supervise::<bitstamp::Snapshot, bitstamp::BookUpdate, Bitstamp>(exchange, symbol, config, tx_orderbook, statuses, shutdown),
+            ),
+        };
+        self.tasks.insert(key, Connector { task, stop });
+        true
+    }
+
+    // The `stop` function asks a connector to unsubscribe and close its websocket. It returns a
+    // task that finishes once the connector stopped, or was aborted after `STOP_TIMEOUT`, and None
+    // when there was no such connector.
+    pub fn stop(&mut self, exchange: ExchangeName, symbol: Symbol) -> Option<JoinHandle<()>> {
+        let key = (exchange, symbol);
+        let Connector { mut task, stop } = self.tasks.remove(&key)?;
+        stop.cancel();
+        set_state(&self.statuses, key, ConnectorState::Stopped);
+        self.statuses.lock().unwrap().remove(&key);
+        Some(tokio::spawn(async move {
+            if tokio::time::timeout(STOP_TIMEOUT, &mut task).await.is_err() {
+                tracing::warn!("{} {} connector did not stop within {:?}", exchange, symbol, STOP_TIMEOUT);
+                task.abort();
+            }
+        }))
+    }
+
+    // The `shutdown` function asks every connector to stop and returns their tasks, which finish
+    // once the connectors closed their websockets.
+    pub fn shutdown(&mut self) -> Vec<JoinHandle<()>> {
+        self.shutdown.cancel();
+        self.tasks.drain().map(|(_, connector)| connector.task).collect()
+    }
+
+    pub fn statuses(&self) -> Vec<ConnectorStatus> {
+        self.statuses.lock().unwrap().values().cloned().collect()
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        for connector in self.tasks.values() {
+            connector.task.abort();
+        }
+    }
+}
+
+async fn supervise<S, U, E>(
+    exchange: ExchangeName,
+    symbol: Symbol,
+    config: ConnectorConfig,
+    tx_orderbook: mpsc::Sender<OrderBookOnlyLevels>,
+    statuses: Statuses,
+    shutdown: CancellationToken,
+) where
+    S: Update + Send,
+    U: std::fmt::Debug + Update + From<S> + Send + Sync + 'static,
+    E: Exchange<S, U> + Send + Sync,
+{
+    let key = (exchange, symbol);
+    let mut backoff = Backoff::default();
+    // The connector is kept across restarts, so the scales are only fetched once.
+    let mut connector: Option<E> = None;
+    loop {
+        set_state(&statuses, key, ConnectorState::Starting);
+        let started_at = Instant::now();
+        let result = async {
+            if connector.is_none() {
+                let created = tokio::select! {
+                    created = E::new_exchange(symbol, config.clone()) => created?,
+                    _ = shutdown.cancelled() => return Ok(()),
+                };
+                connector = Some(created);
+            }
+            let connector = connector.as_ref().expect("connector was just created");
+            let (tx_ready, rx_ready) = oneshot::channel();
+            let running = connector.run(tx_orderbook.clone(), shutdown.clone(), Some(tx_ready));
+            tokio::pin!(running);
+            tokio::select! {
+                result = &mut running => return result,
+                Ok(()) = rx_ready => set_state(&statuses, key, ConnectorState::Running),
+            }
+            running.await
+        }
+        .await;
+        if shutdown.is_cancelled() || tx_orderbook.is_closed() {
+            if let Err(err) = result {
+                tracing::warn!("{} {} connector did not stop cleanly: {:#}", exchange, symbol, err);
+            }
+            set_state(&statuses, key, ConnectorState::Stopped);
+            return;
+        }
+
+        let error = match result {
+            Ok(()) => "stream ended".to_string(),
+            Err(err) => format!("{:#}", err),
+        };
+        tracing::error!("{} {} connector failed: {}", exchange, symbol, error);
+        if let Some(status) = statuses.lock().unwrap().get_mut(&key) {
+            status.restarts += 1;
+            status.last_error = Some(error);
+        }
+        set_state(&statuses, key, ConnectorState::Restarting);
+        tokio::select! {
+            _ = tokio::time::sleep(backoff.delay(started_at.elapsed())) => {}
+            _ = shutdown.cancelled() => {
+                set_state(&statuses, key, ConnectorState::Stopped);
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::{bail, Context, Result};
+use rustls::{server::AllowAnyAuthenticatedClient, PrivateKey, RootCertStore, ServerConfig};
+use rustls_pemfile::Item;
+use std::{path::Path, sync::Arc};
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity, ServerTlsConfig};
+use crate::settings::Settings;
+
+fn read_pem(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+fn load_identity(cert_path: &Path, key_path: &Path) -> Result<Identity> {
+    Ok(Identity::from_pem(read_pem(cert_path)?, read_pem(key_path)?))
+}
+
+// The `server_tls_config` function builds the server TLS configuration from PEM files. When a
+// `client_ca_path` is given, clients must present a certificate signed by that CA (mutual TLS).
+pub fn server_tls_config(
+    cert_path: &Path,
+    key_path: &Path,
+    client_ca_path: Option<&Path>,
+) -> Result<ServerTlsConfig> {
+    let mut tls = ServerTlsConfig::new().identity(load_identity(cert_path, key_path)?);
+    if let Some(client_ca_path) = client_ca_path {
+        tls = tls.client_ca_root(Certificate::from_pem(read_pem(client_ca_path)?));
+    }
+    Ok(tls)
+}
+
+// The `client_tls_config` function builds the client TLS configuration that trusts the server
+// certificates signed by the CA at `ca_path`, optionally presenting a client certificate for
+// mutual TLS.
+pub fn client_tls_config(
+    ca_path: &Path,
+    domain_name: &str,
+    client_identity: Option<(&Path, &Path)>,
+) -> Result<ClientTlsConfig> {
+    let mut tls = ClientTlsConfig::new()
+        .ca_certificate(Certificate::from_pem(read_pem(ca_path)?))
+        .domain_name(domain_name);
+    if let Some((cert_path, key_path)) = client_identity {
+        tls = tls.identity(load_identity(cert_path, key_path)?);
+    }
+    Ok(tls)
+}
+
+// The `server_tls_from_settings` function reads the optional `tls-cert`, `tls-key` and
+// `tls-client-ca` settings. TLS is only enabled when both a certificate and a key are configured,
+// which `Settings::validate` also requires of `tls-client-ca`.
+pub fn server_tls_from_settings(settings: &Settings) -> Result<Option<ServerTlsConfig>> {
+    match (&settings.tls_cert, &settings.tls_key) {
+        (Some(cert), Some(key)) => Ok(Some(server_tls_config(
+            cert,
+            key,
+            settings.tls_client_ca.as_deref(),
+        )?)),
+        _ => Ok(None),
+    }
+}
+
+fn read_certificates(path: &Path) -> Result<Vec<rustls::Certificate>> {
+    let certificates = rustls_pemfile::certs(&mut read_pem(path)?.as_slice())
+        .with_context(|| format!("Failed to parse certificates of {}", path.display()))?;
+    Ok(certificates.into_iter().map(rustls::Certificate).collect())
+}
+
+fn read_private_key(path: &Path) -> Result<PrivateKey> {
+    let pem = read_pem(path)?;
+    let mut reader = pem.as_slice();
+    loop {
+        match rustls_pemfile::read_one(&mut reader).with_context(|| format!("Failed to parse {}", path.display()))? {
+            Some(Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => continue,
+            None => bail!("no private key in {}", path.display()),
+        }
+    }
+}
+
+// The `http_tls_config` function builds the TLS configuration of the HTTP front-ends, the JSON
+// gateway and the websocket publisher, from the same `tls-cert`, `tls-key` and `tls-client-ca`
+// settings as the gRPC server, so tokens never travel in cleartext when the server uses TLS.
+pub fn http_tls_config(settings: &Settings) -> Result<Option<Arc<ServerConfig>>> {
+    let (Some(cert), Some(key)) = (&settings.tls_cert, &settings.tls_key) else {
+        return Ok(None);
+    };
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &settings.tls_client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in read_certificates(client_ca)? {
+                roots.add(&certificate).context("Invalid client CA certificate")?;
+            }
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder
+        .with_single_cert(read_certificates(cert)?, read_private_key(key)?)
+        .context("Invalid server certificate or key")?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Some(Arc::new(config)))
+}
+
+// The `client_endpoint` function returns the endpoint of the configured server. It connects over
+// TLS when `tls-ca` is set, verifying the server against `tls-domain` (the server ip by default),
+// and presents `tls-client-cert`/`tls-client-key` when both are set.
+pub fn client_endpoint(settings: &Settings) -> Result<Endpoint> {
+    let (ip, port) = (&settings.server_ip, settings.server_port);
+
+    let Some(ca) = &settings.tls_ca else {
+        return Ok(Endpoint::from_shared(format!("http://{}:{}", ip, port))?);
+    };
+    let domain_name = settings.tls_domain.as_ref().unwrap_or(ip);
+    let client_identity = match (&settings.tls_client_cert, &settings.tls_client_key) {
+        (Some(cert), Some(key)) => Some((cert.as_path(), key.as_path())),
+        _ => None,
+    };
+    let tls = client_tls_config(ca, domain_name, client_identity)?;
+    Ok(Endpoint::from_shared(format!("https://{}:{}", ip, port))?.tls_config(tls)?)
+}
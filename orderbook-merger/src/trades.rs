@@ -0,0 +1,45 @@
+use anyhow::Result;
+use rust_decimal::prelude::ToPrimitive;
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::broadcast;
+use crate::{orderbook_summary::Trade, str_to_storage, ToDisplay};
+
+// The `TradeTape` struct is where the connectors of one symbol publish the trades of their
+// exchange, merged in the order they are received. Every subscriber receives every trade, except
+// the oldest ones when it falls more than the capacity behind. Clones share the same tape.
+#[derive(Debug, Clone)]
+pub struct TradeTape(Arc<broadcast::Sender<Trade>>);
+
+impl TradeTape {
+    pub fn new(capacity: usize) -> Self {
+        Self(Arc::new(broadcast::channel(capacity).0))
+    }
+
+    pub fn publish(&self, trade: Trade) {
+        // Trades are dropped while nobody is subscribed.
+        let _ = self.0.send(trade);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Trade> {
+        self.0.subscribe()
+    }
+}
+
+impl PartialEq for TradeTape {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+// The `display_amount` function converts a price or quantity of a trade, as exchanges send it, to
+// a display amount with at most `scale` decimals, like the levels of a book.
+pub fn display_amount(amount: &str, scale: u32) -> Result<f64> {
+    Ok(str_to_storage(amount, scale)?.to_display(scale)?.to_f64().unwrap_or_default())
+}
+
+pub fn unix_micros(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64
+}
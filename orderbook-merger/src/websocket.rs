@@ -0,0 +1,184 @@
+use anyhow::{Context, Result};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use rustls::ServerConfig;
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+use tokio_tungstenite::tungstenite::{
+    handshake::server::{Request, Response},
+    Message,
+};
+use tonic::Status;
+use crate::{
+    auth::AuthInterceptor,
+    orderbook_summary::{Summary, SummaryRequest},
+    service::OrderbookSummary,
+    subscription::parse_conflation,
+};
+
+// The `SubscribeMessage` struct is the JSON message websocket clients send to choose what they
+// receive. It mirrors `SummaryRequest`; sending a new one replaces the previous subscription.
+// Clients that cannot set an `authorization` header on the handshake can pass their token here.
+// `{"unsubscribe": true}` stops the summaries without closing the connection.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SubscribeMessage {
+    pub symbol: String,
+    pub depth: u32,
+    pub include_exchanges: Vec<String>,
+    pub exclude_exchanges: Vec<String>,
+    pub max_updates_per_second: u32,
+    pub conflation: Option<String>,
+    pub top_n: u32,
+    pub token: Option<String>,
+    pub unsubscribe: bool,
+}
+
+impl TryFrom<&SubscribeMessage> for SummaryRequest {
+    type Error = Status;
+
+    fn try_from(message: &SubscribeMessage) -> Result<Self, Status> {
+        let conflation = parse_conflation(message.conflation.as_deref())?;
+        Ok(SummaryRequest {
+            max_updates_per_second: message.max_updates_per_second,
+            conflation: conflation as i32,
+            top_n: message.top_n,
+            include_exchanges: message.include_exchanges.clone(),
+            exclude_exchanges: message.exclude_exchanges.clone(),
+            symbol: message.symbol.clone(),
+            depth: message.depth,
+        })
+    }
+}
+
+// The `ServerMessage` enum is what the server sends, e.g. `{"type":"summary","data":{...}}`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ServerMessage {
+    Summary { data: Summary },
+    Error { message: String },
+}
+
+impl ServerMessage {
+    fn to_message(&self) -> Result<Message> {
+        Ok(Message::Text(serde_json::to_string(self)?))
+    }
+}
+
+// The `serve` function accepts websocket connections and fans out summaries to every client that
+// has sent a `SubscribeMessage`. With `tls` the connections are `wss://`, so tokens are never sent
+// in cleartext. New connections are refused once `shutdown` is cancelled.
+pub async fn serve(
+    address: SocketAddr,
+    service: OrderbookSummary,
+    auth: AuthInterceptor,
+    tls: Option<Arc<ServerConfig>>,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let acceptor = tls.map(TlsAcceptor::from);
+    let listener = TcpListener::bind(address)
+        .await
+        .context("Failed to bind websocket server")?;
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown.cancelled() => return Ok(()),
+        };
+        let service = service.clone();
+        let auth = auth.clone();
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            let handled = match acceptor {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => handle_connection(stream, service, auth).await,
+                    Err(err) => Err(err).context("Failed TLS handshake"),
+                },
+                None => handle_connection(stream, service, auth).await,
+            };
+            if let Err(err) = handled {
+                tracing::debug!("websocket client {} disconnected: {:?}", peer, err);
+            }
+        });
+    }
+}
+
+async fn handle_connection<S>(stream: S, service: OrderbookSummary, auth: AuthInterceptor) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut authorization = None;
+    let websocket = tokio_tungstenite::accept_hdr_async(stream, |request: &Request, response: Response| {
+        authorization = request
+            .headers()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        Ok(response)
+    })
+    .await
+    .context("Failed websocket handshake")?;
+    let (mut sink, mut source) = websocket.split();
+
+    let mut summaries: Option<ReceiverStream<Result<Summary, Status>>> = None;
+    loop {
+        let next_summary = async {
+            match summaries.as_mut() {
+                Some(summaries) => summaries.next().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            message = source.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    match subscribe(&service, &auth, authorization.as_deref(), &text) {
+                        Ok(stream) => summaries = stream,
+                        Err(status) => {
+                            let error = ServerMessage::Error { message: status.message().to_string() };
+                            sink.send(error.to_message()?).await?;
+                        }
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => continue,
+                Some(Err(err)) => return Err(err.into()),
+            },
+            summary = next_summary => {
+                let message = match summary {
+                    Some(Ok(data)) => ServerMessage::Summary { data },
+                    Some(Err(status)) => ServerMessage::Error { message: status.message().to_string() },
+                    // Summaries end when the server shuts down, after a final error message.
+                    None => {
+                        sink.send(Message::Close(None)).await?;
+                        break;
+                    }
+                };
+                sink.send(message.to_message()?).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn subscribe(
+    service: &OrderbookSummary,
+    auth: &AuthInterceptor,
+    authorization: Option<&str>,
+    text: &str,
+) -> Result<Option<ReceiverStream<Result<Summary, Status>>>, Status> {
+    let message = serde_json::from_str::<SubscribeMessage>(text)
+        .map_err(|e| Status::invalid_argument(format!("invalid subscribe message: {e}")))?;
+    if message.unsubscribe {
+        return Ok(None);
+    }
+    let token_authorization = message.token.as_ref().map(|token| format!("Bearer {token}"));
+    let permissions = auth.permissions(token_authorization.as_deref().or(authorization))?;
+    let request = SummaryRequest::try_from(&message)?;
+    let options = service.subscription_options(&request, permissions.as_ref())?;
+    service.subscribe(options).map(Some)
+}
@@ -0,0 +1,86 @@
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::{transport::{Channel, Server}, Code, Request};
+use orderbook_merger::{
+    admin::AdminService,
+    auth::{with_bearer_token, AuthInterceptor, ClientPermissions},
+    orderbook_summary::{admin_client::AdminClient, admin_server::AdminServer, ConnectorRequest, Empty},
+    pipeline::{PipelineConfig, Pipelines},
+    settings::Settings,
+    ExchangeName, Symbol,
+};
+
+fn permissions(admin: bool) -> ClientPermissions {
+    ClientPermissions { symbols: vec![Symbol::ETHUSDT], exchanges: vec![ExchangeName::BINANCE], max_depth: 5, admin }
+}
+
+// The `start_admin` function serves the admin service behind `auth`, for pipelines without
+// connectors.
+async fn start_admin(auth: AuthInterceptor) -> (AdminClient<Channel>, Pipelines) {
+    let pipelines = Pipelines::new(PipelineConfig { connectors: HashMap::new(), ..Default::default() });
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address: SocketAddr = listener.local_addr().unwrap();
+    let service = AdminServer::with_interceptor(AdminService::new(pipelines.clone()), auth);
+    tokio::spawn(Server::builder().add_service(service).serve_with_incoming(TcpListenerStream::new(listener)));
+    let channel = Channel::from_shared(format!("http://{address}"))
+        .unwrap()
+        .connect_timeout(Duration::from_secs(5))
+        .connect()
+        .await
+        .unwrap();
+    (AdminClient::new(channel), pipelines)
+}
+
+fn list_request(token: Option<&str>) -> Request<Empty> {
+    match token {
+        Some(token) => with_bearer_token(Request::new(Empty {}), token).unwrap(),
+        None => Request::new(Empty {}),
+    }
+}
+
+#[tokio::test]
+async fn admin_service_requires_an_admin_token() {
+    let tokens = HashMap::from([
+        ("reader".to_string(), permissions(false)),
+        ("operator".to_string(), permissions(true)),
+    ]);
+    let (mut client, pipelines) = start_admin(AuthInterceptor::new(tokens)).await;
+
+    let status = client.list_pipelines(list_request(None)).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+    let status = client.list_pipelines(list_request(Some("reader"))).await.unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+    let stop = |token| {
+        let request = ConnectorRequest { exchange: "BINANCE".to_string(), symbol: "ETHUSDT".to_string() };
+        with_bearer_token(Request::new(request), token).unwrap()
+    };
+    let status = client.stop_connector(stop("reader")).await.unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    let list = client.list_pipelines(list_request(Some("operator"))).await.unwrap().into_inner();
+    assert!(list.pipelines.is_empty());
+    // Authorized, but there is nothing to stop.
+    let status = client.stop_connector(stop("operator")).await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+    pipelines.shutdown().await;
+}
+
+#[tokio::test]
+async fn admin_service_without_authentication_rejects_every_request() {
+    let (mut client, pipelines) = start_admin(AuthInterceptor::default()).await;
+    let status = client.list_pipelines(list_request(None)).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+    assert_eq!(status.message(), "the admin service requires a bearer token");
+    pipelines.shutdown().await;
+}
+
+#[test]
+fn admin_port_requires_auth_tokens() {
+    let settings = Settings { admin_port: Some(5559), ..Default::default() };
+    assert_eq!(settings.validate().unwrap_err().to_string(), "admin-port requires auth-tokens");
+    let settings = Settings { auth_tokens: Some("tokens.toml".into()), ..settings };
+    assert!(settings.validate().is_ok());
+    let settings = Settings { admin_port: Some(5556), ..settings };
+    assert_eq!(settings.validate().unwrap_err().to_string(), "port 5556 is configured more than once");
+}
@@ -0,0 +1,100 @@
+use std::collections::{HashMap, HashSet};
+use orderbook_merger::{
+    auth::{load_tokens, AuthInterceptor, ClientPermissions},
+    subscription::{ExchangeFilter, SubscriptionOptions},
+    ExchangeName, Symbol,
+};
+
+fn binance_reader() -> ClientPermissions {
+    ClientPermissions {
+        symbols: vec![Symbol::ETHUSDT],
+        exchanges: vec![ExchangeName::BINANCE],
+        max_depth: 5,
+        admin: false,
+    }
+}
+
+fn interceptor() -> AuthInterceptor {
+    AuthInterceptor::new(HashMap::from([("secret".to_string(), binance_reader())]))
+}
+
+#[test]
+fn interceptor_rejects_missing_malformed_and_unknown_tokens() {
+    let interceptor = interceptor();
+    for authorization in [None, Some("secret"), Some("Basic secret"), Some("Bearer"), Some("bearer secret")] {
+        let status = interceptor.permissions(authorization).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated, "{authorization:?}");
+        assert_eq!(status.message(), "missing bearer token");
+    }
+    for token in ["Bearer secre", "Bearer secret2", "Bearer SECRET", "Bearer "] {
+        let status = interceptor.permissions(Some(token)).unwrap_err();
+        assert_eq!(status.message(), "invalid bearer token", "{token}");
+    }
+    assert_eq!(interceptor.permissions(Some("Bearer secret")).unwrap(), Some(binance_reader()));
+}
+
+#[test]
+fn interceptor_without_tokens_lets_every_request_through() {
+    assert_eq!(AuthInterceptor::default().permissions(None).unwrap(), None);
+}
+
+#[test]
+fn authorize_rejects_disallowed_symbols_and_exchanges() {
+    let permissions = binance_reader();
+    let error = permissions.authorize(&Symbol::BTCUSDT, &mut SubscriptionOptions::default()).unwrap_err();
+    assert_eq!(error.to_string(), "symbol BTCUSDT is not allowed");
+
+    let mut options = SubscriptionOptions {
+        exchanges: ExchangeFilter { include: HashSet::from([ExchangeName::BITSTAMP]), ..Default::default() },
+        ..Default::default()
+    };
+    let error = permissions.authorize(&Symbol::ETHUSDT, &mut options).unwrap_err();
+    assert_eq!(error.to_string(), "exchange BITSTAMP is not allowed");
+}
+
+#[test]
+fn authorize_narrows_exchanges_and_caps_depth() {
+    let permissions = binance_reader();
+    let mut options = SubscriptionOptions::default();
+    permissions.authorize(&Symbol::ETHUSDT, &mut options).unwrap();
+    assert_eq!(options.exchanges.include, HashSet::from([ExchangeName::BINANCE]));
+    // Every level means every allowed level.
+    assert_eq!(options.depth, 5);
+
+    let mut options = SubscriptionOptions { depth: 3, ..Default::default() };
+    permissions.authorize(&Symbol::ETHUSDT, &mut options).unwrap();
+    assert_eq!(options.depth, 3);
+    let mut options = SubscriptionOptions { depth: 6, ..Default::default() };
+    let error = permissions.authorize(&Symbol::ETHUSDT, &mut options).unwrap_err();
+    assert_eq!(error.to_string(), "depth 6 exceeds the allowed depth of 5");
+}
+
+#[test]
+fn load_tokens_reads_the_key_file() {
+    let path = std::env::temp_dir().join(format!("orderbook-merger-tokens-{}.toml", std::process::id()));
+    std::fs::write(
+        &path,
+        r#"
+[[tokens]]
+token = "secret"
+symbols = ["ETHUSDT"]
+exchanges = ["BINANCE"]
+max-depth = 5
+
+[[tokens]]
+token = "operator"
+symbols = ["ETHUSDT", "BTCUSDT"]
+exchanges = ["BINANCE", "BITSTAMP"]
+max-depth = 10
+admin = true
+"#,
+    )
+    .unwrap();
+    let tokens = load_tokens(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(tokens.len(), 2);
+    assert_eq!(tokens["secret"], binance_reader());
+    assert!(tokens["operator"].admin);
+    assert!(load_tokens(&path).is_err());
+}
@@ -0,0 +1,137 @@
+use std::{collections::HashMap, time::Duration};
+use tokio::time::Instant;
+use orderbook_merger::{
+    candles::{CandleBuilder, CandleHistory},
+    orderbook::orderbook::OrderBookOnlyLevels,
+    orderbook_summary::{Candle, CandleInterval, Level, Trade},
+    AggregatedBooks, ExchangeName, Symbol,
+};
+
+// A multiple of five minutes, so bars of every interval start here.
+const START_US: u64 = 1_700_000_100_000_000;
+const MILLIS: u64 = 1_000;
+
+fn ohlcv(candle: &Candle) -> (f64, f64, f64, f64, f64, u32) {
+    (candle.open, candle.high, candle.low, candle.close, candle.volume, candle.trades)
+}
+
+fn trade(exchange: ExchangeName, price: f64, quantity: f64) -> Trade {
+    Trade {
+        exchange: exchange.to_string(),
+        symbol: Symbol::ETHUSDT.to_string(),
+        price,
+        quantity,
+        ..Default::default()
+    }
+}
+
+fn book_levels(exchange: ExchangeName, bid: f64, ask: f64) -> OrderBookOnlyLevels {
+    let level = |price| Level { exchange: exchange.to_string(), price, quantity: 1.0 };
+    OrderBookOnlyLevels { exchange, bids: vec![level(bid)], asks: vec![level(ask)], ..Default::default() }
+}
+
+#[test]
+fn builder_rolls_bars_into_history() {
+    let history = CandleHistory::new(2);
+    let mut builder = CandleBuilder::new(Symbol::ETHUSDT, history.clone());
+    let binance_seconds = (Some(ExchangeName::BINANCE), CandleInterval::OneSecond);
+    let (_, mut rx_candles) = history.subscribe(binance_seconds, 0);
+
+    builder.mid_price(Some(ExchangeName::BINANCE), 100.0, START_US);
+    builder.mid_price(Some(ExchangeName::BINANCE), 102.0, START_US + 200 * MILLIS);
+    builder.mid_price(Some(ExchangeName::BINANCE), 99.0, START_US + 500 * MILLIS);
+    builder.trade(&trade(ExchangeName::BINANCE, 100.5, 0.5), START_US + 600 * MILLIS);
+    builder.mid_price(Some(ExchangeName::BINANCE), 101.0, START_US + 900 * MILLIS);
+    // Opens the next second, which closes the first one.
+    builder.mid_price(Some(ExchangeName::BINANCE), 103.0, START_US + 1_100 * MILLIS);
+
+    let (bars, _) = history.subscribe(binance_seconds, 0);
+    assert_eq!(bars.len(), 1);
+    assert_eq!((bars[0].exchange.as_str(), bars[0].symbol.as_str()), ("BINANCE", "ETHUSDT"));
+    assert_eq!(bars[0].interval(), CandleInterval::OneSecond);
+    assert_eq!(bars[0].open_time_us, START_US);
+    assert_eq!(ohlcv(&bars[0]), (100.0, 102.0, 99.0, 101.0, 0.5, 1));
+    assert_eq!(rx_candles.try_recv().unwrap(), bars[0]);
+
+    // The consolidated bars only had a trade, so they opened at its price.
+    builder.close_expired(START_US + 2_000 * MILLIS);
+    let (bars, _) = history.subscribe((None, CandleInterval::OneSecond), 0);
+    assert_eq!(bars.len(), 1);
+    assert_eq!(bars[0].exchange, "");
+    assert_eq!(ohlcv(&bars[0]), (100.5, 100.5, 100.5, 100.5, 0.5, 1));
+
+    // Minute bars are still open, and only the last two bars of a series are kept.
+    assert!(history.subscribe((Some(ExchangeName::BINANCE), CandleInterval::OneMinute), 0).0.is_empty());
+    builder.mid_price(Some(ExchangeName::BINANCE), 104.0, START_US + 2_100 * MILLIS);
+    builder.close_expired(START_US + 3_000 * MILLIS);
+    let (bars, _) = history.subscribe(binance_seconds, 0);
+    let opens: Vec<_> = bars.iter().map(|bar| bar.open).collect();
+    assert_eq!(opens, vec![103.0, 104.0]);
+    let (bars, _) = history.subscribe(binance_seconds, 1);
+    assert_eq!(bars[0].open_time_us, START_US + 2_000 * MILLIS);
+}
+
+#[test]
+fn bars_open_on_interval_boundaries() {
+    let history = CandleHistory::new(10);
+    let mut builder = CandleBuilder::new(Symbol::ETHUSDT, history.clone());
+    let exchange = Some(ExchangeName::BINANCE);
+    let opens = |interval| {
+        let (bars, _) = history.subscribe((exchange, interval), 0);
+        bars.iter().map(|bar| (bar.open_time_us, bar.open, bar.close)).collect::<Vec<_>>()
+    };
+
+    // The last microsecond of a second still belongs to its bar.
+    builder.mid_price(exchange, 100.0, START_US + 999_999);
+    builder.mid_price(exchange, 101.0, START_US + 1_000_000);
+    builder.mid_price(exchange, 102.0, START_US + 60_000_000 - 1);
+    builder.mid_price(exchange, 103.0, START_US + 60_000_000);
+    assert_eq!(
+        opens(CandleInterval::OneSecond),
+        vec![(START_US, 100.0, 100.0), (START_US + 1_000_000, 101.0, 101.0), (START_US + 59_000_000, 102.0, 102.0)]
+    );
+    assert_eq!(opens(CandleInterval::OneMinute), vec![(START_US, 100.0, 102.0)]);
+    assert!(opens(CandleInterval::FiveMinutes).is_empty());
+
+    // A bar closes once its whole interval has passed, not before.
+    builder.close_expired(START_US + 300_000_000 - 1);
+    assert!(opens(CandleInterval::FiveMinutes).is_empty());
+    assert_eq!(opens(CandleInterval::OneMinute).len(), 2);
+    builder.close_expired(START_US + 300_000_000);
+    assert_eq!(opens(CandleInterval::FiveMinutes), vec![(START_US, 100.0, 103.0)]);
+}
+
+#[test]
+fn exchanges_without_updates_keep_their_bars() {
+    let history = CandleHistory::new(10);
+    let mut builder = CandleBuilder::new(Symbol::ETHUSDT, history.clone());
+    let bars = |exchange, interval| history.subscribe((exchange, interval), 0).0;
+    let (_, mut rx_bitstamp) = history.subscribe((Some(ExchangeName::BITSTAMP), CandleInterval::OneSecond), 0);
+    let start = Instant::now();
+    let mut books = HashMap::from([
+        (ExchangeName::BINANCE, book_levels(ExchangeName::BINANCE, 100.0, 102.0)),
+        (ExchangeName::BITSTAMP, book_levels(ExchangeName::BITSTAMP, 200.0, 202.0)),
+    ]);
+    let mut last_updated = HashMap::from([(ExchangeName::BINANCE, start), (ExchangeName::BITSTAMP, start)]);
+    builder.books(&AggregatedBooks::new(books.clone(), last_updated.clone()), START_US);
+
+    // Only Binance sends a book in the next seconds.
+    for (second, bid) in [(1, 104.0), (2, 106.0)] {
+        books.insert(ExchangeName::BINANCE, book_levels(ExchangeName::BINANCE, bid, bid + 2.0));
+        last_updated.insert(ExchangeName::BINANCE, start + Duration::from_secs(second));
+        builder.books(&AggregatedBooks::new(books.clone(), last_updated.clone()), START_US + second * 1_000_000);
+    }
+    builder.close_expired(START_US + 3_000_000);
+
+    let closes = |bars: Vec<Candle>| bars.iter().map(|bar| (bar.open_time_us, bar.close)).collect::<Vec<_>>();
+    assert_eq!(
+        closes(bars(Some(ExchangeName::BINANCE), CandleInterval::OneSecond)),
+        vec![(START_US, 101.0), (START_US + 1_000_000, 105.0), (START_US + 2_000_000, 107.0)]
+    );
+    assert_eq!(closes(bars(Some(ExchangeName::BITSTAMP), CandleInterval::OneSecond)), vec![(START_US, 201.0)]);
+    // The merged book changed with every Binance book.
+    assert_eq!(bars(None, CandleInterval::OneSecond).len(), 3);
+    // Bars of other series are sent on their own channels.
+    assert_eq!(rx_bitstamp.try_recv().unwrap().open_time_us, START_US);
+    assert!(rx_bitstamp.try_recv().is_err());
+}
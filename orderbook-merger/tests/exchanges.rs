@@ -0,0 +1,825 @@
+mod mock_exchange;
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+use tokio_stream::{wrappers::TcpListenerStream, StreamExt};
+use tokio::{net::TcpListener, sync::mpsc};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_util::sync::CancellationToken;
+use tonic::transport::Server;
+use orderbook_merger::{
+    drain::serve_until_drained,
+    exchanges::{binance::Binance, bitstamp::Bitstamp, exchange::Exchange},
+    metrics,
+    orderbook::orderbook::{OrderBookOnlyLevels, PublishedBook},
+    orderbook_summary::{
+        orderbook_aggregator_client::OrderbookAggregatorClient,
+        orderbook_aggregator_server::OrderbookAggregatorServer,
+        AggressorSide, BboRequest, BestBidOffer, CandleInterval, CandlesRequest, ExchangeBookRequest, Level, SummaryRequest,
+        TradesRequest,
+    },
+    pipeline::{PipelineConfig, Pipelines},
+    reload,
+    service::OrderbookSummary,
+    settings::Settings,
+    supervisor::ConnectorState,
+    ExchangeName, Symbol,
+};
+use mock_exchange::{
+    binance_snapshot, binance_trade, binance_update, bitstamp_snapshot, bitstamp_trade, bitstamp_update, MockExchange,
+    Scenario, Step,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+fn prices(levels: &[Level]) -> Vec<(f64, f64)> {
+    levels.iter().map(|level| (level.price, level.quantity)).collect()
+}
+
+// The `levels_until` function returns the first book levels sent by a connector that match
+// `done`.
+async fn levels_until(
+    rx: &mut mpsc::Receiver<OrderBookOnlyLevels>,
+    done: impl Fn(&OrderBookOnlyLevels) -> bool,
+) -> OrderBookOnlyLevels {
+    tokio::time::timeout(TIMEOUT, async {
+        loop {
+            let levels = rx.recv().await.expect("connector stopped sending levels");
+            if done(&levels) {
+                return levels;
+            }
+        }
+    })
+    .await
+    .expect("timed out waiting for book levels")
+}
+
+fn binance_scenario(connection: Vec<Step>) -> Scenario {
+    Scenario {
+        snapshots: vec![binance_snapshot(
+            100,
+            &[("2000.00000000", "1.00000000"), ("1999.00000000", "2.00000000")],
+            &[("2001.00000000", "1.50000000"), ("2002.00000000", "3.00000000")],
+        )],
+        connections: vec![connection],
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn binance_connector_applies_snapshot_then_diffs() {
+    let mock = MockExchange::start(
+        ExchangeName::BINANCE,
+        binance_scenario(vec![
+            // Buffered before the snapshot was taken, so it is skipped.
+            binance_update(95, 100, &[("2000.00000000", "9.00000000")], &[]),
+            binance_update(99, 101, &[("2000.50000000", "0.50000000")], &[]),
+            binance_update(102, 103, &[("1999.00000000", "0.00000000")], &[("2001.00000000", "0.25000000")]),
+        ]),
+    )
+    .await;
+    let binance = Binance::new_exchange(Symbol::ETHUSDT, mock.config()).await.unwrap();
+    let (tx, mut rx) = mpsc::channel(100);
+    let shutdown = CancellationToken::new();
+    let connector = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move { binance.start(tx, shutdown).await }
+    });
+
+    let levels = levels_until(&mut rx, |levels| levels.last_update_id == 103).await;
+    assert_eq!(prices(&levels.bids), vec![(2000.5, 0.5), (2000.0, 1.0)]);
+    assert_eq!(prices(&levels.asks), vec![(2001.0, 0.25), (2002.0, 3.0)]);
+    assert_eq!(mock.paths(), vec!["/ethusdt@depth@100ms"]);
+
+    shutdown.cancel();
+    connector.await.unwrap().unwrap();
+}
+
+// Only `depth` levels are sent to the aggregator, while the published book holds every level.
+#[tokio::test(flavor = "multi_thread")]
+async fn connector_publishes_full_book() {
+    let mock = MockExchange::start(
+        ExchangeName::BINANCE,
+        binance_scenario(vec![binance_update(101, 102, &[("2000.50000000", "0.50000000")], &[])]),
+    )
+    .await;
+    let published = PublishedBook::default();
+    let mut config = mock.config();
+    config.depth = 1;
+    config.book = Some(published.clone());
+    let binance = Binance::new_exchange(Symbol::ETHUSDT, config).await.unwrap();
+    let (tx, mut rx) = mpsc::channel(100);
+    let shutdown = CancellationToken::new();
+    let connector = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move { binance.start(tx, shutdown).await }
+    });
+
+    let levels = levels_until(&mut rx, |levels| levels.last_update_id == 102).await;
+    assert_eq!(prices(&levels.bids), vec![(2000.5, 0.5)]);
+    let book = published.load();
+    assert_eq!(book.last_update_id, 102);
+    assert_eq!(book.bids, vec![(200050, 50000000), (200000, 100000000), (199900, 200000000)]);
+    assert_eq!(book.asks, vec![(200100, 150000000), (200200, 300000000)]);
+
+    shutdown.cancel();
+    connector.await.unwrap().unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn binance_connector_fails_on_sequence_gap() {
+    let mock = MockExchange::start(
+        ExchangeName::BINANCE,
+        binance_scenario(vec![
+            binance_update(101, 102, &[("2000.50000000", "0.50000000")], &[]),
+            // Updates 103 and 104 were missed.
+            binance_update(105, 106, &[("2000.25000000", "0.50000000")], &[]),
+        ]),
+    )
+    .await;
+    let binance = Binance::new_exchange(Symbol::ETHUSDT, mock.config()).await.unwrap();
+    let (tx, _rx) = mpsc::channel(100);
+    let validation_failures = metrics::VALIDATION_FAILURES.with_label_values(&["BINANCE"]);
+    let failures_before = validation_failures.get();
+
+    let result = tokio::time::timeout(TIMEOUT, binance.start(tx, CancellationToken::new()))
+        .await
+        .expect("connector did not stop");
+    let error = format!("{:#}", result.unwrap_err());
+    assert!(error.contains("first_update_id: 105"), "unexpected error: {error}");
+    assert!(validation_failures.get() > failures_before);
+}
+
+// The websocket reader is spawned by the connector, it must not keep the connection open once the
+// connector is gone.
+#[tokio::test(flavor = "multi_thread")]
+async fn aborted_connector_drops_its_websocket() {
+    let mock = MockExchange::start(
+        ExchangeName::BINANCE,
+        binance_scenario(vec![binance_update(101, 102, &[("2000.50000000", "0.50000000")], &[])]),
+    )
+    .await;
+    let binance = Binance::new_exchange(Symbol::ETHUSDT, mock.config()).await.unwrap();
+    let (tx, mut rx) = mpsc::channel(100);
+    let connector = tokio::spawn(async move { binance.start(tx, CancellationToken::new()).await });
+    levels_until(&mut rx, |levels| levels.last_update_id == 102).await;
+    assert_eq!(mock.disconnections(), 0);
+
+    connector.abort();
+    tokio::time::timeout(TIMEOUT, async {
+        while mock.disconnections() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("websocket was left open");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn binance_connector_skips_malformed_frames() {
+    let valid = binance_update(101, 102, &[("2000.50000000", "0.50000000")], &[]);
+    let Step::Send(Message::Text(valid_text)) = &valid else {
+        unreachable!()
+    };
+    let mock = MockExchange::start(
+        ExchangeName::BINANCE,
+        binance_scenario(vec![
+            Step::text("not json"),
+            Step::text(r#"{"e":"depthUpdate","U":101,"u":101,"b":[["abc","1"]],"a":[]}"#),
+            Step::text(&valid_text[..valid_text.len() / 2]),
+            Step::Send(Message::Binary(vec![0xff, 0x00, 0x7b])),
+            valid.clone(),
+        ]),
+    )
+    .await;
+    let binance = Binance::new_exchange(Symbol::ETHUSDT, mock.config()).await.unwrap();
+    let (tx, mut rx) = mpsc::channel(100);
+    let shutdown = CancellationToken::new();
+    let parse_failures = metrics::PARSE_FAILURES.with_label_values(&["BINANCE"]);
+    let failures_before = parse_failures.get();
+    let connector = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move { binance.start(tx, shutdown).await }
+    });
+
+    let levels = levels_until(&mut rx, |levels| levels.last_update_id == 102).await;
+    assert_eq!(prices(&levels.bids), vec![(2000.5, 0.5), (2000.0, 1.0), (1999.0, 2.0)]);
+    // Other tests of this binary only add to the counter.
+    assert!(parse_failures.get() - failures_before >= 4);
+
+    shutdown.cancel();
+    connector.await.unwrap().unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bitstamp_connector_subscribes_and_unsubscribes() {
+    let mock = MockExchange::start(
+        ExchangeName::BITSTAMP,
+        Scenario {
+            snapshots: vec![bitstamp_snapshot(
+                1_000_000,
+                &[("2000.00", "1.00000000")],
+                &[("2001.00", "1.50000000")],
+            )],
+            connections: vec![vec![
+                bitstamp_update(Symbol::ETHUSDT, 900_000, &[("2000.00", "9.00000000")], &[]),
+                bitstamp_update(Symbol::ETHUSDT, 1_100_000, &[("2000.50", "0.50000000")], &[]),
+            ]],
+        },
+    )
+    .await;
+    let bitstamp = Bitstamp::new_exchange(Symbol::ETHUSDT, mock.config()).await.unwrap();
+    let (tx, mut rx) = mpsc::channel(100);
+    let shutdown = CancellationToken::new();
+    let connector = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move { bitstamp.start(tx, shutdown).await }
+    });
+
+    let levels = levels_until(&mut rx, |levels| levels.last_update_id == 1_100_000).await;
+    assert_eq!(prices(&levels.bids), vec![(2000.5, 0.5), (2000.0, 1.0)]);
+    assert_eq!(prices(&levels.asks), vec![(2001.0, 1.5)]);
+
+    shutdown.cancel();
+    connector.await.unwrap().unwrap();
+    tokio::time::timeout(TIMEOUT, async {
+        while mock.received().len() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("connector did not unsubscribe");
+    let received = mock.received();
+    assert!(received[0].contains("bts:subscribe") && received[0].contains("diff_order_book_ethusdt"));
+    assert!(received[1].contains("bts:unsubscribe") && received[1].contains("diff_order_book_ethusdt"));
+}
+
+// Replies to subscribing carry no data and are skipped without counting as parse failures.
+#[test]
+fn subscription_replies_are_not_parse_failures() {
+    assert!(Binance::is_subscription_reply(br#"{"result":null,"id":1}"#));
+    assert!(!Binance::is_subscription_reply(br#"{"error":{"code":2,"msg":"Invalid request"},"id":1}"#));
+    assert!(!Binance::is_subscription_reply(br#"{"e":"depthUpdate","U":101,"u":101,"b":[],"a":[]}"#));
+    let reply = br#"{"event":"bts:subscription_succeeded","channel":"diff_order_book_ethusdt","data":{}}"#;
+    assert!(Bitstamp::is_subscription_reply(reply));
+    assert!(Bitstamp::is_subscription_reply(br#"{"event":"bts:unsubscription_succeeded","channel":"diff_order_book_ethusdt","data":{}}"#));
+    assert!(!Bitstamp::is_subscription_reply(br#"{"event":"bts:error","channel":"","data":{"code":null,"message":"Bad subscription string."}}"#));
+    assert!(!Bitstamp::is_subscription_reply(b"not json"));
+}
+
+fn pipeline_config(connectors: &[(ExchangeName, &MockExchange)]) -> PipelineConfig {
+    PipelineConfig {
+        connectors: connectors
+            .iter()
+            .map(|(exchange, mock)| (*exchange, mock.config()))
+            .collect::<HashMap<_, _>>(),
+        ..Default::default()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn supervisor_resyncs_after_disconnect() {
+    let mock = MockExchange::start(
+        ExchangeName::BINANCE,
+        Scenario {
+            snapshots: vec![
+                binance_snapshot(100, &[("2000.00000000", "1.00000000")], &[("2001.00000000", "1.00000000")]),
+                binance_snapshot(200, &[("1990.00000000", "1.00000000")], &[("1991.00000000", "1.00000000")]),
+            ],
+            connections: vec![
+                vec![
+                    binance_update(101, 101, &[("2000.50000000", "1.00000000")], &[]),
+                    Step::Sleep(Duration::from_millis(100)),
+                    Step::Disconnect,
+                ],
+                vec![],
+            ],
+        },
+    )
+    .await;
+    let resyncs = metrics::RESYNCS.with_label_values(&["BINANCE"]);
+    let resyncs_before = resyncs.get();
+    let pipelines = Pipelines::new(pipeline_config(&[(ExchangeName::BINANCE, &mock)]));
+    assert!(pipelines.start_connector(ExchangeName::BINANCE, Symbol::ETHUSDT));
+    let mut books = pipelines.books(Symbol::ETHUSDT).unwrap();
+
+    // Levels of the first connection are gone once the book is rebuilt from the second snapshot.
+    let resynced = tokio::time::timeout(
+        TIMEOUT,
+        books.wait_for(|books| books.summary().bids.first().is_some_and(|level| level.price == 1990.0)),
+    )
+    .await
+    .expect("book was not resynced")
+    .unwrap()
+    .summary()
+    .clone();
+    assert_eq!(prices(&resynced.bids), vec![(1990.0, 1.0)]);
+    assert_eq!(mock.connections(), 2);
+    assert_eq!(mock.snapshots_served(), 2);
+    assert!(resyncs.get() > resyncs_before);
+
+    let connector = pipelines.pipelines()[0].connectors[0].clone();
+    assert_eq!(connector.restarts, 1);
+    assert_eq!(connector.last_error.as_deref(), Some("stream ended"));
+    pipelines.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn supervisor_reports_running_once_the_snapshot_is_applied() {
+    let mock = MockExchange::start(ExchangeName::BINANCE, binance_scenario(vec![])).await;
+    let pipelines = Pipelines::new(pipeline_config(&[(ExchangeName::BINANCE, &mock)]));
+    assert!(pipelines.start_connector(ExchangeName::BINANCE, Symbol::ETHUSDT));
+    let state = || pipelines.pipelines()[0].connectors[0].state;
+    assert_eq!(state(), ConnectorState::Starting);
+
+    tokio::time::timeout(TIMEOUT, async {
+        while state() != ConnectorState::Running {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("connector is not running");
+    assert_eq!(mock.snapshots_served(), 1);
+    pipelines.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stopped_connectors_unsubscribe_and_close_their_websocket() {
+    let mock = MockExchange::start(
+        ExchangeName::BITSTAMP,
+        Scenario {
+            snapshots: vec![bitstamp_snapshot(1_000_000, &[("2000.00", "1.00000000")], &[("2001.00", "1.50000000")])],
+            connections: vec![vec![]],
+        },
+    )
+    .await;
+    let unsubscribed = || mock.received().iter().filter(|message| message.contains("bts:unsubscribe")).count();
+    let wait_for_unsubscribes = |count: usize| async move {
+        tokio::time::timeout(TIMEOUT, async {
+            while unsubscribed() < count {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("connector did not unsubscribe")
+    };
+    let pipelines = Pipelines::new(pipeline_config(&[(ExchangeName::BITSTAMP, &mock)]));
+    assert!(pipelines.start_connector(ExchangeName::BITSTAMP, Symbol::ETHUSDT));
+    let state = || pipelines.pipelines()[0].connectors.first().map(|connector| connector.state);
+    let wait_until_running = || async {
+        tokio::time::timeout(TIMEOUT, async {
+            while state() != Some(ConnectorState::Running) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("connector is not running")
+    };
+    wait_until_running().await;
+
+    // The connector is only started again once the previous one left the exchange.
+    assert!(pipelines.resync_connector(ExchangeName::BITSTAMP, Symbol::ETHUSDT).await);
+    wait_for_unsubscribes(1).await;
+    wait_until_running().await;
+    assert_eq!(mock.connections(), 2);
+
+    let mut books = pipelines.books(Symbol::ETHUSDT).unwrap();
+    assert!(pipelines.stop_connector(ExchangeName::BITSTAMP, Symbol::ETHUSDT).await);
+    wait_for_unsubscribes(2).await;
+    assert_eq!(state(), None);
+    // The book is removed after the last levels of the stopped connector.
+    tokio::time::timeout(TIMEOUT, books.wait_for(|books| books.books.is_empty()))
+        .await
+        .expect("book of the stopped connector was not removed")
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(books.borrow().summary().bids.is_empty());
+    assert!(!pipelines.stop_connector(ExchangeName::BITSTAMP, Symbol::ETHUSDT).await);
+    pipelines.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pipeline_merges_mock_exchanges() {
+    let binance = MockExchange::start(
+        ExchangeName::BINANCE,
+        Scenario {
+            snapshots: vec![binance_snapshot(
+                100,
+                &[("2000.00000000", "1.00000000"), ("1998.00000000", "2.00000000")],
+                &[("2002.00000000", "2.00000000"), ("2003.00000000", "1.00000000")],
+            )],
+            connections: vec![vec![]],
+        },
+    )
+    .await;
+    let bitstamp = MockExchange::start(
+        ExchangeName::BITSTAMP,
+        Scenario {
+            snapshots: vec![bitstamp_snapshot(
+                1_000_000,
+                &[("2000.50", "3.00000000"), ("1997.00", "1.00000000")],
+                &[("2001.50", "4.00000000"), ("2004.00", "1.00000000")],
+            )],
+            // Bitstamp closes the connection cleanly after its first update.
+            connections: vec![vec![
+                bitstamp_update(Symbol::ETHUSDT, 1_100_000, &[("1999.00", "5.00000000"), ("1997.00", "0.00000000")], &[]),
+                Step::Close,
+            ]],
+        },
+    )
+    .await;
+    let pipelines = Pipelines::new(pipeline_config(&[
+        (ExchangeName::BINANCE, &binance),
+        (ExchangeName::BITSTAMP, &bitstamp),
+    ]));
+    pipelines.start_connector(ExchangeName::BINANCE, Symbol::ETHUSDT);
+    pipelines.start_connector(ExchangeName::BITSTAMP, Symbol::ETHUSDT);
+    let mut books = pipelines.books(Symbol::ETHUSDT).unwrap();
+
+    let merged = tokio::time::timeout(
+        TIMEOUT,
+        books.wait_for(|books| {
+            books.books.contains_key(&ExchangeName::BINANCE)
+                && books.books.get(&ExchangeName::BITSTAMP).is_some_and(|book| book.last_update_id == 1_100_000)
+        }),
+    )
+    .await
+    .expect("books were not merged")
+    .unwrap()
+    .clone();
+    assert_eq!(prices(&merged.books[&ExchangeName::BITSTAMP].bids), vec![(2000.5, 3.0), (1999.0, 5.0)]);
+
+    let summary = merged.summary();
+    let exchanges = |levels: &[Level]| levels.iter().map(|level| level.exchange.clone()).collect::<Vec<_>>();
+    assert_eq!(prices(&summary.bids), vec![(2000.5, 3.0), (2000.0, 1.0)]);
+    assert_eq!(exchanges(&summary.bids), vec!["BITSTAMP", "BINANCE"]);
+    assert_eq!(prices(&summary.asks), vec![(2001.5, 4.0), (2002.0, 2.0)]);
+    assert_eq!(exchanges(&summary.asks), vec!["BITSTAMP", "BINANCE"]);
+    assert_eq!(summary.spread, 1.0);
+
+    let bitstamp_book = pipelines.exchange_book(ExchangeName::BITSTAMP, Symbol::ETHUSDT).unwrap().load();
+    assert_eq!(bitstamp_book.last_update_id, 1_100_000);
+    assert_eq!(bitstamp_book.bids, vec![(200050, 300000000), (199900, 500000000)]);
+    pipelines.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exchange_book_streams_unmerged_book() {
+    let mock = MockExchange::start(
+        ExchangeName::BINANCE,
+        binance_scenario(vec![binance_update(101, 102, &[("2000.50000000", "0.50000000")], &[])]),
+    )
+    .await;
+    let pipelines = Pipelines::new(pipeline_config(&[(ExchangeName::BINANCE, &mock)]));
+    pipelines.start_connector(ExchangeName::BINANCE, Symbol::ETHUSDT);
+    let service = OrderbookSummary::new(Symbol::ETHUSDT, pipelines.clone());
+
+    let request = |exchange: &str, depth| ExchangeBookRequest { exchange: exchange.to_string(), depth, ..Default::default() };
+    let error = service.subscribe_exchange_book(&request("BITSTAMP", 0), None).unwrap_err();
+    assert_eq!(error.code(), tonic::Code::NotFound);
+
+    let mut stream = service.subscribe_exchange_book(&request("BINANCE", 2), None).unwrap();
+    let book = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            let book = stream.next().await.expect("stream ended").unwrap();
+            if book.last_update_id == 102 {
+                return book;
+            }
+        }
+    })
+    .await
+    .expect("book was not streamed");
+    assert_eq!((book.exchange.as_str(), book.symbol.as_str()), ("BINANCE", "ETHUSDT"));
+    assert_eq!(prices(&book.bids), vec![(2000.5, 0.5), (2000.0, 1.0)]);
+    assert_eq!(prices(&book.asks), vec![(2001.0, 1.5), (2002.0, 3.0)]);
+    pipelines.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn trades_stream_merges_exchanges() {
+    let binance = MockExchange::start(
+        ExchangeName::BINANCE,
+        binance_scenario(vec![
+            Step::text(r#"{"result":null,"id":1}"#),
+            binance_trade(7, "2000.50000000", "0.25000000", true),
+        ]),
+    )
+    .await;
+    let bitstamp = MockExchange::start(
+        ExchangeName::BITSTAMP,
+        Scenario {
+            snapshots: vec![bitstamp_snapshot(1_000_000, &[("2000.00", "1.00000000")], &[("2001.00", "1.50000000")])],
+            // Sent after the Binance trade, so the tape is in a known order.
+            connections: vec![vec![
+                Step::Sleep(Duration::from_millis(500)),
+                bitstamp_trade(Symbol::ETHUSDT, 8, "2001.00", "0.5", 0, 1_700_000_000_500_000),
+            ]],
+        },
+    )
+    .await;
+    let pipelines = Pipelines::new(pipeline_config(&[
+        (ExchangeName::BINANCE, &binance),
+        (ExchangeName::BITSTAMP, &bitstamp),
+    ]));
+    pipelines.start_connector(ExchangeName::BINANCE, Symbol::ETHUSDT);
+    pipelines.start_connector(ExchangeName::BITSTAMP, Symbol::ETHUSDT);
+    let service = OrderbookSummary::new(Symbol::ETHUSDT, pipelines.clone());
+    let mut trades = service.subscribe_trades(&TradesRequest::default(), None).unwrap();
+
+    let trades = tokio::time::timeout(TIMEOUT, async {
+        let first = trades.next().await.expect("stream ended").unwrap();
+        let second = trades.next().await.expect("stream ended").unwrap();
+        [first, second]
+    })
+    .await
+    .expect("trades were not streamed");
+    let [binance_trade, bitstamp_trade] = trades;
+    assert_eq!((binance_trade.exchange.as_str(), binance_trade.symbol.as_str()), ("BINANCE", "ETHUSDT"));
+    assert_eq!((binance_trade.trade_id, binance_trade.price, binance_trade.quantity), (7, 2000.5, 0.25));
+    // The buyer was the maker, so the seller took liquidity.
+    assert_eq!(binance_trade.aggressor_side(), AggressorSide::Sell);
+    assert_eq!(binance_trade.traded_at_us, 1_700_000_000_000_000);
+    assert!(binance_trade.received_at_us > binance_trade.traded_at_us);
+    assert_eq!(bitstamp_trade.exchange, "BITSTAMP");
+    assert_eq!((bitstamp_trade.trade_id, bitstamp_trade.price, bitstamp_trade.quantity), (8, 2001.0, 0.5));
+    assert_eq!(bitstamp_trade.aggressor_side(), AggressorSide::Buy);
+    assert_eq!(bitstamp_trade.traded_at_us, 1_700_000_000_500_000);
+
+    assert!(binance.received().iter().any(|message| message.contains("SUBSCRIBE") && message.contains("ethusdt@trade")));
+    pipelines.shutdown().await;
+    let received = |event: &str| {
+        bitstamp.received().iter().any(|message| message.contains(event) && message.contains("live_trades_ethusdt"))
+    };
+    tokio::time::timeout(TIMEOUT, async {
+        while !received("bts:unsubscribe") {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("connector did not unsubscribe from trades");
+    assert!(received("bts:subscribe"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bbo_streams_top_of_each_exchange() {
+    let binance = MockExchange::start(ExchangeName::BINANCE, binance_scenario(vec![])).await;
+    let bitstamp = MockExchange::start(
+        ExchangeName::BITSTAMP,
+        Scenario {
+            snapshots: vec![bitstamp_snapshot(
+                1_000_000,
+                &[("2000.50", "3.00000000"), ("1997.00", "1.00000000")],
+                &[("2002.00", "1.00000000")],
+            )],
+            connections: vec![vec![]],
+        },
+    )
+    .await;
+    let pipelines = Pipelines::new(pipeline_config(&[
+        (ExchangeName::BINANCE, &binance),
+        (ExchangeName::BITSTAMP, &bitstamp),
+    ]));
+    pipelines.start_connector(ExchangeName::BINANCE, Symbol::ETHUSDT);
+    pipelines.start_connector(ExchangeName::BITSTAMP, Symbol::ETHUSDT);
+    let service = OrderbookSummary::new(Symbol::ETHUSDT, pipelines.clone());
+
+    let bbo_of = |request: BboRequest, venues: usize| {
+        let mut stream = service.subscribe_bbo(&request, None).unwrap();
+        async move {
+            tokio::time::timeout(TIMEOUT, async {
+                loop {
+                    let bbo: BestBidOffer = stream.next().await.expect("stream ended").unwrap();
+                    if bbo.venues.len() == venues {
+                        return bbo;
+                    }
+                }
+            })
+            .await
+            .expect("best bid and offer was not streamed")
+        }
+    };
+    let level = |level: Option<&Level>| level.map(|level| (level.exchange.clone(), level.price, level.quantity));
+
+    let bbo = bbo_of(BboRequest::default(), 2).await;
+    assert_eq!(level(bbo.best_bid.as_ref()), Some(("BITSTAMP".to_string(), 2000.5, 3.0)));
+    assert_eq!(level(bbo.best_ask.as_ref()), Some(("BINANCE".to_string(), 2001.0, 1.5)));
+    assert_eq!(bbo.spread, 0.5);
+    let venues = bbo.venues.iter().map(|venue| venue.exchange.as_str()).collect::<Vec<_>>();
+    assert_eq!(venues, vec!["BINANCE", "BITSTAMP"]);
+    assert_eq!(level(bbo.venues[0].bid.as_ref()), Some(("BINANCE".to_string(), 2000.0, 1.0)));
+    assert_eq!(level(bbo.venues[1].ask.as_ref()), Some(("BITSTAMP".to_string(), 2002.0, 1.0)));
+
+    let request = BboRequest { exclude_exchanges: vec!["BITSTAMP".to_string()], ..Default::default() };
+    let bbo = bbo_of(request, 1).await;
+    assert_eq!(level(bbo.best_bid.as_ref()), Some(("BINANCE".to_string(), 2000.0, 1.0)));
+    assert_eq!(bbo.spread, 1.0);
+    pipelines.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn candles_stream_history_then_new_bars() {
+    let mock = MockExchange::start(ExchangeName::BINANCE, binance_scenario(vec![])).await;
+    let pipelines = Pipelines::new(pipeline_config(&[(ExchangeName::BINANCE, &mock)]));
+    pipelines.start_connector(ExchangeName::BINANCE, Symbol::ETHUSDT);
+    let service = OrderbookSummary::new(Symbol::ETHUSDT, pipelines.clone());
+
+    let request = |exchange: &str| CandlesRequest {
+        exchange: exchange.to_string(),
+        interval: CandleInterval::OneSecond as i32,
+        ..Default::default()
+    };
+    let error = service.subscribe_candles(&request("KRAKEN"), None).unwrap_err();
+    assert_eq!(error.code(), tonic::Code::InvalidArgument);
+
+    // The book does not change after its snapshot, so a single bar closes for each series.
+    let mut stream = service.subscribe_candles(&request("BINANCE"), None).unwrap();
+    let candle = tokio::time::timeout(TIMEOUT, stream.next())
+        .await
+        .expect("candle was not streamed")
+        .expect("stream ended")
+        .unwrap();
+    assert_eq!((candle.exchange.as_str(), candle.symbol.as_str()), ("BINANCE", "ETHUSDT"));
+    assert_eq!((candle.open, candle.high, candle.low, candle.close), (2000.5, 2000.5, 2000.5, 2000.5));
+    assert_eq!(candle.open_time_us % 1_000_000, 0);
+
+    let mut stream = service.subscribe_candles(&request("BINANCE"), None).unwrap();
+    let history = tokio::time::timeout(TIMEOUT, stream.next()).await.unwrap().unwrap().unwrap();
+    assert_eq!(history, candle);
+    let mut consolidated = service.subscribe_candles(&request(""), None).unwrap();
+    let history = tokio::time::timeout(TIMEOUT, consolidated.next()).await.unwrap().unwrap().unwrap();
+    assert_eq!((history.exchange.as_str(), history.close), ("", 2000.5));
+    pipelines.shutdown().await;
+}
+
+// The `mock_settings` function returns settings with the exchanges that have a mock enabled and
+// pointed at it.
+fn mock_settings(binance: Option<&MockExchange>, bitstamp: Option<&MockExchange>) -> Settings {
+    let mut settings = Settings::default();
+    let exchanges = &mut settings.exchanges;
+    for (exchange, mock) in [(&mut exchanges.binance, binance), (&mut exchanges.bitstamp, bitstamp)] {
+        exchange.enabled = mock.is_some();
+        if let Some(mock) = mock {
+            exchange.rest_url = mock.config().rest_url.to_string();
+            exchange.websocket_url = mock.config().websocket_url.to_string();
+        }
+    }
+    settings
+}
+
+fn start_pipelines(settings: &Settings) -> Pipelines {
+    let pipelines = Pipelines::new(settings.pipeline_config().unwrap());
+    for (exchange, symbol) in settings.connectors() {
+        assert!(pipelines.start_connector(exchange, symbol));
+    }
+    pipelines
+}
+
+fn connectors(pipelines: &Pipelines) -> HashSet<ExchangeName> {
+    pipelines.pipelines().iter().flat_map(|pipeline| pipeline.connectors.iter().map(|status| status.exchange)).collect()
+}
+
+async fn wait_for_connections(mock: &MockExchange, connections: usize) {
+    tokio::time::timeout(TIMEOUT, async {
+        while mock.connections() < connections {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("connector did not connect");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reload_starts_and_stops_connectors() {
+    let binance = MockExchange::start(ExchangeName::BINANCE, binance_scenario(vec![])).await;
+    let bitstamp = MockExchange::start(
+        ExchangeName::BITSTAMP,
+        Scenario {
+            snapshots: vec![bitstamp_snapshot(1_000_000, &[("2000.00", "1.00000000")], &[("2001.00", "1.50000000")])],
+            connections: vec![vec![]],
+        },
+    )
+    .await;
+    let current = mock_settings(Some(&binance), None);
+    let pipelines = start_pipelines(&current);
+    wait_for_connections(&binance, 1).await;
+
+    let next = mock_settings(None, Some(&bitstamp));
+    reload::apply(&current, &next, &pipelines).await.unwrap();
+    assert_eq!(connectors(&pipelines), HashSet::from([ExchangeName::BITSTAMP]));
+    wait_for_connections(&bitstamp, 1).await;
+
+    let both = mock_settings(Some(&binance), Some(&bitstamp));
+    reload::apply(&next, &both, &pipelines).await.unwrap();
+    assert_eq!(connectors(&pipelines), HashSet::from([ExchangeName::BINANCE, ExchangeName::BITSTAMP]));
+    wait_for_connections(&binance, 2).await;
+    // The running Bitstamp connector was left alone.
+    assert_eq!(bitstamp.connections(), 1);
+    pipelines.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reload_restarts_connectors_whose_url_changed() {
+    let first = MockExchange::start(ExchangeName::BINANCE, binance_scenario(vec![])).await;
+    let second = MockExchange::start(
+        ExchangeName::BINANCE,
+        Scenario {
+            snapshots: vec![binance_snapshot(200, &[("1990.00000000", "1.00000000")], &[("1991.00000000", "1.00000000")])],
+            connections: vec![vec![]],
+        },
+    )
+    .await;
+    let current = mock_settings(Some(&first), None);
+    let pipelines = start_pipelines(&current);
+    let mut books = pipelines.books(Symbol::ETHUSDT).unwrap();
+    tokio::time::timeout(TIMEOUT, books.wait_for(|books| !books.summary().bids.is_empty()))
+        .await
+        .expect("no book from the first mock")
+        .unwrap();
+
+    // Unchanged settings leave the connector alone.
+    reload::apply(&current, &current.clone(), &pipelines).await.unwrap();
+    let next = mock_settings(Some(&second), None);
+    reload::apply(&current, &next, &pipelines).await.unwrap();
+    wait_for_connections(&second, 1).await;
+    assert_eq!(first.connections(), 1);
+
+    // Subscribers of the pipeline keep receiving books, now from the second mock.
+    let resynced = tokio::time::timeout(
+        TIMEOUT,
+        books.wait_for(|books| books.summary().bids.first().is_some_and(|level| level.price == 1990.0)),
+    )
+    .await
+    .expect("book was not rebuilt from the second mock")
+    .unwrap()
+    .summary()
+    .clone();
+    assert_eq!(prices(&resynced.bids), vec![(1990.0, 1.0)]);
+    assert_eq!(connectors(&pipelines), HashSet::from([ExchangeName::BINANCE]));
+    pipelines.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown_drains_streams_and_closes_websockets() {
+    let mock = MockExchange::start(
+        ExchangeName::BITSTAMP,
+        Scenario {
+            snapshots: vec![bitstamp_snapshot(1_000_000, &[("2000.00", "1.00000000")], &[("2001.00", "1.50000000")])],
+            connections: vec![vec![]],
+        },
+    )
+    .await;
+    let pipelines = Pipelines::new(pipeline_config(&[(ExchangeName::BITSTAMP, &mock)]));
+    pipelines.start_connector(ExchangeName::BITSTAMP, Symbol::ETHUSDT);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let shutdown = CancellationToken::new();
+    let server = Server::builder()
+        .add_service(OrderbookAggregatorServer::new(OrderbookSummary::new(Symbol::ETHUSDT, pipelines.clone())))
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown.clone().cancelled_owned());
+    let serving = tokio::spawn(serve_until_drained(server, pipelines, shutdown.clone(), TIMEOUT));
+
+    let mut client = OrderbookAggregatorClient::connect(format!("http://{}", address)).await.unwrap();
+    let mut stream = client.book_summary(SummaryRequest::default()).await.unwrap().into_inner();
+    tokio::time::timeout(TIMEOUT, async {
+        while stream.next().await.expect("stream ended").unwrap().bids.is_empty() {}
+    })
+    .await
+    .expect("no summary was streamed");
+
+    shutdown.cancel();
+    let status = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            match stream.next().await.expect("stream ended without a status") {
+                Ok(_) => continue,
+                Err(status) => return status,
+            }
+        }
+    })
+    .await
+    .expect("stream was not drained");
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+    tokio::time::timeout(TIMEOUT, serving).await.expect("server did not return").unwrap().unwrap();
+    tokio::time::timeout(TIMEOUT, async {
+        while mock.disconnections() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("websocket was not closed");
+    assert!(mock.received().iter().any(|message| message.contains("bts:unsubscribe")));
+}
+
+#[tokio::test]
+async fn shutdown_gives_up_on_streams_that_do_not_drain() {
+    let pipelines = Pipelines::new(PipelineConfig { connectors: HashMap::new(), ..Default::default() });
+    let shutdown = CancellationToken::new();
+    let server = std::future::pending::<Result<(), std::io::Error>>();
+    let drain_timeout = Duration::from_millis(100);
+    let serving = tokio::spawn(serve_until_drained(server, pipelines, shutdown.clone(), drain_timeout));
+
+    let cancelled_at = Instant::now();
+    shutdown.cancel();
+    tokio::time::timeout(TIMEOUT, serving).await.expect("server did not give up").unwrap().unwrap();
+    assert!(cancelled_at.elapsed() >= drain_timeout);
+}
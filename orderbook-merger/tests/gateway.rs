@@ -0,0 +1,110 @@
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use axum::http::HeaderValue;
+use reqwest::{header, StatusCode};
+use tokio_util::sync::CancellationToken;
+use orderbook_merger::{
+    auth::{AuthInterceptor, ClientPermissions},
+    gateway,
+    orderbook::orderbook::OrderBookOnlyLevels,
+    orderbook_summary::Level,
+    pipeline::{PipelineConfig, Pipelines},
+    service::OrderbookSummary,
+    ExchangeName, Symbol,
+};
+
+const ALLOWED_ORIGIN: &str = "https://dashboard.example.com";
+
+fn free_address() -> SocketAddr {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+// The `start_gateway` function serves the gateway for a pipeline fed with one Binance book, with
+// a `secret` token allowed to read Binance only.
+async fn start_gateway(shutdown: CancellationToken) -> (SocketAddr, Pipelines) {
+    let pipelines = Pipelines::new(PipelineConfig { connectors: HashMap::new(), ..Default::default() });
+    let level = |price| Level { exchange: "BINANCE".to_string(), price, quantity: 1.0 };
+    let book = OrderBookOnlyLevels {
+        exchange: ExchangeName::BINANCE,
+        symbol: Symbol::ETHUSDT,
+        bids: vec![level(100.0)],
+        asks: vec![level(101.0)],
+        ..Default::default()
+    };
+    pipelines.feed(ExchangeName::BINANCE, Symbol::ETHUSDT).send(book).await.unwrap();
+    let mut books = pipelines.books(Symbol::ETHUSDT).unwrap();
+    books.wait_for(|books| !books.summary().bids.is_empty()).await.unwrap();
+
+    let permissions = ClientPermissions {
+        symbols: vec![Symbol::ETHUSDT],
+        exchanges: vec![ExchangeName::BINANCE],
+        max_depth: 5,
+        admin: false,
+    };
+    let auth = AuthInterceptor::new(HashMap::from([("secret".to_string(), permissions)]));
+    let service = OrderbookSummary::new(Symbol::ETHUSDT, pipelines.clone());
+    let router = gateway::router(service, auth, vec![HeaderValue::from_static(ALLOWED_ORIGIN)]);
+    let address = free_address();
+    tokio::spawn(gateway::serve(address, router, None, shutdown));
+    for _ in 0..50 {
+        if tokio::net::TcpStream::connect(address).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    (address, pipelines)
+}
+
+#[tokio::test]
+async fn summary_requires_a_valid_bearer_token() {
+    let shutdown = CancellationToken::new();
+    let (address, pipelines) = start_gateway(shutdown.clone()).await;
+    let client = reqwest::Client::new();
+    let get = |query: &str, token: Option<&str>| {
+        let request = client.get(format!("http://{address}/summary{query}"));
+        match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+        .send()
+    };
+
+    let response = get("", None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.text().await.unwrap(), "missing bearer token");
+    assert_eq!(get("", Some("guess")).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(get("?symbol=BTCUSDT", Some("secret")).await.unwrap().status(), StatusCode::FORBIDDEN);
+    assert_eq!(get("?depth=6", Some("secret")).await.unwrap().status(), StatusCode::FORBIDDEN);
+    assert_eq!(get("?conflation=SOMETIMES", Some("secret")).await.unwrap().status(), StatusCode::BAD_REQUEST);
+
+    let response = get("", Some("secret")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let summary: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(summary["bids"][0]["price"], 100.0);
+    assert_eq!(summary["asks"][0]["exchange"], "BINANCE");
+
+    shutdown.cancel();
+    pipelines.shutdown().await;
+}
+
+#[tokio::test]
+async fn cors_only_allows_configured_origins() {
+    let shutdown = CancellationToken::new();
+    let (address, pipelines) = start_gateway(shutdown.clone()).await;
+    let client = reqwest::Client::new();
+    let preflight = |origin: &'static str| {
+        client
+            .request(reqwest::Method::OPTIONS, format!("http://{address}/summary"))
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization")
+            .send()
+    };
+
+    let response = preflight(ALLOWED_ORIGIN).await.unwrap();
+    assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], ALLOWED_ORIGIN);
+    let response = preflight("https://attacker.example.com").await.unwrap();
+    assert!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+    shutdown.cancel();
+    pipelines.shutdown().await;
+}
@@ -0,0 +1,65 @@
+use std::{collections::HashMap, time::Duration};
+use tokio::{
+    sync::{mpsc, watch},
+    time::Instant,
+};
+use orderbook_merger::{
+    health::is_serving,
+    orderbook::orderbook::OrderBookOnlyLevels,
+    orderbook_summary::Level,
+    pipeline::{aggregate_and_broadcast_data, PipelineConfig},
+    AggregatedBooks, ExchangeName, Symbol,
+};
+
+const STALE_AFTER: Duration = Duration::from_secs(10);
+
+fn book_levels(exchange: ExchangeName, bid: f64) -> OrderBookOnlyLevels {
+    let level = |price| Level { exchange: exchange.to_string(), price, quantity: 1.0 };
+    OrderBookOnlyLevels { exchange, bids: vec![level(bid)], asks: vec![level(bid + 1.0)], ..Default::default() }
+}
+
+#[test]
+fn not_serving_until_every_exchange_is_fresh() {
+    let exchanges = [ExchangeName::BINANCE, ExchangeName::BITSTAMP];
+    let start = Instant::now();
+    let mut books = AggregatedBooks::default();
+    assert!(!is_serving(&books, &exchanges, STALE_AFTER, start));
+
+    books.last_updated.insert(ExchangeName::BINANCE, start);
+    assert!(!is_serving(&books, &exchanges, STALE_AFTER, start));
+    books.last_updated.insert(ExchangeName::BITSTAMP, start);
+    assert!(is_serving(&books, &exchanges, STALE_AFTER, start));
+    assert!(is_serving(&books, &exchanges, STALE_AFTER, start + STALE_AFTER));
+
+    // Bitstamp goes silent while Binance keeps updating.
+    let later = start + STALE_AFTER + Duration::from_secs(1);
+    books.last_updated.insert(ExchangeName::BINANCE, later);
+    assert!(!is_serving(&books, &exchanges, STALE_AFTER, later));
+    assert!(is_serving(&books, &[ExchangeName::BINANCE], STALE_AFTER, later));
+}
+
+#[tokio::test]
+async fn aggregator_leaves_out_stale_exchanges() {
+    let stale_after = Duration::from_millis(200);
+    let (_tx_config, rx_config) =
+        watch::channel(PipelineConfig { stale_after, connectors: HashMap::new(), ..Default::default() });
+    let (tx, rx) = mpsc::channel(10);
+    let (tx_books, mut rx_books) = watch::channel(AggregatedBooks::default());
+    tokio::spawn(aggregate_and_broadcast_data(Symbol::ETHUSDT, rx, tx_books, rx_config));
+
+    tx.send(book_levels(ExchangeName::BITSTAMP, 101.0)).await.unwrap();
+    tx.send(book_levels(ExchangeName::BINANCE, 100.0)).await.unwrap();
+    let books = rx_books.wait_for(|books| books.books.len() == 2).await.unwrap().clone();
+    assert_eq!(books.summary().bids[0].exchange, "BITSTAMP");
+
+    tokio::time::sleep(stale_after + Duration::from_millis(50)).await;
+    tx.send(book_levels(ExchangeName::BINANCE, 100.5)).await.unwrap();
+    let books = rx_books
+        .wait_for(|books| books.summary().bids.first().is_some_and(|level| level.price == 100.5))
+        .await
+        .unwrap()
+        .clone();
+    assert_eq!(books.books.keys().collect::<Vec<_>>(), vec![&ExchangeName::BINANCE]);
+    assert!(!books.last_updated.contains_key(&ExchangeName::BITSTAMP));
+    assert_eq!(books.summary().bids.len(), 1);
+}
@@ -0,0 +1,86 @@
+use std::{collections::HashMap, time::Duration};
+use tokio::sync::{mpsc, watch};
+use tokio_stream::StreamExt;
+use orderbook_merger::{
+    metrics,
+    orderbook::orderbook::OrderBookOnlyLevels,
+    orderbook_summary::Level,
+    pipeline::{PipelineConfig, Pipelines},
+    subscription::{subscribe, SubscriptionOptions},
+    AggregatedBooks, ExchangeName, Symbol,
+};
+
+#[tokio::test]
+async fn record_backlog_counts_queued_messages() {
+    let (tx, mut rx) = mpsc::channel(10);
+    for message in 0..3 {
+        tx.send(message).await.unwrap();
+    }
+    metrics::record_backlog("test_channel", &tx);
+    assert_eq!(metrics::CHANNEL_BACKLOG.with_label_values(&["test_channel"]).get(), 3);
+    rx.recv().await.unwrap();
+    metrics::record_backlog("test_channel", &tx);
+    assert_eq!(metrics::CHANNEL_BACKLOG.with_label_values(&["test_channel"]).get(), 2);
+}
+
+#[tokio::test]
+async fn subscribers_are_counted_until_they_go_away() {
+    let (_tx_books, rx_books) = watch::channel(AggregatedBooks::default());
+    let mut stream = subscribe(rx_books, SubscriptionOptions::default());
+    stream.next().await.unwrap().unwrap();
+    assert_eq!(metrics::SUBSCRIBERS.get(), 1);
+
+    drop(stream);
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while metrics::SUBSCRIBERS.get() != 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("subscriber was not removed");
+}
+
+#[tokio::test]
+async fn metrics_endpoint_serves_the_text_format() {
+    // Not a served symbol, so other tests do not change it.
+    metrics::SPREAD.with_label_values(&["TEST"]).set(1.5);
+    metrics::RESYNCS.with_label_values(&["BINANCE"]).inc();
+    let address = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    tokio::spawn(metrics::serve(address));
+
+    let response = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match reqwest::get(format!("http://{address}/metrics")).await {
+                Ok(response) => return response,
+                Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+            }
+        }
+    })
+    .await
+    .expect("metrics endpoint did not start");
+    assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/plain"));
+    let body = response.text().await.unwrap();
+    assert!(body.contains(r#"orderbook_spread{symbol="TEST"} 1.5"#), "{body}");
+    assert!(body.contains(r#"orderbook_resyncs_total{exchange="BINANCE"} 1"#), "{body}");
+}
+
+#[tokio::test]
+async fn spread_is_labelled_with_the_symbol() {
+    let pipelines = Pipelines::new(PipelineConfig { connectors: HashMap::new(), ..Default::default() });
+    let level = |price| Level { exchange: "BINANCE".to_string(), price, quantity: 1.0 };
+    let levels = |symbol, bid, ask| OrderBookOnlyLevels {
+        exchange: ExchangeName::BINANCE,
+        symbol,
+        bids: vec![level(bid)],
+        asks: vec![level(ask)],
+        ..Default::default()
+    };
+    for (symbol, bid, ask) in [(Symbol::ETHUSDT, 2000.0, 2000.5), (Symbol::BTCUSDT, 30000.0, 30002.0)] {
+        pipelines.feed(ExchangeName::BINANCE, symbol).send(levels(symbol, bid, ask)).await.unwrap();
+        let mut books = pipelines.books(symbol).unwrap();
+        books.wait_for(|books| !books.summary().bids.is_empty()).await.unwrap();
+    }
+    assert_eq!(metrics::SPREAD.with_label_values(&["ETHUSDT"]).get(), 0.5);
+    assert_eq!(metrics::SPREAD.with_label_values(&["BTCUSDT"]).get(), 2.0);
+    pipelines.shutdown().await;
+}
@@ -0,0 +1,321 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use axum::{extract::State, http::{StatusCode, Uri}, Router};
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpListener;
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::{
+        handshake::server::{Request, Response},
+        Message,
+    },
+};
+use url::Url;
+use orderbook_merger::{
+    exchanges::exchange::ConnectorConfig,
+    ExchangeName, Symbol,
+};
+
+// A `Step` is one thing the mock exchange does on a websocket connection.
+#[derive(Debug, Clone)]
+pub enum Step {
+    Send(Message),
+    Sleep(Duration),
+    // Drops the connection without a close frame, like a network failure.
+    Disconnect,
+    // Closes the connection with a close frame, like an exchange going into maintenance.
+    Close,
+}
+
+impl Step {
+    pub fn text(text: impl Into<String>) -> Self {
+        Step::Send(Message::Text(text.into()))
+    }
+}
+
+// The `Scenario` struct scripts a mock exchange. Each REST snapshot request gets the next entry of
+// `snapshots` and each websocket connection the next entry of `connections`; the last entry is
+// repeated once they run out.
+#[derive(Debug, Clone, Default)]
+pub struct Scenario {
+    pub snapshots: Vec<String>,
+    pub connections: Vec<Vec<Step>>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    exchange: ExchangeName,
+    scenario: Scenario,
+    snapshots_served: AtomicUsize,
+    connections: AtomicUsize,
+    // Connections closed or dropped by the connectors.
+    disconnections: AtomicUsize,
+    // Request paths of the websocket connections.
+    paths: Mutex<Vec<String>>,
+    // Text messages sent by the connectors, e.g. subscriptions.
+    received: Mutex<Vec<String>>,
+}
+
+// The `MockExchange` serves the REST and websocket endpoints of Binance or Bitstamp on local ports,
+// so connectors can be tested offline by pointing their `ConnectorConfig` at it.
+pub struct MockExchange {
+    shared: Arc<Shared>,
+    rest_address: SocketAddr,
+    websocket_address: SocketAddr,
+}
+
+impl MockExchange {
+    pub async fn start(exchange: ExchangeName, scenario: Scenario) -> Self {
+        let shared = Arc::new(Shared {
+            exchange,
+            scenario,
+            snapshots_served: AtomicUsize::new(0),
+            connections: AtomicUsize::new(0),
+            disconnections: AtomicUsize::new(0),
+            paths: Mutex::new(Vec::new()),
+            received: Mutex::new(Vec::new()),
+        });
+
+        let router = Router::new().fallback(rest).with_state(shared.clone());
+        let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(router.into_make_service());
+        let rest_address = server.local_addr();
+        tokio::spawn(server);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let websocket_address = listener.local_addr().unwrap();
+        let websocket_shared = shared.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_websocket(stream, websocket_shared.clone()));
+            }
+        });
+
+        Self {
+            shared,
+            rest_address,
+            websocket_address,
+        }
+    }
+
+    pub fn config(&self) -> ConnectorConfig {
+        ConnectorConfig {
+            rest_url: Url::parse(&format!("http://{}/", self.rest_address)).unwrap(),
+            websocket_url: Url::parse(&format!("ws://{}/", self.websocket_address)).unwrap(),
+            depth: 10,
+            update_channel_size: 100,
+            recorder: None,
+            book: None,
+            trades: None,
+        }
+    }
+
+    pub fn connections(&self) -> usize {
+        self.shared.connections.load(Ordering::SeqCst)
+    }
+
+    pub fn disconnections(&self) -> usize {
+        self.shared.disconnections.load(Ordering::SeqCst)
+    }
+
+    pub fn snapshots_served(&self) -> usize {
+        self.shared.snapshots_served.load(Ordering::SeqCst)
+    }
+
+    pub fn paths(&self) -> Vec<String> {
+        self.shared.paths.lock().unwrap().clone()
+    }
+
+    pub fn received(&self) -> Vec<String> {
+        self.shared.received.lock().unwrap().clone()
+    }
+}
+
+fn scripted<T: Clone>(entries: &[T], index: usize) -> Option<T> {
+    entries.get(index).or(entries.last()).cloned()
+}
+
+// Binance is served with a price tick of 0.01 and 8 quantity decimals, Bitstamp with 2 price and
+// 8 quantity decimals, which gives both books the same scales.
+async fn rest(State(shared): State<Arc<Shared>>, uri: Uri) -> (StatusCode, String) {
+    let path = uri.path();
+    let body = match (shared.exchange, path) {
+        (ExchangeName::BINANCE, "/exchangeInfo") => serde_json::json!({
+            "symbols": [{
+                "symbol": "ETHUSDT",
+                "baseAssetPrecision": 8,
+                "quoteAssetPrecision": 8,
+                "filters": [{"filterType": "PRICE_FILTER", "tickSize": "0.01000000"}],
+            }]
+        })
+        .to_string(),
+        (ExchangeName::BITSTAMP, "/trading-pairs-info") => serde_json::json!([{
+            "url_symbol": "ethusdt",
+            "base_decimals": 8,
+            "counter_decimals": 2,
+            "instant_order_counter_decimals": 2,
+        }])
+        .to_string(),
+        (ExchangeName::BINANCE, "/depth") | (ExchangeName::BITSTAMP, "/order_book/ethusdt") => {
+            let index = shared.snapshots_served.fetch_add(1, Ordering::SeqCst);
+            match scripted(&shared.scenario.snapshots, index) {
+                Some(snapshot) => snapshot,
+                None => return (StatusCode::SERVICE_UNAVAILABLE, "no snapshot".to_string()),
+            }
+        }
+        _ => return (StatusCode::NOT_FOUND, format!("unknown endpoint {path}")),
+    };
+    (StatusCode::OK, body)
+}
+
+async fn serve_websocket(stream: tokio::net::TcpStream, shared: Arc<Shared>) {
+    let paths = shared.clone();
+    #[allow(clippy::result_large_err)]
+    let callback = move |request: &Request, response: Response| {
+        paths.paths.lock().unwrap().push(request.uri().path().to_string());
+        Ok(response)
+    };
+    let Ok(websocket) = accept_hdr_async(stream, callback).await else {
+        return;
+    };
+    let index = shared.connections.fetch_add(1, Ordering::SeqCst);
+    let steps = scripted(&shared.scenario.connections, index).unwrap_or_default();
+    let (mut sink, mut stream) = websocket.split();
+
+    // Bitstamp only streams a channel after the client subscribed to it.
+    if shared.exchange == ExchangeName::BITSTAMP {
+        let Some(Ok(Message::Text(subscribe))) = stream.next().await else {
+            return;
+        };
+        let channel = serde_json::from_str::<serde_json::Value>(&subscribe).unwrap()["data"]["channel"].clone();
+        shared.received.lock().unwrap().push(subscribe);
+        let reply = serde_json::json!({"event": "bts:subscription_succeeded", "channel": channel, "data": {}});
+        if sink.send(Message::Text(reply.to_string())).await.is_err() {
+            return;
+        }
+    }
+
+    let received = shared.clone();
+    let reader = tokio::spawn(async move {
+        while let Some(Ok(message)) = stream.next().await {
+            if let Message::Text(text) = message {
+                received.received.lock().unwrap().push(text);
+            }
+        }
+        received.disconnections.fetch_add(1, Ordering::SeqCst);
+    });
+    for step in steps {
+        match step {
+            Step::Send(message) => {
+                if sink.send(message).await.is_err() {
+                    return;
+                }
+            }
+            Step::Sleep(duration) => tokio::time::sleep(duration).await,
+            Step::Disconnect => {
+                reader.abort();
+                return;
+            }
+            Step::Close => {
+                let _ = sink.send(Message::Close(None)).await;
+                break;
+            }
+        }
+    }
+    // The connection is kept open until the connector closes it.
+    let _ = reader.await;
+}
+
+fn levels(levels: &[(&str, &str)]) -> Vec<[String; 2]> {
+    levels.iter().map(|(price, quantity)| [price.to_string(), quantity.to_string()]).collect()
+}
+
+pub fn binance_snapshot(last_update_id: u64, bids: &[(&str, &str)], asks: &[(&str, &str)]) -> String {
+    serde_json::json!({"lastUpdateId": last_update_id, "bids": levels(bids), "asks": levels(asks)}).to_string()
+}
+
+pub fn binance_update(first_update_id: u64, last_update_id: u64, bids: &[(&str, &str)], asks: &[(&str, &str)]) -> Step {
+    Step::text(
+        serde_json::json!({
+            "e": "depthUpdate",
+            "E": 1_700_000_000_000u64,
+            "s": "ETHUSDT",
+            "U": first_update_id,
+            "u": last_update_id,
+            "b": levels(bids),
+            "a": levels(asks),
+        })
+        .to_string(),
+    )
+}
+
+pub fn bitstamp_snapshot(microtimestamp: u64, bids: &[(&str, &str)], asks: &[(&str, &str)]) -> String {
+    serde_json::json!({
+        "timestamp": (microtimestamp / 1_000_000).to_string(),
+        "microtimestamp": microtimestamp.to_string(),
+        "bids": levels(bids),
+        "asks": levels(asks),
+    })
+    .to_string()
+}
+
+pub fn bitstamp_update(symbol: Symbol, microtimestamp: u64, bids: &[(&str, &str)], asks: &[(&str, &str)]) -> Step {
+    Step::text(
+        serde_json::json!({
+            "data": {
+                "timestamp": (microtimestamp / 1_000_000).to_string(),
+                "microtimestamp": microtimestamp.to_string(),
+                "bids": levels(bids),
+                "asks": levels(asks),
+            },
+            "channel": format!("diff_order_book_{}", symbol.to_string().to_lowercase()),
+            "event": "data",
+        })
+        .to_string(),
+    )
+}
+
+pub fn binance_trade(trade_id: u64, price: &str, quantity: &str, buyer_is_maker: bool) -> Step {
+    Step::text(
+        serde_json::json!({
+            "e": "trade",
+            "E": 1_700_000_000_001u64,
+            "s": "ETHUSDT",
+            "t": trade_id,
+            "p": price,
+            "q": quantity,
+            "T": 1_700_000_000_000u64,
+            "m": buyer_is_maker,
+            "M": true,
+        })
+        .to_string(),
+    )
+}
+
+// Bitstamp trades have a `type` of 0 when the buyer took liquidity and 1 when the seller did.
+pub fn bitstamp_trade(symbol: Symbol, trade_id: u64, price: &str, amount: &str, side: u8, microtimestamp: u64) -> Step {
+    Step::text(
+        serde_json::json!({
+            "data": {
+                "id": trade_id,
+                "timestamp": (microtimestamp / 1_000_000).to_string(),
+                "amount": amount.parse::<f64>().unwrap(),
+                "amount_str": amount,
+                "price": price.parse::<f64>().unwrap(),
+                "price_str": price,
+                "type": side,
+                "microtimestamp": microtimestamp.to_string(),
+                "buy_order_id": 1,
+                "sell_order_id": 2,
+            },
+            "channel": format!("live_trades_{}", symbol.to_string().to_lowercase()),
+            "event": "trade",
+        })
+        .to_string(),
+    )
+}
@@ -0,0 +1,260 @@
+use std::collections::BTreeMap;
+use proptest::prelude::*;
+use rust_decimal::Decimal;
+use orderbook_merger::{
+    display_to_storage,
+    exchanges::binance::{BookUpdate, Snapshot},
+    make_summary,
+    orderbook::orderbook::{OrderBook, OrderBookOnlyLevels, StorageLevel, Update},
+    str_to_storage, ExchangeName, Symbol, ToStorage,
+};
+
+const PRICE_SCALE: u32 = 2;
+const QUANTITY_SCALE: u32 = 8;
+const DEPTH: usize = 10;
+
+fn new_book(exchange: ExchangeName, depth: usize) -> OrderBook {
+    OrderBook::new_orderbook(exchange, Symbol::ETHUSDT, PRICE_SCALE, QUANTITY_SCALE, depth)
+}
+
+fn price(ticks: u64) -> Decimal {
+    Decimal::new(ticks as i64, PRICE_SCALE)
+}
+
+fn quantity(ticks: u64) -> Decimal {
+    Decimal::new(ticks as i64, QUANTITY_SCALE)
+}
+
+// Quantities are zero often enough for levels to be removed as well as added.
+fn quantity_ticks() -> impl Strategy<Value = u64> {
+    prop_oneof![1 => Just(0u64), 4 => 1..1_000_000_000u64]
+}
+
+// A change is a side, true for bids, with a price and quantity in ticks.
+fn changes() -> impl Strategy<Value = Vec<(bool, u64, u64)>> {
+    prop::collection::vec((any::<bool>(), 1..2_000u64, quantity_ticks()), 0..200)
+}
+
+fn side() -> impl Strategy<Value = BTreeMap<Decimal, Decimal>> {
+    prop::collection::btree_map(1..500u64, quantity_ticks(), 0..20).prop_map(|levels| {
+        levels
+            .into_iter()
+            .map(|(price_ticks, quantity_ticks)| (price(price_ticks), quantity(quantity_ticks)))
+            .collect()
+    })
+}
+
+fn apply_changes(book: &mut OrderBook, changes: &[(bool, u64, u64)]) {
+    for &(is_bid, price_ticks, quantity_ticks) in changes {
+        let level = [price(price_ticks), quantity(quantity_ticks)];
+        if is_bid {
+            book.add_bid(level).unwrap();
+        } else {
+            book.add_ask(level).unwrap();
+        }
+    }
+}
+
+fn merge(state: &mut BTreeMap<Decimal, Decimal>, changes: &BTreeMap<Decimal, Decimal>) {
+    for (price, quantity) in changes {
+        if quantity.is_zero() {
+            state.remove(price);
+        } else {
+            state.insert(*price, *quantity);
+        }
+    }
+}
+
+fn storage_levels(side: &BTreeMap<Decimal, Decimal>) -> Vec<StorageLevel> {
+    side.iter()
+        .map(|(price, quantity)| (price.to_storage(PRICE_SCALE).unwrap(), quantity.to_storage(QUANTITY_SCALE).unwrap()))
+        .collect()
+}
+
+fn book_levels(exchange: ExchangeName, changes: &[(bool, u64, u64)]) -> OrderBookOnlyLevels {
+    let mut book = new_book(exchange, DEPTH);
+    apply_changes(&mut book, changes);
+    book.get_book_levels().unwrap_or(OrderBookOnlyLevels { exchange, ..Default::default() })
+}
+
+proptest! {
+    #[test]
+    fn bids_descend_and_asks_ascend(changes in changes()) {
+        let mut book = new_book(ExchangeName::BINANCE, DEPTH);
+        apply_changes(&mut book, &changes);
+
+        let bids = book.get_bids_levels().unwrap();
+        let asks = book.get_asks_levels().unwrap();
+        prop_assert!(bids.windows(2).all(|pair| pair[0].price > pair[1].price));
+        prop_assert!(asks.windows(2).all(|pair| pair[0].price < pair[1].price));
+        prop_assert_eq!(bids.len(), book.bids.len().min(DEPTH));
+        prop_assert_eq!(asks.len(), book.asks.len().min(DEPTH));
+        prop_assert!(bids.iter().chain(&asks).all(|level| level.quantity > 0.0));
+    }
+
+    #[test]
+    fn zero_quantity_removes_level(changes in changes(), price_ticks in 1..2_000u64) {
+        let mut book = new_book(ExchangeName::BINANCE, usize::from(u16::MAX));
+        apply_changes(&mut book, &changes);
+        book.add_bid([price(price_ticks), quantity(1)]).unwrap();
+        book.add_ask([price(price_ticks), quantity(1)]).unwrap();
+
+        book.add_bid([price(price_ticks), Decimal::ZERO]).unwrap();
+        book.add_ask([price(price_ticks), Decimal::ZERO]).unwrap();
+        let removed = price_ticks as f64 / 100.0;
+        prop_assert!(book.get_bids_levels().unwrap().iter().all(|level| level.price != removed));
+        prop_assert!(book.get_asks_levels().unwrap().iter().all(|level| level.price != removed));
+    }
+
+    // Exchanges send amounts with any number of decimals up to the scale, e.g. `1.5` as well as
+    // `1.50000000`, which must be stored alike.
+    #[test]
+    fn levels_round_trip(price_ticks in 1..10_000_000u64, quantity_ticks in 1..1_000_000_000_000u64) {
+        let mut book = new_book(ExchangeName::BINANCE, DEPTH);
+        book.add_bid([price(price_ticks).normalize(), quantity(quantity_ticks).normalize()]).unwrap();
+        book.add_ask([price(price_ticks), quantity(quantity_ticks)]).unwrap();
+
+        let bid = &book.get_bids_levels().unwrap()[0];
+        let ask = &book.get_asks_levels().unwrap()[0];
+        let expected = (price_ticks as f64 / 1e2, quantity_ticks as f64 / 1e8);
+        prop_assert_eq!((bid.price, bid.quantity), expected);
+        prop_assert_eq!((ask.price, ask.quantity), expected);
+    }
+
+    // Levels are parsed from the exchanges' strings without a `Decimal`, which must give the same
+    // storage amounts, including for amounts with more decimals than the scale.
+    #[test]
+    fn str_to_storage_matches_display_to_storage(
+        mantissa in 0..1_000_000_000_000_000u64,
+        decimals in 0..16u32,
+        scale in 0..12u32,
+        normalize in any::<bool>(),
+    ) {
+        let mut amount = Decimal::new(mantissa as i64, decimals);
+        if normalize {
+            amount = amount.normalize();
+        }
+        let parsed = str_to_storage(&amount.to_string(), scale);
+        match display_to_storage(amount, scale) {
+            Ok(expected) => prop_assert_eq!(parsed.unwrap(), expected),
+            Err(_) => prop_assert!(parsed.is_err()),
+        }
+    }
+
+    #[test]
+    fn snapshot_then_diffs_equals_combined_state(
+        snapshot_bids in side(),
+        snapshot_asks in side(),
+        diffs in prop::collection::vec((side(), side()), 0..20),
+    ) {
+        // Snapshots only contain levels with a quantity.
+        let snapshot_bids: BTreeMap<_, _> = snapshot_bids.into_iter().filter(|(_, q)| !q.is_zero()).collect();
+        let snapshot_asks: BTreeMap<_, _> = snapshot_asks.into_iter().filter(|(_, q)| !q.is_zero()).collect();
+
+        let mut book = new_book(ExchangeName::BINANCE, DEPTH);
+        let (mut bids, mut asks) = (snapshot_bids.clone(), snapshot_asks.clone());
+        let snapshot = Snapshot {
+            last_update_id: 100,
+            bids: storage_levels(&snapshot_bids),
+            asks: storage_levels(&snapshot_asks),
+        };
+        book.update(&BookUpdate::from(snapshot)).unwrap();
+        let mut last_update_id = 100;
+        for (diff_bids, diff_asks) in diffs {
+            last_update_id += 1;
+            merge(&mut bids, &diff_bids);
+            merge(&mut asks, &diff_asks);
+            let diff = BookUpdate {
+                first_update_id: last_update_id,
+                last_update_id,
+                bids: storage_levels(&diff_bids),
+                asks: storage_levels(&diff_asks),
+            };
+            book.update(&diff).unwrap();
+        }
+
+        let mut combined = new_book(ExchangeName::BINANCE, DEPTH);
+        let snapshot = Snapshot { last_update_id, bids: storage_levels(&bids), asks: storage_levels(&asks) };
+        combined.update(&BookUpdate::from(snapshot)).unwrap();
+        prop_assert_eq!(&book.bids, &combined.bids);
+        prop_assert_eq!(&book.asks, &combined.asks);
+        prop_assert_eq!(book.last_update_id, combined.last_update_id);
+        prop_assert_eq!(
+            book.get_book_levels().map(|levels| (levels.bids, levels.asks)),
+            combined.get_book_levels().map(|levels| (levels.bids, levels.asks))
+        );
+    }
+
+    #[test]
+    fn merged_summary_is_sorted_and_limited(books in prop::collection::vec(changes(), 1..5)) {
+        let exchanges = [ExchangeName::BINANCE, ExchangeName::BITSTAMP];
+        let books: Vec<_> = books
+            .iter()
+            .enumerate()
+            .map(|(i, changes)| book_levels(exchanges[i % exchanges.len()], changes))
+            .collect();
+        // N is the depth of the books, the most levels on either side of any book.
+        let depth = books.iter().map(|book| book.bids.len().max(book.asks.len())).max().unwrap();
+        let mut all_bids: Vec<f64> = books.iter().flat_map(|book| book.bids.iter().map(|level| level.price)).collect();
+        let mut all_asks: Vec<f64> = books.iter().flat_map(|book| book.asks.iter().map(|level| level.price)).collect();
+        all_bids.sort_by(|a, b| b.total_cmp(a));
+        all_asks.sort_by(|a, b| a.total_cmp(b));
+
+        let summary = make_summary(books);
+        prop_assert!(summary.bids.windows(2).all(|pair| pair[0].price >= pair[1].price));
+        prop_assert!(summary.asks.windows(2).all(|pair| pair[0].price <= pair[1].price));
+        prop_assert_eq!(summary.bids.len(), all_bids.len().min(depth));
+        prop_assert_eq!(summary.asks.len(), all_asks.len().min(depth));
+        // The merged levels are the best levels of all books.
+        let prices = |levels: &[orderbook_merger::orderbook_summary::Level]| {
+            levels.iter().map(|level| level.price).collect::<Vec<_>>()
+        };
+        prop_assert_eq!(prices(&summary.bids), all_bids[..summary.bids.len()].to_vec());
+        prop_assert_eq!(prices(&summary.asks), all_asks[..summary.asks.len()].to_vec());
+
+        match (summary.bids.first(), summary.asks.first()) {
+            (Some(best_bid), Some(best_ask)) => prop_assert_eq!(summary.spread, best_ask.price - best_bid.price),
+            _ => prop_assert_eq!(summary.spread, 0.0),
+        }
+    }
+}
+
+fn binance_diff(first_update_id: u64, last_update_id: u64, bid_ticks: u64) -> BookUpdate {
+    let bids = BTreeMap::from([(price(bid_ticks), quantity(100))]);
+    BookUpdate { first_update_id, last_update_id, bids: storage_levels(&bids), asks: vec![] }
+}
+
+// Binance buffers diffs while the snapshot is fetched: diffs the snapshot already contains are
+// skipped, the first one applied may overlap it, and every later one must follow the previous one.
+#[test]
+fn binance_skips_stale_diffs_and_rejects_gaps() {
+    let mut book = new_book(ExchangeName::BINANCE, DEPTH);
+    let bids = BTreeMap::from([(price(1_000), quantity(100))]);
+    let snapshot = Snapshot { last_update_id: 100, bids: storage_levels(&bids), asks: vec![] };
+    book.update(&BookUpdate::from(snapshot)).unwrap();
+
+    let stale = binance_diff(90, 100, 900);
+    assert!(stale.is_stale(100));
+    book.update(&stale).unwrap();
+    assert_eq!(book.last_update_id, 100);
+    assert_eq!(book.bids.len(), 1);
+
+    let overlapping = binance_diff(95, 102, 1_001);
+    assert!(!overlapping.is_stale(100));
+    book.update(&overlapping).unwrap();
+    assert_eq!(book.last_update_id, 102);
+    book.update(&binance_diff(103, 105, 1_002)).unwrap();
+    assert_eq!(book.bids.len(), 3);
+
+    let error = book.update(&binance_diff(107, 108, 1_003)).unwrap_err();
+    assert_eq!(error.to_string(), "first_update_id: 107 != last_id: 105 + 1");
+    assert_eq!(book.last_update_id, 105);
+    // Only the first diff after the snapshot may overlap the book, later ones repeating updates
+    // are out of sequence.
+    let error = book.update(&binance_diff(104, 106, 1_003)).unwrap_err();
+    assert_eq!(error.to_string(), "first_update_id: 104 != last_id: 105 + 1");
+    assert_eq!(book.last_update_id, 105);
+    assert_eq!(book.bids.len(), 3);
+    // An empty book accepts any diff.
+    assert!(binance_diff(500, 600, 1_000).validate(0, 0).is_ok());
+}
@@ -0,0 +1,149 @@
+use std::{
+    io::Write,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use flate2::{write::GzEncoder, Compression};
+use orderbook_merger::{
+    metrics,
+    recorder::{Record, RecordKind, RecordReader, Recorder, RecorderConfig, MAX_RECORD_LEN},
+    ExchangeName, Symbol,
+};
+
+fn record(kind: RecordKind, payload: &[u8]) -> Record {
+    Record {
+        received_at: UNIX_EPOCH + Duration::from_nanos(1_700_000_000_123_456_789),
+        exchange: ExchangeName::BITSTAMP,
+        symbol: Symbol::BTCUSDT,
+        kind,
+        payload: payload.to_vec(),
+    }
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("orderbook-merger-recorder-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+// The `recordings` function returns the files of `dir` in name order, which is the order they
+// were written in.
+fn recordings(dir: &PathBuf) -> Vec<PathBuf> {
+    let mut paths: Vec<_> = std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn records_round_trip_through_encode_and_decode() {
+    for (kind, payload) in [
+        (RecordKind::Text, &br#"{"e":"depthUpdate"}"#[..]),
+        (RecordKind::Binary, &[0xff, 0x00, 0x7b]),
+        (RecordKind::Close, &[]),
+        (RecordKind::Snapshot, b"{}"),
+    ] {
+        let record = record(kind, payload);
+        let mut encoded = Vec::new();
+        record.encode(&mut encoded).unwrap();
+        let len = u32::from_be_bytes(encoded[..4].try_into().unwrap()) as usize;
+        assert_eq!(len, encoded.len() - 4);
+        assert_eq!(Record::decode(&encoded[4..]).unwrap(), record);
+        // Cut inside the symbol.
+        assert!(Record::decode(&encoded[4..encoded.len() - payload.len() - 1]).is_err());
+    }
+
+    let mut encoded = Vec::new();
+    record(RecordKind::Text, b"").encode(&mut encoded).unwrap();
+    encoded[12] = 42;
+    assert_eq!(Record::decode(&encoded[4..]).unwrap_err().to_string(), "unknown record kind: 42");
+}
+
+#[test]
+fn oversized_records_are_rejected() {
+    let mut encoded = Vec::new();
+    let error = record(RecordKind::Binary, &vec![0; MAX_RECORD_LEN]).encode(&mut encoded).unwrap_err();
+    assert!(error.to_string().contains("exceeds"), "{error}");
+    assert!(encoded.is_empty());
+
+    // A corrupt length is an error, not an allocation of up to 4 GiB.
+    let dir = temp_dir("oversized");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("corrupt.rec.gz");
+    let mut encoder = GzEncoder::new(std::fs::File::create(&path).unwrap(), Compression::default());
+    encoder.write_all(&u32::MAX.to_be_bytes()).unwrap();
+    encoder.finish().unwrap();
+    let error = RecordReader::open(&path).unwrap().next().unwrap().unwrap_err();
+    assert_eq!(error.to_string(), format!("record of {} bytes exceeds {MAX_RECORD_LEN} bytes", u32::MAX));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn recorder_rotates_files_and_flushes() {
+    let dir = temp_dir("rotation");
+    let config = RecorderConfig {
+        directory: dir.clone(),
+        // Every record fills a file.
+        max_file_bytes: 1,
+        max_file_age: Duration::from_secs(3600),
+        channel_size: 100,
+    };
+    let recorder = Recorder::start(config).unwrap();
+    let payloads: Vec<Vec<u8>> = (0..3).map(|i| format!("message {i}").into_bytes()).collect();
+    for payload in &payloads {
+        recorder.record(Record { received_at: SystemTime::now(), ..record(RecordKind::Text, payload) });
+    }
+    recorder.flush().await.unwrap();
+
+    let files = recordings(&dir);
+    assert_eq!(files.len(), 3);
+    let read: Vec<Vec<u8>> = files
+        .iter()
+        .flat_map(|path| RecordReader::open(path).unwrap().map(|record| record.unwrap().payload))
+        .collect();
+    assert_eq!(read, payloads);
+
+    // Flushing finished the last file, so the next record starts a new one.
+    recorder.record_snapshot(ExchangeName::BINANCE, Symbol::ETHUSDT, b"{}");
+    recorder.flush().await.unwrap();
+    let files = recordings(&dir);
+    assert_eq!(files.len(), 4);
+    let snapshot = RecordReader::open(&files[3]).unwrap().next().unwrap().unwrap();
+    assert_eq!((snapshot.kind, snapshot.exchange), (RecordKind::Snapshot, ExchangeName::BINANCE));
+    assert_eq!(snapshot.payload, b"{}");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn recorder_config(directory: PathBuf) -> RecorderConfig {
+    RecorderConfig { directory, max_file_bytes: 1024 * 1024, max_file_age: Duration::from_secs(3600), channel_size: 100 }
+}
+
+fn read_payloads(dir: &PathBuf) -> Vec<Vec<u8>> {
+    recordings(dir)
+        .iter()
+        .flat_map(|path| RecordReader::open(path).unwrap().map(|record| record.unwrap().payload))
+        .collect()
+}
+
+#[tokio::test]
+async fn recorder_keeps_recording_after_errors() {
+    let dir = temp_dir("errors");
+    let recorder = Recorder::start(recorder_config(dir.clone())).unwrap();
+    let dropped = metrics::RECORDS_DROPPED.get();
+
+    recorder.record(record(RecordKind::Binary, &vec![0; MAX_RECORD_LEN]));
+    recorder.record(record(RecordKind::Text, b"after oversized"));
+    recorder.flush().await.unwrap();
+    assert_eq!(read_payloads(&dir), vec![b"after oversized".to_vec()]);
+    assert_eq!(metrics::RECORDS_DROPPED.get(), dropped + 1);
+
+    // The next file can not be created while the directory is gone.
+    std::fs::remove_dir_all(&dir).unwrap();
+    recorder.record(record(RecordKind::Text, b"lost"));
+    recorder.flush().await.unwrap();
+    assert_eq!(metrics::RECORDS_DROPPED.get(), dropped + 2);
+    std::fs::create_dir_all(&dir).unwrap();
+    recorder.record(record(RecordKind::Text, b"after failure"));
+    recorder.flush().await.unwrap();
+    assert_eq!(read_payloads(&dir), vec![b"after failure".to_vec()]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,160 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+use orderbook_merger::{
+    orderbook_summary::Level,
+    pipeline::{PipelineConfig, Pipelines},
+    recorder::{Record, RecordKind, Recorder, RecorderConfig, Scales},
+    replay::{replay, ReplayConfig},
+    ExchangeName, Symbol,
+};
+use tokio_util::sync::CancellationToken;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn levels(levels: &[(&str, &str)]) -> Vec<[String; 2]> {
+    levels.iter().map(|(price, quantity)| [price.to_string(), quantity.to_string()]).collect()
+}
+
+fn snapshot(last_update_id: u64, bids: &[(&str, &str)], asks: &[(&str, &str)]) -> (RecordKind, String) {
+    let body = serde_json::json!({"lastUpdateId": last_update_id, "bids": levels(bids), "asks": levels(asks)});
+    (RecordKind::Snapshot, body.to_string())
+}
+
+fn update(first_update_id: u64, last_update_id: u64, bids: &[(&str, &str)]) -> (RecordKind, String) {
+    let message = serde_json::json!({
+        "e": "depthUpdate",
+        "E": 1_700_000_000_000u64,
+        "s": "ETHUSDT",
+        "U": first_update_id,
+        "u": last_update_id,
+        "b": levels(bids),
+        "a": [],
+    });
+    (RecordKind::Text, message.to_string())
+}
+
+fn prices(levels: &[Level]) -> Vec<(f64, f64)> {
+    levels.iter().map(|level| (level.price, level.quantity)).collect()
+}
+
+// The `write_recording` function records the scales of the Binance ETHUSDT book followed by
+// `records`, `interval` apart, and returns the recording directory.
+async fn write_recording(name: &str, records: Vec<(RecordKind, String)>, interval: Duration) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("orderbook-merger-replay-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    let config = RecorderConfig {
+        directory: directory.clone(),
+        max_file_bytes: 1024 * 1024,
+        max_file_age: Duration::from_secs(3600),
+        channel_size: 100,
+    };
+    let recorder = Recorder::start(config).unwrap();
+    let scales = serde_json::to_vec(&Scales { price_scale: 2, quantity_scale: 8 }).unwrap();
+    let start = SystemTime::now();
+    let scales = (RecordKind::Scales, String::from_utf8(scales).unwrap());
+    for (index, (kind, payload)) in std::iter::once(scales).chain(records).enumerate() {
+        recorder.record(Record {
+            received_at: start + interval * index as u32,
+            exchange: ExchangeName::BINANCE,
+            symbol: Symbol::ETHUSDT,
+            kind,
+            payload: payload.into_bytes(),
+        });
+    }
+    recorder.flush().await.unwrap();
+    directory
+}
+
+fn pipelines() -> Pipelines {
+    Pipelines::new(PipelineConfig { connectors: HashMap::new(), ..Default::default() })
+}
+
+#[tokio::test]
+async fn replay_publishes_the_recorded_book_and_resyncs_after_a_gap() {
+    let recording = write_recording(
+        "resync",
+        vec![
+            snapshot(100, &[("2000.00", "1.0")], &[("2001.00", "1.5")]),
+            update(101, 102, &[("2000.50", "0.5")]),
+            (RecordKind::Text, r#"{"result":null,"id":1}"#.to_string()),
+            // Updates 103 and 104 were missed, so the book waits for the next snapshot.
+            update(105, 106, &[("2000.25", "0.5")]),
+            update(107, 107, &[("1999.00", "3.0")]),
+            snapshot(200, &[("1990.00", "1.0")], &[("1991.00", "1.0")]),
+            update(201, 201, &[("1990.50", "2.0")]),
+        ],
+        Duration::from_millis(1),
+    )
+    .await;
+    let pipelines = pipelines();
+    let config = ReplayConfig { path: recording.clone(), speed: 0.0, depth: 10 };
+    tokio::time::timeout(TIMEOUT, replay(config, pipelines.clone(), CancellationToken::new()))
+        .await
+        .expect("replay did not finish")
+        .unwrap();
+
+    let book = pipelines.exchange_book(ExchangeName::BINANCE, Symbol::ETHUSDT).unwrap().load();
+    assert_eq!(book.last_update_id, 201);
+    assert_eq!(book.bids, vec![(199050, 200000000), (199000, 100000000)]);
+    assert_eq!(book.asks, vec![(199100, 100000000)]);
+
+    let mut books = pipelines.books(Symbol::ETHUSDT).unwrap();
+    let summary = tokio::time::timeout(
+        TIMEOUT,
+        books.wait_for(|books| books.summary().bids.first().is_some_and(|level| level.price == 1990.5)),
+    )
+    .await
+    .expect("summary of the replayed book was not published")
+    .unwrap()
+    .summary()
+    .clone();
+    assert_eq!(prices(&summary.bids), vec![(1990.5, 2.0), (1990.0, 1.0)]);
+    assert_eq!(prices(&summary.asks), vec![(1991.0, 1.0)]);
+    assert_eq!(summary.bids[0].exchange, "BINANCE");
+    pipelines.shutdown().await;
+    std::fs::remove_dir_all(&recording).unwrap();
+}
+
+#[tokio::test]
+async fn replay_stops_when_cancelled() {
+    let recording = write_recording(
+        "cancel",
+        vec![
+            snapshot(100, &[("2000.00", "1.0")], &[("2001.00", "1.5")]),
+            update(101, 101, &[("2000.50", "0.5")]),
+        ],
+        Duration::from_secs(3600),
+    )
+    .await;
+    let pipelines = pipelines();
+    let shutdown = CancellationToken::new();
+    // At the recorded pace the update is an hour away.
+    let config = ReplayConfig { path: recording.clone(), speed: 1.0, depth: 10 };
+    let replaying = tokio::spawn(replay(config, pipelines.clone(), shutdown.clone()));
+
+    tokio::time::timeout(TIMEOUT, async {
+        while pipelines.exchange_book(ExchangeName::BINANCE, Symbol::ETHUSDT).is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("replay did not start");
+    shutdown.cancel();
+    tokio::time::timeout(TIMEOUT, replaying).await.expect("replay was not cancelled").unwrap().unwrap();
+    assert_eq!(pipelines.exchange_book(ExchangeName::BINANCE, Symbol::ETHUSDT).unwrap().load().last_update_id, 0);
+    pipelines.shutdown().await;
+    std::fs::remove_dir_all(&recording).unwrap();
+}
+
+#[tokio::test]
+async fn replay_without_recordings_fails() {
+    let directory = std::env::temp_dir().join(format!("orderbook-merger-replay-empty-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let config = ReplayConfig { path: directory.clone(), speed: 0.0, depth: 10 };
+    let error = replay(config, pipelines(), CancellationToken::new()).await.unwrap_err();
+    assert!(error.to_string().starts_with("no recordings found"), "{error}");
+    std::fs::remove_dir_all(&directory).unwrap();
+}
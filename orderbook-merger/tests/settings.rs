@@ -0,0 +1,132 @@
+use std::{collections::HashMap, path::{Path, PathBuf}};
+use orderbook_merger::{
+    settings::{
+        env_overrides, ChannelSettings, LoggingSettings, RecorderSettings, ReplaySettings, Settings, DEFAULT_CONFIG_PATH,
+    },
+    ExchangeName, Symbol,
+};
+
+#[test]
+fn client_ca_requires_a_server_certificate() {
+    let settings = Settings { tls_client_ca: Some(PathBuf::from("certs/ca.pem")), ..Default::default() };
+    let error = settings.validate().unwrap_err();
+    assert_eq!(error.to_string(), "tls-client-ca requires tls-cert and tls-key");
+
+    let settings = Settings {
+        tls_cert: Some(PathBuf::from("certs/server.pem")),
+        tls_key: Some(PathBuf::from("certs/server.key")),
+        ..settings
+    };
+    assert!(settings.validate().is_ok());
+}
+
+fn args(args: &[&str]) -> impl Iterator<Item = String> {
+    args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().into_iter()
+}
+
+fn vars(vars: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+    vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect::<Vec<_>>().into_iter()
+}
+
+#[test]
+fn env_overrides_map_names_to_setting_keys() {
+    let overrides = env_overrides(vars(&[
+        ("ORDERBOOK_SERVER_PORT", "5560"),
+        ("ORDERBOOK_CHANNELS__ORDERBOOK_LEVELS", "50"),
+        ("ORDERBOOK_EXCHANGES__BITSTAMP__ENABLED", "false"),
+        ("HOME", "/root"),
+        ("orderbook_depth", "5"),
+    ]));
+    assert_eq!(
+        overrides,
+        HashMap::from([
+            ("server-port".to_string(), "5560".to_string()),
+            ("channels.orderbook-levels".to_string(), "50".to_string()),
+            ("exchanges.bitstamp.enabled".to_string(), "false".to_string()),
+        ])
+    );
+}
+
+#[test]
+fn environment_overrides_the_default_settings_file() {
+    let path = Path::new(DEFAULT_CONFIG_PATH);
+    assert!(path.is_absolute());
+    let defaults = Settings::load_with_env(path, vars(&[])).unwrap();
+    let settings = Settings::load_with_env(
+        path,
+        vars(&[
+            ("ORDERBOOK_SERVER_PORT", "5560"),
+            ("ORDERBOOK_CHANNELS__ORDERBOOK_LEVELS", "50"),
+            ("ORDERBOOK_EXCHANGES__BITSTAMP__ENABLED", "false"),
+        ]),
+    )
+    .unwrap();
+    assert_eq!(settings.server_port, 5560);
+    assert_eq!(settings.channels.orderbook_levels, 50);
+    assert_eq!(settings.exchanges.enabled(), vec![ExchangeName::BINANCE]);
+    assert_ne!(defaults.server_port, 5560);
+    assert_eq!(settings.symbols, defaults.symbols);
+
+    let error = Settings::load_with_env(path, vars(&[("ORDERBOOK_DEPTH", "0")])).unwrap_err();
+    assert_eq!(error.to_string(), "depth must be greater than 0");
+}
+
+#[test]
+fn config_path_comes_from_the_arguments() {
+    assert_eq!(Settings::parse_config_path(args(&[])).unwrap(), PathBuf::from(DEFAULT_CONFIG_PATH));
+    for arguments in [&["--config", "my.toml"][..], &["-c", "my.toml"], &["--config=my.toml"]] {
+        assert_eq!(Settings::parse_config_path(args(arguments)).unwrap(), PathBuf::from("my.toml"));
+    }
+    let error = Settings::parse_config_path(args(&["--config"])).unwrap_err();
+    assert_eq!(error.to_string(), "--config needs a path");
+    let error = Settings::parse_config_path(args(&["--verbose"])).unwrap_err();
+    assert_eq!(error.to_string(), "unknown argument: --verbose");
+}
+
+#[test]
+fn validate_rejects_invalid_settings() {
+    let defaults = Settings::default();
+    assert!(defaults.validate().is_ok());
+    let mut exchanges = defaults.exchanges.clone();
+    exchanges.binance.enabled = false;
+    exchanges.bitstamp.enabled = false;
+    let mut bad_url = defaults.exchanges.clone();
+    bad_url.binance.rest_url = "not a url".to_string();
+    let channels = |orderbook_levels, updates, trades| ChannelSettings { orderbook_levels, updates, trades };
+    let recorder = |max_file_mb, max_file_secs, channel_size| {
+        Some(RecorderSettings { max_file_mb, max_file_secs, channel_size, ..Default::default() })
+    };
+    let replay = |speed| Some(ReplaySettings { speed, ..Default::default() });
+
+    let cases = [
+        (Settings { symbols: vec![], ..Default::default() }, "at least one symbol must be configured"),
+        (Settings { default_symbol: Some(Symbol::BTCUSDT), ..Default::default() }, "default-symbol BTCUSDT is not in symbols"),
+        (Settings { exchanges, ..Default::default() }, "at least one exchange must be enabled"),
+        (Settings { depth: 0, ..Default::default() }, "depth must be greater than 0"),
+        (Settings { stale_after_secs: 0, ..Default::default() }, "stale-after-secs must be greater than 0"),
+        (Settings { candle_history: 0, ..Default::default() }, "candle-history must be greater than 0"),
+        (Settings { channels: channels(0, 1, 1), ..Default::default() }, "channels.orderbook-levels must be greater than 0"),
+        (Settings { channels: channels(1, 0, 1), ..Default::default() }, "channels.updates must be greater than 0"),
+        (Settings { channels: channels(1, 1, 0), ..Default::default() }, "channels.trades must be greater than 0"),
+        (
+            Settings { logging: LoggingSettings { level: "loud".to_string(), line_number: true }, ..Default::default() },
+            "unknown log level: loud",
+        ),
+        (Settings { recorder: recorder(0, 1, 1), ..Default::default() }, "recorder.max-file-mb must be greater than 0"),
+        (Settings { recorder: recorder(1, 0, 1), ..Default::default() }, "recorder.max-file-secs must be greater than 0"),
+        (Settings { recorder: recorder(1, 1, 0), ..Default::default() }, "recorder.channel-size must be greater than 0"),
+        (Settings { replay: replay(-1.0), ..Default::default() }, "replay.speed must be 0 or greater"),
+        (Settings { replay: replay(f64::NAN), ..Default::default() }, "replay.speed must be 0 or greater"),
+        (Settings { exchanges: bad_url, ..Default::default() }, "invalid BINANCE url: not a url/"),
+        (Settings { http_port: Some(5557), ws_port: Some(5557), ..Default::default() }, "port 5557 is configured more than once"),
+        (Settings { tls_cert: Some(PathBuf::from("server.pem")), ..Default::default() }, "tls-cert and tls-key must be set together"),
+        (Settings { cors_origins: vec!["bad\norigin".to_string()], ..Default::default() }, "invalid cors origin: bad\norigin"),
+        (
+            Settings { tls_client_key: Some(PathBuf::from("client.key")), ..Default::default() },
+            "tls-client-cert and tls-client-key must be set together",
+        ),
+    ];
+    for (settings, expected) in cases {
+        assert_eq!(settings.validate().unwrap_err().to_string(), expected);
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+use tokio_stream::StreamExt;
+use orderbook_merger::{
+    make_summary,
+    orderbook::orderbook::OrderBookOnlyLevels,
+    orderbook_summary::{Conflation, Level, Summary, SummaryRequest},
+    subscription::{subscribe, subscribe_bbo, BboOptions, ExchangeFilter, SubscriptionOptions},
+    AggregatedBooks, ExchangeName,
+};
+
+fn level(price: f64, quantity: f64) -> Level {
+    Level { exchange: "BINANCE".to_string(), price, quantity }
+}
+
+fn exchanges(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+fn book_levels(exchange: ExchangeName, bid: f64, ask: f64) -> OrderBookOnlyLevels {
+    let level = |price| Level { exchange: exchange.to_string(), price, quantity: 1.0 };
+    OrderBookOnlyLevels { exchange, bids: vec![level(bid)], asks: vec![level(ask)], ..Default::default() }
+}
+
+fn summary(bids: &[(f64, f64)], asks: &[(f64, f64)]) -> Summary {
+    Summary {
+        spread: asks[0].0 - bids[0].0,
+        bids: bids.iter().map(|(price, quantity)| level(*price, *quantity)).collect(),
+        asks: asks.iter().map(|(price, quantity)| level(*price, *quantity)).collect(),
+    }
+}
+
+fn options(conflation: Conflation, top_n: usize) -> SubscriptionOptions {
+    SubscriptionOptions { conflation, top_n, ..Default::default() }
+}
+
+#[test]
+fn every_update_forwards_identical_summaries() {
+    let last = summary(&[(100.0, 1.0)], &[(101.0, 1.0)]);
+    assert!(options(Conflation::EveryUpdate, 0).is_relevant_change(&last, &last.clone()));
+}
+
+#[test]
+fn top_of_book_ignores_deeper_levels() {
+    let options = options(Conflation::TopOfBook, 0);
+    let last = summary(&[(100.0, 1.0), (99.0, 1.0)], &[(101.0, 1.0), (102.0, 1.0)]);
+    let deeper = summary(&[(100.0, 1.0), (99.0, 5.0)], &[(101.0, 1.0), (103.0, 1.0)]);
+    assert!(!options.is_relevant_change(&last, &deeper));
+    // A new quantity at the best price is a change of the top of the book.
+    let best_bid = summary(&[(100.0, 2.0), (99.0, 1.0)], &[(101.0, 1.0), (102.0, 1.0)]);
+    assert!(options.is_relevant_change(&last, &best_bid));
+    let best_ask = summary(&[(100.0, 1.0), (99.0, 1.0)], &[(100.5, 1.0), (102.0, 1.0)]);
+    assert!(options.is_relevant_change(&last, &best_ask));
+}
+
+#[test]
+fn top_n_levels_compares_the_first_n_levels() {
+    let last = summary(&[(100.0, 1.0), (99.0, 1.0), (98.0, 1.0)], &[(101.0, 1.0)]);
+    let second = summary(&[(100.0, 1.0), (99.0, 2.0), (98.0, 1.0)], &[(101.0, 1.0)]);
+    let third = summary(&[(100.0, 1.0), (99.0, 1.0), (98.0, 2.0)], &[(101.0, 1.0)]);
+    let options_2 = options(Conflation::TopNLevels, 2);
+    assert!(options_2.is_relevant_change(&last, &second));
+    assert!(!options_2.is_relevant_change(&last, &third));
+    // 0 compares every level.
+    assert!(options(Conflation::TopNLevels, 0).is_relevant_change(&last, &third));
+}
+
+#[test]
+fn unknown_conflation_forwards_every_update() {
+    let request = SummaryRequest { conflation: 42, max_updates_per_second: 4, ..Default::default() };
+    let options = SubscriptionOptions::try_from(&request).unwrap();
+    assert_eq!(options.conflation, Conflation::EveryUpdate);
+    assert_eq!(options.min_interval, Some(Duration::from_millis(250)));
+    let unlimited = SubscriptionOptions::try_from(&SummaryRequest::default()).unwrap();
+    assert_eq!(unlimited.min_interval, None);
+}
+
+#[tokio::test]
+async fn throttled_subscriber_receives_the_latest_summary() {
+    let books = |bid: f64| {
+        AggregatedBooks::new(HashMap::from([(ExchangeName::BINANCE, book_levels(ExchangeName::BINANCE, bid, 200.0))]), HashMap::new())
+    };
+    let (tx_books, rx_books) = watch::channel(books(100.0));
+    let min_interval = Duration::from_millis(200);
+    let options = SubscriptionOptions { min_interval: Some(min_interval), ..Default::default() };
+    let mut stream = subscribe(rx_books, options);
+
+    let first = stream.next().await.unwrap().unwrap();
+    let sent_at = Instant::now();
+    assert_eq!(first.bids[0].price, 100.0);
+    // Published while the subscriber is throttled, so only the last one is forwarded.
+    for bid in [101.0, 102.0, 103.0] {
+        tx_books.send_replace(books(bid));
+    }
+    let second = stream.next().await.unwrap().unwrap();
+    assert!(sent_at.elapsed() >= min_interval - Duration::from_millis(10));
+    assert_eq!(second.bids[0].price, 103.0);
+
+    drop(tx_books);
+    let status = stream.next().await.unwrap().unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+}
+
+#[test]
+fn exchange_filter_excludes_over_includes() {
+    let filter = ExchangeFilter::parse(&exchanges(&["BINANCE", "bitstamp"]), &exchanges(&["BITSTAMP"])).unwrap();
+    assert!(filter.accepts(&ExchangeName::BINANCE));
+    assert!(!filter.accepts(&ExchangeName::BITSTAMP));
+
+    let everything = ExchangeFilter::parse(&[], &[]).unwrap();
+    assert!(everything.accepts(&ExchangeName::BINANCE) && everything.accepts(&ExchangeName::BITSTAMP));
+    let only_excluded = ExchangeFilter::parse(&[], &exchanges(&["BINANCE"])).unwrap();
+    assert!(!only_excluded.accepts(&ExchangeName::BINANCE));
+    assert!(only_excluded.accepts(&ExchangeName::BITSTAMP));
+}
+
+#[test]
+fn exchange_filter_rejects_unknown_exchanges() {
+    let error = ExchangeFilter::parse(&exchanges(&["KRAKEN"]), &[]).unwrap_err();
+    assert_eq!(error.to_string(), "unknown exchange: KRAKEN");
+    assert!(ExchangeFilter::parse(&[], &exchanges(&[""])).is_err());
+}
+
+#[test]
+fn summary_for_merges_only_accepted_books() {
+    let binance = book_levels(ExchangeName::BINANCE, 100.0, 102.0);
+    let bitstamp = book_levels(ExchangeName::BITSTAMP, 101.0, 103.0);
+    let books = AggregatedBooks::new(
+        HashMap::from([(ExchangeName::BINANCE, binance.clone()), (ExchangeName::BITSTAMP, bitstamp.clone())]),
+        HashMap::new(),
+    );
+
+    assert_eq!(&books.summary_for(|_| true), books.summary());
+    let binance_only = books.summary_for(|exchange| *exchange == ExchangeName::BINANCE);
+    assert_eq!(binance_only, make_summary(vec![binance]));
+    assert_eq!(binance_only.spread, 2.0);
+    assert_eq!(books.summary_for(|_| false), Summary::default());
+}
+
+#[tokio::test]
+async fn bbo_is_only_sent_once_an_exchange_has_a_book() {
+    let (tx_books, rx_books) = watch::channel(AggregatedBooks::default());
+    let mut stream = subscribe_bbo(rx_books, BboOptions::default());
+    assert!(tokio::time::timeout(Duration::from_millis(100), stream.next()).await.is_err());
+
+    let binance = book_levels(ExchangeName::BINANCE, 100.0, 102.0);
+    tx_books.send_replace(AggregatedBooks::new(HashMap::from([(ExchangeName::BINANCE, binance)]), HashMap::new()));
+    let bbo = stream.next().await.unwrap().unwrap();
+    assert_eq!(bbo.best_bid.unwrap().price, 100.0);
+    assert_eq!(bbo.spread, 2.0);
+
+    drop(tx_books);
+    let status = stream.next().await.unwrap().unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+}
@@ -0,0 +1,22 @@
+use std::time::Duration;
+use orderbook_merger::supervisor::Backoff;
+
+const SECOND: Duration = Duration::from_secs(1);
+
+#[test]
+fn backoff_doubles_up_to_a_minute() {
+    let mut backoff = Backoff::default();
+    let delays: Vec<u64> = (0..8).map(|_| backoff.delay(Duration::ZERO).as_secs()).collect();
+    assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+}
+
+#[test]
+fn backoff_starts_over_after_a_stable_run() {
+    let mut backoff = Backoff::default();
+    for _ in 0..4 {
+        backoff.delay(30 * SECOND);
+    }
+    assert_eq!(backoff.delay(59 * SECOND), 16 * SECOND);
+    assert_eq!(backoff.delay(60 * SECOND), SECOND);
+    assert_eq!(backoff.delay(SECOND), 2 * SECOND);
+}
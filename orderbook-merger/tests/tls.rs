@@ -0,0 +1,290 @@
+use std::{collections::HashMap, net::SocketAddr, path::{Path, PathBuf}, time::Duration};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+use futures::SinkExt;
+use tokio::{net::{TcpListener, TcpStream}, sync::watch};
+use tokio_rustls::{rustls, TlsConnector};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_util::sync::CancellationToken;
+use tokio_stream::{wrappers::{ReceiverStream, TcpListenerStream}, StreamExt};
+use tonic::{transport::{Endpoint, Server}, Request, Response, Status};
+use orderbook_merger::{
+    orderbook_summary::{
+        orderbook_aggregator_client::OrderbookAggregatorClient,
+        orderbook_aggregator_server::{OrderbookAggregator, OrderbookAggregatorServer},
+        BboRequest, BestBidOffer, Candle, CandlesRequest, ExchangeBookRequest, ExchangeOrderBook, Level, Summary,
+        SummaryRequest, Trade, TradesRequest,
+    },
+    auth::AuthInterceptor,
+    gateway,
+    orderbook::orderbook::OrderBookOnlyLevels,
+    pipeline::{PipelineConfig, Pipelines},
+    service::OrderbookSummary,
+    settings::Settings,
+    subscription::{subscribe, SubscriptionOptions},
+    tls::{client_tls_config, http_tls_config, server_tls_config},
+    websocket, AggregatedBooks, ExchangeName, Symbol,
+};
+
+struct TestAggregator {
+    books: watch::Receiver<AggregatedBooks>,
+}
+
+#[tonic::async_trait]
+impl OrderbookAggregator for TestAggregator {
+    type BookSummaryStream = ReceiverStream<Result<Summary, Status>>;
+    async fn book_summary(
+        &self,
+        _request: Request<SummaryRequest>,
+    ) -> Result<Response<Self::BookSummaryStream>, Status> {
+        Ok(Response::new(subscribe(self.books.clone(), SubscriptionOptions::default())))
+    }
+
+    type ExchangeBookStream = ReceiverStream<Result<ExchangeOrderBook, Status>>;
+    async fn exchange_book(
+        &self,
+        _request: Request<ExchangeBookRequest>,
+    ) -> Result<Response<Self::ExchangeBookStream>, Status> {
+        Err(Status::unimplemented("not served by the test aggregator"))
+    }
+
+    type TradesStream = ReceiverStream<Result<Trade, Status>>;
+    async fn trades(&self, _request: Request<TradesRequest>) -> Result<Response<Self::TradesStream>, Status> {
+        Err(Status::unimplemented("not served by the test aggregator"))
+    }
+
+    type BboStream = ReceiverStream<Result<BestBidOffer, Status>>;
+    async fn bbo(&self, _request: Request<BboRequest>) -> Result<Response<Self::BboStream>, Status> {
+        Err(Status::unimplemented("not served by the test aggregator"))
+    }
+
+    type CandlesStream = ReceiverStream<Result<Candle, Status>>;
+    async fn candles(&self, _request: Request<CandlesRequest>) -> Result<Response<Self::CandlesStream>, Status> {
+        Err(Status::unimplemented("not served by the test aggregator"))
+    }
+}
+
+// Self-signed CA with a server certificate for `localhost` and a client certificate, written as
+// PEM files to a fresh temporary directory.
+struct TestCerts {
+    dir: PathBuf,
+}
+
+impl TestCerts {
+    fn generate(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("orderbook-merger-tls-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut ca_params = CertificateParams::new(vec![]);
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params.distinguished_name.push(DnType::CommonName, "orderbook-merger test ca");
+        let ca = Certificate::from_params(ca_params).unwrap();
+        std::fs::write(dir.join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+
+        for (name, subject_alt_names) in [("server", vec!["localhost".to_string()]), ("client", vec![])] {
+            let cert = Certificate::from_params(CertificateParams::new(subject_alt_names)).unwrap();
+            std::fs::write(dir.join(format!("{name}.pem")), cert.serialize_pem_with_signer(&ca).unwrap()).unwrap();
+            std::fs::write(dir.join(format!("{name}.key")), cert.serialize_private_key_pem()).unwrap();
+        }
+        Self { dir }
+    }
+
+    fn path(&self, file: &str) -> PathBuf {
+        self.dir.join(file)
+    }
+}
+
+impl Drop for TestCerts {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+async fn start_server(certs: &TestCerts, require_client_cert: bool) -> SocketAddr {
+    let client_ca = certs.path("ca.pem");
+    let tls = server_tls_config(
+        &certs.path("server.pem"),
+        &certs.path("server.key"),
+        require_client_cert.then_some(client_ca.as_path()),
+    )
+    .unwrap();
+
+    let level = |exchange: ExchangeName, price, quantity| Level { exchange: exchange.to_string(), price, quantity };
+    let books = HashMap::from([
+        (
+            ExchangeName::BINANCE,
+            OrderBookOnlyLevels {
+                exchange: ExchangeName::BINANCE,
+                bids: vec![level(ExchangeName::BINANCE, 100.0, 1.0)],
+                ..Default::default()
+            },
+        ),
+        (
+            ExchangeName::BITSTAMP,
+            OrderBookOnlyLevels {
+                exchange: ExchangeName::BITSTAMP,
+                asks: vec![level(ExchangeName::BITSTAMP, 101.0, 2.0)],
+                ..Default::default()
+            },
+        ),
+    ]);
+    let (tx_books, rx_books) = watch::channel(AggregatedBooks::new(books, HashMap::new()));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        // Keep the sender alive for as long as the server runs.
+        let _tx_books = tx_books;
+        Server::builder()
+            .tls_config(tls)
+            .unwrap()
+            .add_service(OrderbookAggregatorServer::new(TestAggregator { books: rx_books }))
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+            .unwrap();
+    });
+    address
+}
+
+async fn first_summary(address: SocketAddr, ca: &Path, client_identity: Option<(&Path, &Path)>) -> anyhow::Result<Summary> {
+    let tls = client_tls_config(ca, "localhost", client_identity)?;
+    let channel = Endpoint::from_shared(format!("https://{}", address))?
+        .tls_config(tls)?
+        .connect()
+        .await?;
+    let mut stream = OrderbookAggregatorClient::new(channel)
+        .book_summary(SummaryRequest::default())
+        .await?
+        .into_inner();
+    Ok(stream.next().await.expect("stream ended")?)
+}
+
+#[tokio::test]
+async fn tls_client_receives_summaries() {
+    let certs = TestCerts::generate("tls");
+    let address = start_server(&certs, false).await;
+
+    let summary = first_summary(address, &certs.path("ca.pem"), None).await.unwrap();
+    assert_eq!(summary.spread, 1.0);
+    assert_eq!(summary.bids[0].exchange, "BINANCE");
+}
+
+#[tokio::test]
+async fn tls_client_rejects_untrusted_server() {
+    let certs = TestCerts::generate("untrusted");
+    let other_certs = TestCerts::generate("untrusted-other");
+    let address = start_server(&certs, false).await;
+
+    assert!(first_summary(address, &other_certs.path("ca.pem"), None).await.is_err());
+}
+
+#[tokio::test]
+async fn mtls_accepts_client_with_certificate() {
+    let certs = TestCerts::generate("mtls-accept");
+    let address = start_server(&certs, true).await;
+
+    let (cert, key) = (certs.path("client.pem"), certs.path("client.key"));
+    let summary = first_summary(address, &certs.path("ca.pem"), Some((&cert, &key))).await.unwrap();
+    assert_eq!(summary.asks[0].price, 101.0);
+}
+
+#[tokio::test]
+async fn mtls_rejects_client_without_certificate() {
+    let certs = TestCerts::generate("mtls-reject");
+    let address = start_server(&certs, true).await;
+
+    assert!(first_summary(address, &certs.path("ca.pem"), None).await.is_err());
+}
+
+// The `start_gateway` function serves the JSON gateway with the TLS settings of the server. No
+// pipeline is running, so requests are answered with 404.
+async fn start_gateway(certs: &TestCerts, require_client_cert: bool) -> (SocketAddr, CancellationToken) {
+    let settings = Settings {
+        tls_cert: Some(certs.path("server.pem")),
+        tls_key: Some(certs.path("server.key")),
+        tls_client_ca: require_client_cert.then(|| certs.path("ca.pem")),
+        ..Default::default()
+    };
+    let tls = http_tls_config(&settings).unwrap();
+    assert!(tls.is_some());
+    let pipelines = Pipelines::new(PipelineConfig { connectors: HashMap::new(), ..Default::default() });
+    let router = gateway::router(OrderbookSummary::new(Symbol::ETHUSDT, pipelines), AuthInterceptor::default(), vec![]);
+    let address = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let shutdown = CancellationToken::new();
+    tokio::spawn(gateway::serve(address, router, tls, shutdown.clone()));
+    for _ in 0..50 {
+        if TcpStream::connect(address).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    (address, shutdown)
+}
+
+fn https_client(certs: &TestCerts) -> reqwest::Client {
+    let ca = reqwest::Certificate::from_pem(&std::fs::read(certs.path("ca.pem")).unwrap()).unwrap();
+    reqwest::Client::builder().add_root_certificate(ca).build().unwrap()
+}
+
+#[tokio::test]
+async fn gateway_is_served_over_tls() {
+    let certs = TestCerts::generate("gateway");
+    let (address, shutdown) = start_gateway(&certs, false).await;
+
+    let url = format!("https://localhost:{}/summary", address.port());
+    let response = https_client(&certs).get(&url).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    // Tokens can not be sent in cleartext.
+    let plain = reqwest::get(format!("http://localhost:{}/summary", address.port())).await;
+    assert!(plain.is_err());
+    shutdown.cancel();
+}
+
+#[tokio::test]
+async fn gateway_requires_client_certificate_with_client_ca() {
+    let certs = TestCerts::generate("gateway-mtls");
+    let (address, shutdown) = start_gateway(&certs, true).await;
+
+    let url = format!("https://localhost:{}/summary", address.port());
+    assert!(https_client(&certs).get(&url).send().await.is_err());
+    shutdown.cancel();
+}
+
+#[tokio::test]
+async fn websocket_is_served_over_tls() {
+    let certs = TestCerts::generate("websocket");
+    let settings = Settings {
+        tls_cert: Some(certs.path("server.pem")),
+        tls_key: Some(certs.path("server.key")),
+        ..Default::default()
+    };
+    let pipelines = Pipelines::new(PipelineConfig { connectors: HashMap::new(), ..Default::default() });
+    let service = OrderbookSummary::new(Symbol::ETHUSDT, pipelines);
+    let address = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let shutdown = CancellationToken::new();
+    let tls = http_tls_config(&settings).unwrap();
+    tokio::spawn(websocket::serve(address, service, AuthInterceptor::default(), tls, shutdown.clone()));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Tokens can not be sent in cleartext.
+    assert!(tokio_tungstenite::connect_async(format!("ws://localhost:{}", address.port())).await.is_err());
+
+    let mut roots = rustls::RootCertStore::empty();
+    let ca = std::fs::read(certs.path("ca.pem")).unwrap();
+    for cert in rustls_pemfile::certs(&mut ca.as_slice()).unwrap() {
+        roots.add(&rustls::Certificate(cert)).unwrap();
+    }
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let stream = TcpStream::connect(address).await.unwrap();
+    let server_name = rustls::ServerName::try_from("localhost").unwrap();
+    let stream = TlsConnector::from(std::sync::Arc::new(config)).connect(server_name, stream).await.unwrap();
+    let url = format!("wss://localhost:{}", address.port());
+    let (mut client, _) = tokio_tungstenite::client_async(url, stream).await.unwrap();
+    client.send(Message::Text("{}".to_string())).await.unwrap();
+    let reply = client.next().await.unwrap().unwrap();
+    let reply: serde_json::Value = serde_json::from_str(reply.to_text().unwrap()).unwrap();
+    assert_eq!(reply["type"], "error");
+    shutdown.cancel();
+}
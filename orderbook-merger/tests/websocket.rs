@@ -0,0 +1,137 @@
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    tungstenite::{client::IntoClientRequest, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+use tokio_util::sync::CancellationToken;
+use orderbook_merger::{
+    auth::{AuthInterceptor, ClientPermissions},
+    orderbook::orderbook::OrderBookOnlyLevels,
+    orderbook_summary::Level,
+    pipeline::{PipelineConfig, Pipelines},
+    service::OrderbookSummary,
+    websocket, ExchangeName, Symbol,
+};
+
+type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+fn book_levels(exchange: ExchangeName, bid: f64) -> OrderBookOnlyLevels {
+    let level = |price| Level { exchange: exchange.to_string(), price, quantity: 1.0 };
+    OrderBookOnlyLevels {
+        exchange,
+        symbol: Symbol::ETHUSDT,
+        bids: vec![level(bid)],
+        asks: vec![level(bid + 1.0)],
+        ..Default::default()
+    }
+}
+
+async fn publish(pipelines: &Pipelines, bid: f64) {
+    let feed = pipelines.feed(ExchangeName::BINANCE, Symbol::ETHUSDT);
+    feed.send(book_levels(ExchangeName::BINANCE, bid)).await.unwrap();
+}
+
+// The `start_publisher` function serves the websocket publisher for a pipeline fed with one Binance
+// book, with a `secret` token allowed to read Binance only.
+async fn start_publisher(shutdown: CancellationToken) -> (SocketAddr, Pipelines) {
+    let pipelines = Pipelines::new(PipelineConfig { connectors: HashMap::new(), ..Default::default() });
+    publish(&pipelines, 100.0).await;
+    let mut books = pipelines.books(Symbol::ETHUSDT).unwrap();
+    books.wait_for(|books| !books.summary().bids.is_empty()).await.unwrap();
+
+    let permissions = ClientPermissions {
+        symbols: vec![Symbol::ETHUSDT],
+        exchanges: vec![ExchangeName::BINANCE],
+        max_depth: 5,
+        admin: false,
+    };
+    let auth = AuthInterceptor::new(HashMap::from([("secret".to_string(), permissions)]));
+    let service = OrderbookSummary::new(Symbol::ETHUSDT, pipelines.clone());
+    let address = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    tokio::spawn(websocket::serve(address, service, auth, None, shutdown));
+    for _ in 0..50 {
+        if TcpStream::connect(address).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    (address, pipelines)
+}
+
+async fn connect(address: SocketAddr) -> Client {
+    tokio_tungstenite::connect_async(format!("ws://{address}")).await.unwrap().0
+}
+
+async fn next_message(client: &mut Client) -> serde_json::Value {
+    let message = tokio::time::timeout(Duration::from_secs(5), client.next()).await.unwrap().unwrap().unwrap();
+    serde_json::from_str(message.to_text().unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn subscriber_receives_summaries() {
+    let shutdown = CancellationToken::new();
+    let (address, pipelines) = start_publisher(shutdown.clone()).await;
+    let mut client = connect(address).await;
+
+    client.send(Message::Text(r#"{"token": "secret", "depth": 1}"#.to_string())).await.unwrap();
+    let message = next_message(&mut client).await;
+    assert_eq!(message["type"], "summary");
+    assert_eq!(message["data"]["bids"][0]["price"], 100.0);
+
+    publish(&pipelines, 100.5).await;
+    let message = next_message(&mut client).await;
+    assert_eq!(message["data"]["bids"][0]["price"], 100.5);
+
+    shutdown.cancel();
+    pipelines.shutdown().await;
+}
+
+#[tokio::test]
+async fn subscriber_without_a_valid_token_receives_an_error() {
+    let shutdown = CancellationToken::new();
+    let (address, pipelines) = start_publisher(shutdown.clone()).await;
+    let mut client = connect(address).await;
+
+    client.send(Message::Text(r#"{"symbol": "ETHUSDT"}"#.to_string())).await.unwrap();
+    let message = next_message(&mut client).await;
+    assert_eq!(message["type"], "error");
+    assert_eq!(message["message"], "missing bearer token");
+    client.send(Message::Text(r#"{"token": "guess"}"#.to_string())).await.unwrap();
+    assert_eq!(next_message(&mut client).await["message"], "invalid bearer token");
+    client.send(Message::Text(r#"{"token": "secret", "symbol": "BTCUSDT"}"#.to_string())).await.unwrap();
+    assert_eq!(next_message(&mut client).await["message"], "symbol BTCUSDT is not allowed");
+
+    // The token of the handshake is used when the message has none.
+    let mut request = format!("ws://{address}").into_client_request().unwrap();
+    request.headers_mut().insert("authorization", "Bearer secret".parse().unwrap());
+    let (mut client, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+    client.send(Message::Text("{}".to_string())).await.unwrap();
+    assert_eq!(next_message(&mut client).await["type"], "summary");
+
+    shutdown.cancel();
+    pipelines.shutdown().await;
+}
+
+#[tokio::test]
+async fn unsubscribe_stops_the_summaries() {
+    let shutdown = CancellationToken::new();
+    let (address, pipelines) = start_publisher(shutdown.clone()).await;
+    let mut client = connect(address).await;
+
+    client.send(Message::Text(r#"{"token": "secret"}"#.to_string())).await.unwrap();
+    assert_eq!(next_message(&mut client).await["type"], "summary");
+    client.send(Message::Text(r#"{"unsubscribe": true}"#.to_string())).await.unwrap();
+    // Sent after the unsubscribe message is handled, but the connection stays open.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    publish(&pipelines, 100.5).await;
+    assert!(tokio::time::timeout(Duration::from_millis(300), client.next()).await.is_err());
+
+    client.send(Message::Text(r#"{"token": "secret"}"#.to_string())).await.unwrap();
+    let message = next_message(&mut client).await;
+    assert_eq!(message["data"]["bids"][0]["price"], 100.5);
+
+    shutdown.cancel();
+    pipelines.shutdown().await;
+}
@@ -2,7 +2,7 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
-use orderbook_merger::orderbook_summary::{orderbook_aggregator_client::OrderbookAggregatorClient, Empty};
+use orderbook_merger::orderbook_summary::{orderbook_aggregator_client::OrderbookAggregatorClient, SummaryRequest};
 use tokio_stream::StreamExt;
 
 use super::{Key, InputEvent};
@@ -20,6 +20,7 @@ impl Events {
     // Constructs an new instance of `Events` with the default config.
     pub fn new(
         mut client: OrderbookAggregatorClient<tonic::transport::Channel>,
+        request: tonic::Request<SummaryRequest>,
     ) -> Events {
         let (tx, rx) = tokio::sync::mpsc::channel(100);
         let stop_capture = Arc::new(AtomicBool::new(false));
@@ -31,7 +32,6 @@ impl Events {
         tokio::spawn(async move {
             // Receiving order book summaries from the `client` and
             // sending them as `InputEvent::Update` through the `client_tx` channel.
-            let request = tonic::Request::new(Empty {});
             let mut stream = client.book_summary(request).await.unwrap().into_inner();
             while let Some(summary) = stream.next().await {
                 match summary {
@@ -1,7 +1,11 @@
-use std::{sync::Arc, io::stdout, collections::HashMap};
-use config::Config;
+use std::{sync::Arc, io::stdout};
 use anyhow::Result;
-use orderbook_merger::orderbook_summary::orderbook_aggregator_client::OrderbookAggregatorClient;
+use orderbook_merger::{
+    auth::with_bearer_token,
+    orderbook_summary::{orderbook_aggregator_client::OrderbookAggregatorClient, SummaryRequest},
+    settings::Settings,
+    tls::client_endpoint,
+};
 use ratatui::backend::CrosstermBackend;
 use ratatui::Terminal;
 
@@ -16,18 +20,14 @@ pub async fn start_ui() -> Result<()> {
     terminal.clear()?;
     terminal.hide_cursor()?;
 
-    let config = Config::builder()
-        // read the setting.toml
-        .add_source(config::File::with_name("orderbook-merger/src/setting"))
-        .build()
-        .unwrap()
-        .try_deserialize::<HashMap<String, String>>()
-        .unwrap();
+    let settings = Settings::from_args()?;
 
-    let address = format!("https://{}:{}", config["server-ip"], config["server-port"]);
-
-    let client = OrderbookAggregatorClient::connect(address).await?;
-    let mut events = Events::new(client);
+    let client = OrderbookAggregatorClient::connect(client_endpoint(&settings)?).await?;
+    let mut request = tonic::Request::new(SummaryRequest::default());
+    if let Some(token) = &settings.auth_token {
+        request = with_bearer_token(request, token)?;
+    }
+    let mut events = Events::new(client, request);
     let summary = Arc::new(tokio::sync::Mutex::new(Summary {
         spread: 0.0, 
         bids: Vec::new(),
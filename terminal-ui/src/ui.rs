@@ -30,7 +30,7 @@ where
     rect.render_widget(title, chunks[0]);
 
     // Summary
-    let summary_widget = draw_summary(&summary, decimals);
+    let summary_widget = draw_summary(summary, decimals);
     rect.render_widget(summary_widget, chunks[1]);
 }
 
@@ -43,7 +43,7 @@ fn draw_title<'a>() -> Paragraph<'a> {
         )
 }
 
-fn draw_summary(summary: &Summary, decimals: u32) -> Table {
+fn draw_summary(summary: &Summary, decimals: u32) -> Table<'_> {
 
     let mut rows = vec![];
 